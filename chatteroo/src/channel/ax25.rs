@@ -1,23 +1,33 @@
 //! Chatteroo over AX.25
+//!
+//! Each transmission is an AX.25 UI frame. Its information field holds the
+//! encoded command followed by a 4-byte CRC which Chatteroo adds itself,
+//! because AX.25 lets too many corrupt packets through. The field ends there.
+//! The 2-byte HDLC frame check sequence (FCS) which follows the information
+//! field on the air is not part of it, and must be removed before decoding.
+//! Captures which still include it can be decoded with `decode_captured`.
+//!
+//! With the `tracing` feature, each encode and decode emits an event carrying
+//! the command, sender and length of the information field. Decode failures
+//! are logged as warnings.
 
 use std::str::FromStr;
+use std::time::Duration;
 
 use ax25::frame::{Address, Ax25Frame, FrameContent, ProtocolIdentifier, UnnumberedInformation};
 use crc32fast::Hasher;
 use thiserror::Error;
 
+use crate::error::Error;
 use crate::protocol::{
-    global::{
-        BucketContentRequest, BucketContentResponse, ChatterooVersion, Command, ContiguousRange,
-        EpochRequest, EpochResponse, FrameDefinition, FrameRequest, FrameWithMetadata, InsertFrame,
-        PingRequest, PingResponse, QuickEpochResponse, Range, StationDataRequest,
-        StationDataResponse, StationHeard, StationSparse, StationSummary, Status, Transmission,
-    },
+    global::{ChatterooVersion, Transmission},
+    hex,
     network::Network,
     station::Station,
+    wire::{decode_command, encode_command, version_byte, version_from_byte, WireError},
 };
 
-use super::{ChannelError, ChannelRx, ChannelTx};
+use super::{ChannelError, ChannelRx, ChannelTx, Received};
 
 pub struct Ax25Channel {}
 
@@ -30,49 +40,126 @@ pub enum Ax25Error {
     #[error("Not a Chatteroo packet")]
     NotChatteroo,
 
-    #[error("Invalid Chatteroo version {0}")]
-    InvalidChatterooVersion(u8),
+    #[error("Wire format error: {0}")]
+    Wire(#[from] WireError),
 
-    #[error("Chatteroo protocol error {0:?}")]
-    ProtocolError(crate::error::Error),
+    #[error("Packet CRC did not match content")]
+    CrcMismatch,
 
-    #[error("Invalid Command")]
-    InvalidCommand,
+    #[error("Destination callsign {0} is longer than 6 characters")]
+    DestinationTooLong(String),
 
-    #[error("Invalid station")]
-    InvalidStation,
+    #[error("Error decoding command {code}: {source}")]
+    InCommand { code: u8, source: WireError },
 
-    #[error("Truncated message")]
-    Truncated,
+    #[error("Invalid AX.25 {which:?} address: {reason}")]
+    InvalidAddress { which: AddressField, reason: String },
 
-    #[error("Invalid UTF-8")]
-    InvalidUtf8,
+    #[error("Not a valid AX.25 frame")]
+    InvalidFrame,
+}
 
-    #[error("Packet CRC did not match content")]
-    CrcMismatch,
+impl From<Ax25Error> for Error {
+    /// Errors from decoding the command are converted as a `WireError` would
+    /// be. Everything else specific to AX.25 is carried in `Error::Channel`.
+    fn from(e: Ax25Error) -> Self {
+        match e {
+            Ax25Error::Wire(e) | Ax25Error::InCommand { source: e, .. } => e.into(),
+            e => Error::Channel(Box::new(e)),
+        }
+    }
+}
+
+/// How a raw AX.25 frame handed to `decode_captured` ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// The frame ends with the information field, as passed on by a KISS TNC.
+    Bare,
+    /// The frame is followed by its 2-byte HDLC FCS, as written by some capture tools.
+    WithFcs,
+}
+
+/// Which of a frame's addresses an `Ax25Error::InvalidAddress` refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressField {
+    Source,
+    Destination,
 }
 
 impl ChannelTx for Ax25Tx {
     fn send(&self, t: Transmission) -> Result<(), ChannelError> {
-        let _packet = encode_transmission(&t);
+        let _packet = encode_transmission(&t).map_err(|_| ChannelError::Unencodable)?;
 
         // TODO: actually send
         Ok(())
     }
 }
 
+impl IntoIterator for Ax25Rx {
+    type Item = Result<Transmission, ChannelError>;
+    type IntoIter = Received<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Received::new(self)
+    }
+}
+
+// TODO: actually receive. Until then, report it rather than panicking so that
+// a node built on this channel fails cleanly.
 impl ChannelRx for Ax25Rx {
     fn recv(&self) -> Result<Transmission, ChannelError> {
-        unimplemented!();
+        Err(ChannelError::Unsupported)
     }
+
+    fn recv_timeout(&self, _timeout: Duration) -> Result<Option<Transmission>, ChannelError> {
+        Err(ChannelError::Unsupported)
+    }
+}
+
+fn encode_transmission(t: &Transmission) -> Result<Ax25Frame, Ax25Error> {
+    let info = encode_command(&t.command, t.network.id());
+    let frame = frame_with_info(t, info);
+    #[cfg(feature = "tracing")]
+    match &frame {
+        Ok(frame) => tracing::debug!(
+            command = t.command.name(),
+            sender = %t.sender,
+            len = info_len(frame),
+            "encoded transmission"
+        ),
+        Err(e) => tracing::warn!(
+            command = t.command.name(),
+            sender = %t.sender,
+            error = %e,
+            "failed to encode transmission"
+        ),
+    }
+    frame
 }
 
-fn encode_transmission(t: &Transmission) -> Ax25Frame {
-    let version = ssid_version(&t.version);
-    let dest_addr_str = format!("CHT{}-{}", t.network.id(), version);
+/// The information field that `t` is sent with.
+///
+/// This is the encoded command followed by the 4-byte packet CRC. The AX.25
+/// addresses aren't included.
+pub fn encode_transmission_info(t: &Transmission) -> Result<Vec<u8>, Ax25Error> {
+    match encode_transmission(t)?.content {
+        FrameContent::UnnumberedInformation(ui) => Ok(ui.info),
+        _ => unreachable!("Chatteroo only sends UI frames"),
+    }
+}
+
+/// Hex dump of `encode_transmission_info`, for debugging and bug reports.
+///
+/// Bytes are lowercase hex with a space between each.
+pub fn encode_transmission_hex(t: &Transmission) -> Result<String, Ax25Error> {
+    Ok(hex::encode(&encode_transmission_info(t)?, " "))
+}
+
+/// Build an AX.25 frame addressed for `t` which carries an already-encoded command.
+fn frame_with_info(t: &Transmission, info: Vec<u8>) -> Result<Ax25Frame, Ax25Error> {
+    let dest_addr_str = destination_address(&t.version, &t.network)?;
     let src_addr_str = t.sender.to_string();
     let pid = ProtocolIdentifier::None;
-    let info = encode_command(&t.command, t.network.id());
     // Take the src, dest and info so far and add a 4-byte CRC
     // AX.25 frequently lets corrupt packets through and Chatteroo will be really
     // sensitive to any errors since it caches aggressively, so let's spend the bytes.
@@ -90,17 +177,93 @@ fn encode_transmission(t: &Transmission) -> Ax25Frame {
         info,
         poll_or_final: false,
     };
-    Ax25Frame {
-        source: Address::from_str(&src_addr_str).unwrap(),
-        destination: Address::from_str(&dest_addr_str).unwrap(),
+    Ok(Ax25Frame {
+        source: parse_address(AddressField::Source, &src_addr_str)?,
+        destination: parse_address(AddressField::Destination, &dest_addr_str)?,
         route: vec![],
         command_or_response: None,
         content: ax25::frame::FrameContent::UnnumberedInformation(ui),
+    })
+}
+
+/// Parse an AX.25 address, reporting which field it was for if it's invalid.
+///
+/// A Chatteroo `Station` can have a callsign too long to be an AX.25 address.
+fn parse_address(which: AddressField, address: &str) -> Result<Address, Ax25Error> {
+    Address::from_str(address).map_err(|e| Ax25Error::InvalidAddress {
+        which,
+        reason: e.to_string(),
+    })
+}
+
+/// Build the destination address which identifies a Chatteroo network and version.
+///
+/// The callsign part is `CHT` followed by the network name, so it must fit within
+/// the 6-character limit of an AX.25 callsign. The SSID is the protocol version.
+fn destination_address(version: &ChatterooVersion, network: &Network) -> Result<String, Ax25Error> {
+    let callsign = format!("CHT{}", network.id());
+    if callsign.len() > 6 {
+        return Err(Ax25Error::DestinationTooLong(callsign));
     }
+    Ok(format!("{}-{}", callsign, version_byte(version)))
+}
+
+/// Decode a transmission from the bytes of a raw AX.25 frame, such as from a packet capture.
+///
+/// The FCS isn't checked, since Chatteroo's own CRC covers the same data.
+pub fn decode_captured(
+    bytes: &[u8],
+    format: CaptureFormat,
+    net_prefix: &str,
+) -> Result<Transmission, Ax25Error> {
+    let bytes = match format {
+        CaptureFormat::Bare => bytes,
+        CaptureFormat::WithFcs if bytes.len() >= 2 => &bytes[..bytes.len() - 2],
+        CaptureFormat::WithFcs => return Err(Ax25Error::InvalidFrame),
+    };
+    let frame = Ax25Frame::from_bytes(bytes).map_err(|_| Ax25Error::InvalidFrame)?;
+    decode_transmission(&frame, net_prefix)
 }
 
-#[allow(dead_code)]
 fn decode_transmission(frame: &Ax25Frame, net_prefix: &str) -> Result<Transmission, Ax25Error> {
+    let result = decode_transmission_inner(frame, net_prefix);
+    #[cfg(feature = "tracing")]
+    match &result {
+        Ok(t) => tracing::debug!(
+            command = t.command.name(),
+            sender = %t.sender,
+            len = info_len(frame),
+            "decoded transmission"
+        ),
+        Err(Ax25Error::NotChatteroo) => {}
+        Err(Ax25Error::CrcMismatch) => tracing::warn!(
+            source = %frame.source,
+            len = info_len(frame),
+            "CRC mismatch decoding transmission"
+        ),
+        Err(e) => tracing::warn!(
+            source = %frame.source,
+            len = info_len(frame),
+            error = %e,
+            "failed to decode transmission"
+        ),
+    }
+    result
+}
+
+/// Length of the information field of `frame`, or 0 if it isn't a UI frame.
+#[cfg(feature = "tracing")]
+fn info_len(frame: &Ax25Frame) -> usize {
+    match &frame.content {
+        FrameContent::UnnumberedInformation(ui) => ui.info.len(),
+        _ => 0,
+    }
+}
+
+fn decode_transmission_inner(
+    frame: &Ax25Frame,
+    net_prefix: &str,
+) -> Result<Transmission, Ax25Error> {
     if !frame.destination.callsign.starts_with("CHT") {
         return Err(Ax25Error::NotChatteroo);
     }
@@ -108,19 +271,16 @@ fn decode_transmission(frame: &Ax25Frame, net_prefix: &str) -> Result<Transmissi
         FrameContent::UnnumberedInformation(ui) => ui.info.as_slice(),
         _ => return Err(Ax25Error::NotChatteroo),
     };
-    let version = match frame.destination.ssid {
-        0 => ChatterooVersion::Test,
-        1 => ChatterooVersion::V1,
-        n => return Err(Ax25Error::InvalidChatterooVersion(n)),
-    };
+    let version = version_from_byte(frame.destination.ssid)?;
     let network = frame.destination.callsign[3..].to_owned();
-    let network = Network::new(network).unwrap();
-    let sender = match Station::new(frame.source.callsign.to_owned(), frame.source.ssid) {
-        Ok(s) => s,
-        Err(e) => return Err(Ax25Error::ProtocolError(e)),
-    };
+    let network = Network::new(network).map_err(|e| Ax25Error::InvalidAddress {
+        which: AddressField::Destination,
+        reason: e.to_string(),
+    })?;
+    let sender = Station::new(frame.source.callsign.to_owned(), frame.source.ssid)
+        .map_err(WireError::from)?;
     if info.len() < 4 {
-        return Err(Ax25Error::Truncated);
+        return Err(WireError::Truncated.into());
     }
     let (info, crc) = info.split_at(info.len() - 4);
     let packet_hash = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
@@ -134,248 +294,13 @@ fn decode_transmission(frame: &Ax25Frame, net_prefix: &str) -> Result<Transmissi
         return Err(Ax25Error::CrcMismatch);
     }
     if info.is_empty() {
-        return Err(Ax25Error::InvalidCommand);
+        return Err(WireError::EmptyCommand.into());
     }
-    let command: Command = match info[0] & 0b00011111 {
-        0 => {
-            // Status
-            let epoch_now_mod8 = info[0] >> 5;
-            let remaining = &info[1..];
-            let (epoch_4_ago_crc, remaining) = take_crc(remaining)?;
-            let (epoch_3_ago_crc, remaining) = take_crc(remaining)?;
-            let (epoch_2_ago_crc, remaining) = take_crc(remaining)?;
-            let (epoch_1_ago_crc, remaining) = take_crc(remaining)?;
-            let (epoch_now_crc, remaining) = take_crc(remaining)?;
-            let (epoch_next_crc, mut remaining) = take_crc(remaining)?;
-            let mut recently_added = vec![];
-            for _ in 0..4 {
-                if remaining.is_empty() {
-                    break;
-                }
-                let (station, r) = Station::try_parse(remaining, net_prefix)
-                    .map_err(|_| Ax25Error::InvalidStation)?;
-                let (top, bottom, r) = take_contiguous_range(r)?;
-                remaining = r;
-                recently_added.push(StationSparse {
-                    station,
-                    top,
-                    bottom,
-                });
-            }
-            Command::Status(Status {
-                epoch_now_mod8,
-                epoch_4_ago_crc,
-                epoch_3_ago_crc,
-                epoch_2_ago_crc,
-                epoch_1_ago_crc,
-                epoch_now_crc,
-                epoch_next_crc,
-                recently_added,
-            })
-        }
-        1 => {
-            // Range
-            if info.len() < 3 {
-                return Err(Ax25Error::Truncated);
-            }
-            let final_page = info[1] >> 4;
-            let page = info[1] & 0x0f;
-            let stations_len = info[2];
-            let mut remaining = &info[3..];
-            let mut stations = vec![];
-            for _ in 0..stations_len {
-                let (station, r) = Station::try_parse(remaining, net_prefix)
-                    .map_err(|_| Ax25Error::InvalidStation)?;
-                stations.push(StationHeard {
-                    station,
-                    is_mutual: false,
-                });
-                remaining = r;
-            }
-            for (i, station) in stations.iter_mut().enumerate() {
-                let mutual_idx = (i / 8) as usize;
-                if let Some(byte) = remaining.get(mutual_idx) {
-                    station.is_mutual = byte & 1 << (7 - (i % 8)) > 0;
-                } else {
-                    return Err(Ax25Error::Truncated);
-                }
-            }
-            Command::Range(Range {
-                final_page,
-                page,
-                stations,
-            })
-        }
-        2 => {
-            // InsertFrame
-            let frame = decode_frame_with_metadata(&info[1..])?;
-            Command::InsertFrame(InsertFrame { frame })
-        }
-        3 => {
-            // RepeatFrame
-            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            let frame = decode_frame_with_metadata(remaining)?;
-            Command::RepeatFrame(FrameDefinition { station, frame })
-        }
-        4 => {
-            // QuickSyncFrameRequest
-            let request = decode_frame_request(&info[1..], net_prefix)?;
-            Command::QuickSyncFrameRequest(request)
-        }
-        5 => {
-            // QuickSyncFrameResponse
-            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            let frame = decode_frame_with_metadata(remaining)?;
-            Command::QuickSyncFrameResponse(FrameDefinition { station, frame })
-        }
-        6 => {
-            // BackfillFrameRequest
-            let request = decode_frame_request(&info[1..], net_prefix)?;
-            Command::BackfillFrameRequest(request)
-        }
-        7 => {
-            // BackfillFrameResponse
-            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            let frame = decode_frame_with_metadata(remaining)?;
-            Command::BackfillFrameResponse(FrameDefinition { station, frame })
-        }
-        8 => {
-            // EpochRequest
-            let epoch_mod8 = info[0] >> 5;
-            let (target, _) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            Command::EpochRequest(EpochRequest { target, epoch_mod8 })
-        }
-        9 => {
-            // QuickEpochResponse
-            let epoch_mod8 = info[0] >> 5;
-            let mut stations = vec![];
-            let mut remaining = &info[1..];
-            while !remaining.is_empty() {
-                let (station, r) = take_station_summary(remaining, net_prefix)?;
-                stations.push(station);
-                remaining = r;
-            }
-            Command::QuickEpochResponse(QuickEpochResponse {
-                epoch_mod8,
-                stations,
-            })
-        }
-        10 => {
-            // EpochResponse
-            let epoch_mod8 = info[0] >> 5;
-            let mut checksums = [0u32; 16];
-            let mut remaining = &info[1..];
-            for c in checksums.iter_mut() {
-                let (crc, r) = take_crc(remaining)?;
-                *c = crc;
-                remaining = r;
-            }
-            Command::EpochResponse(EpochResponse {
-                epoch_mod8,
-                checksums,
-            })
-        }
-        11 => {
-            // BucketContentRequest
-            let epoch_mod8 = info[0] >> 5;
-            let (target, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            if remaining.len() < 1 {
-                return Err(Ax25Error::Truncated);
-            }
-            let bucket = remaining[0] >> 4;
-            let page = remaining[0] & 0x0f;
-            Command::BucketContentRequest(BucketContentRequest {
-                target,
-                epoch_mod8,
-                bucket,
-                page,
-            })
-        }
-        12 => {
-            // BucketContentResponse
-            let epoch_mod8 = info[0] >> 5;
-            if info.len() < 2 {
-                return Err(Ax25Error::Truncated);
-            }
-            let final_page = info[1] >> 4;
-            let page = info[1] & 0x0f;
-            let mut remaining = &info[2..];
-            let mut stations = vec![];
-            while !remaining.is_empty() {
-                let (ss, r) = take_station_summary(remaining, net_prefix)?;
-                stations.push(ss);
-                remaining = r;
-            }
-            Command::BucketContentResponse(BucketContentResponse {
-                epoch_mod8,
-                final_page,
-                page,
-                stations,
-            })
-        }
-        13 => {
-            // StationDataRequest
-            let (target, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            let (station, remaining) =
-                Station::try_parse(remaining, net_prefix).map_err(|_| Ax25Error::InvalidStation)?;
-            if remaining.len() < 2 {
-                return Err(Ax25Error::Truncated);
-            }
-            let epoch_mod8 = remaining[0] >> 5;
-            let from_index = u16::from_be_bytes([remaining[0], remaining[1]]) & 0x1fff;
-            Command::StationDataRequest(StationDataRequest {
-                target,
-                station,
-                epoch_mod8,
-                from_index,
-            })
-        }
-        14 => {
-            // StationDataResponse
-            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            if remaining.is_empty() {
-                return Err(Ax25Error::Truncated);
-            }
-            let epoch_mod8 = remaining[0] & 0b0000111;
-            let end_of_data = (remaining[0] & 0b10000000) > 0;
-            let mut ranges = vec![];
-            let mut remaining = &remaining[1..];
-            while !remaining.is_empty() {
-                let (top, bottom, r) = take_contiguous_range(remaining)?;
-                ranges.push(ContiguousRange { top, bottom });
-                remaining = r;
-            }
-            Command::StationDataResponse(StationDataResponse {
-                station,
-                epoch_mod8,
-                end_of_data,
-                ranges,
-            })
-        }
-        15 => {
-            // PingRequest
-            let (target, _) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            Command::PingRequest(PingRequest { target })
-        }
-        16 => {
-            // PingResponse
-            let (target, remaining) = Station::try_parse(&info[1..], net_prefix)
-                .map_err(|_| Ax25Error::InvalidStation)?;
-            let diagnostic = std::str::from_utf8(remaining)
-                .map_err(|_| Ax25Error::InvalidUtf8)?
-                .to_string();
-            Command::PingResponse(PingResponse { target, diagnostic })
-        }
-        _ => return Err(Ax25Error::InvalidCommand),
-    };
+    let code = info[0] & 0b00011111;
+    let command = decode_command(info, net_prefix).map_err(|e| match e {
+        WireError::InvalidCommand => e.into(),
+        source => Ax25Error::InCommand { code, source },
+    })?;
 
     Ok(Transmission {
         version,
@@ -385,298 +310,13 @@ fn decode_transmission(frame: &Ax25Frame, net_prefix: &str) -> Result<Transmissi
     })
 }
 
-fn ssid_version(v: &ChatterooVersion) -> u8 {
-    match v {
-        ChatterooVersion::Test => 0,
-        ChatterooVersion::V1 => 1,
-    }
-}
-
-fn take_crc(buf: &[u8]) -> Result<(u32, &[u8]), Ax25Error> {
-    if buf.len() < 4 {
-        return Err(Ax25Error::Truncated);
-    }
-    let crc = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
-    Ok((crc, &buf[4..]))
-}
-
-fn command_byte(c: &Command) -> u8 {
-    match c {
-        Command::Status(_) => 0,
-        Command::Range(_) => 1,
-        Command::InsertFrame(_) => 2,
-        Command::RepeatFrame(_) => 3,
-        Command::QuickSyncFrameRequest(_) => 4,
-        Command::QuickSyncFrameResponse(_) => 5,
-        Command::BackfillFrameRequest(_) => 6,
-        Command::BackfillFrameResponse(_) => 7,
-        Command::EpochRequest(_) => 8,
-        Command::QuickEpochResponse(_) => 9,
-        Command::EpochResponse(_) => 10,
-        Command::BucketContentRequest(_) => 11,
-        Command::BucketContentResponse(_) => 12,
-        Command::StationDataRequest(_) => 13,
-        Command::StationDataResponse(_) => 14,
-        Command::PingRequest(_) => 15,
-        Command::PingResponse(_) => 16,
-    }
-}
-
-fn encode_command(c: &Command, net_prefix: &str) -> Vec<u8> {
-    let mut out = vec![];
-    // Top 3 bits of command byte may be used for an epoch mod8 to save space
-    let mut cmd_byte = command_byte(&c);
-    match c {
-        Command::Status(status) => {
-            cmd_byte |= status.epoch_now_mod8 << 5;
-            out.push(cmd_byte);
-            out.extend(status.epoch_4_ago_crc.to_be_bytes().into_iter());
-            out.extend(status.epoch_3_ago_crc.to_be_bytes().into_iter());
-            out.extend(status.epoch_2_ago_crc.to_be_bytes().into_iter());
-            out.extend(status.epoch_1_ago_crc.to_be_bytes().into_iter());
-            out.extend(status.epoch_now_crc.to_be_bytes().into_iter());
-            out.extend(status.epoch_next_crc.to_be_bytes().into_iter());
-            for ss in &status.recently_added {
-                out.extend(ss.station.encoded(net_prefix));
-                encode_contiguous_range(ss.top, ss.bottom, &mut out);
-            }
-        }
-        Command::Range(range) => {
-            out.push(cmd_byte);
-            let mut page_byte = range.page;
-            page_byte |= range.final_page << 4;
-            out.push(page_byte);
-            out.push(range.stations.len() as u8);
-            let mutual_len = if range.stations.len() % 8 > 0 {
-                range.stations.len() / 8 + 1
-            } else {
-                range.stations.len()
-            };
-            let mut mutual = vec![0; mutual_len];
-            for (i, sh) in range.stations.iter().enumerate() {
-                out.extend(sh.station.encoded(net_prefix));
-                if sh.is_mutual {
-                    mutual[i / 8] |= mutual[i / 8] | (1 << (7 - (i % 8)));
-                }
-            }
-            out.append(&mut mutual);
-        }
-        Command::InsertFrame(insert) => {
-            out.push(cmd_byte);
-            encode_frame_with_metadata(&insert.frame, &mut out);
-        }
-        Command::RepeatFrame(repeat) => {
-            out.push(cmd_byte);
-            out.extend(repeat.station.encoded(net_prefix));
-            encode_frame_with_metadata(&repeat.frame, &mut out);
-        }
-        Command::QuickSyncFrameRequest(request) => {
-            out.push(cmd_byte);
-            encode_frame_request(request, net_prefix, &mut out);
-        }
-        Command::QuickSyncFrameResponse(response) => {
-            out.push(cmd_byte);
-            out.extend(response.station.encoded(net_prefix));
-            encode_frame_with_metadata(&response.frame, &mut out);
-        }
-        Command::BackfillFrameRequest(request) => {
-            out.push(cmd_byte);
-            encode_frame_request(request, net_prefix, &mut out);
-        }
-        Command::BackfillFrameResponse(response) => {
-            out.push(cmd_byte);
-            out.extend(response.station.encoded(net_prefix));
-            encode_frame_with_metadata(&response.frame, &mut out);
-        }
-        Command::EpochRequest(epoch) => {
-            cmd_byte |= epoch.epoch_mod8 << 5;
-            out.push(cmd_byte);
-            out.extend(epoch.target.encoded(net_prefix));
-        }
-        Command::QuickEpochResponse(response) => {
-            cmd_byte |= response.epoch_mod8 << 5;
-            out.push(cmd_byte);
-            for ss in &response.stations {
-                encode_station_summary(ss, net_prefix, &mut out);
-            }
-        }
-        Command::EpochResponse(response) => {
-            cmd_byte |= response.epoch_mod8 << 5;
-            out.push(cmd_byte);
-            for crc in response.checksums {
-                out.extend(crc.to_be_bytes().into_iter());
-            }
-        }
-        Command::BucketContentRequest(request) => {
-            cmd_byte |= request.epoch_mod8 << 5;
-            out.push(cmd_byte);
-            out.extend(request.target.encoded(net_prefix));
-            let mut page = request.page;
-            page |= request.bucket << 4;
-            out.push(page);
-        }
-        Command::BucketContentResponse(response) => {
-            cmd_byte |= response.epoch_mod8 << 5;
-            out.push(cmd_byte);
-            let mut page = response.page;
-            page |= response.final_page << 4;
-            out.push(page);
-            for ss in &response.stations {
-                encode_station_summary(ss, net_prefix, &mut out);
-            }
-        }
-        Command::StationDataRequest(request) => {
-            out.push(cmd_byte);
-            out.extend(request.target.encoded(net_prefix));
-            out.extend(request.station.encoded(net_prefix));
-            let index = request.from_index | (request.epoch_mod8 as u16) << 13;
-            out.extend(index.to_be_bytes().into_iter());
-        }
-        Command::StationDataResponse(response) => {
-            out.push(cmd_byte);
-            out.extend(response.station.encoded(net_prefix));
-            let mut epoch = response.epoch_mod8;
-            if response.end_of_data {
-                epoch |= 1 << 7;
-            }
-            out.push(epoch);
-            for r in &response.ranges {
-                encode_contiguous_range(r.top, r.bottom, &mut out);
-            }
-        }
-        Command::PingRequest(request) => {
-            out.push(cmd_byte);
-            out.extend(request.target.encoded(net_prefix));
-        }
-        Command::PingResponse(response) => {
-            out.push(cmd_byte);
-            out.extend(response.target.encoded(net_prefix));
-            out.extend(response.diagnostic.as_bytes());
-        }
-    }
-    out
-}
-
-fn encode_frame_with_metadata(f: &FrameWithMetadata, out: &mut Vec<u8>) {
-    let mut index = f.index;
-    index |= (f.epoch_mod8 as u16) << 13;
-    out.extend(index.to_be_bytes().into_iter());
-    let mut application = f.application & 0x0f;
-    if f.start_of_message {
-        application |= 1 << 7;
-    }
-    if f.end_of_message {
-        application |= 1 << 6;
-    }
-    out.push(application);
-    out.extend(f.data.iter());
-}
-
-fn decode_frame_with_metadata(buf: &[u8]) -> Result<FrameWithMetadata, Ax25Error> {
-    if buf.len() < 3 {
-        return Err(Ax25Error::Truncated);
-    }
-    let epoch_mod8 = buf[0] >> 5;
-    let index = u16::from_be_bytes([buf[0], buf[1]]) & 0x1fff;
-    let application = buf[2] & 0x0f;
-    let start_of_message = buf[2] & (1 << 7) > 0;
-    let end_of_message = buf[2] & (1 << 6) > 0;
-    let data = buf[3..].to_vec();
-    Ok(FrameWithMetadata {
-        epoch_mod8,
-        index,
-        start_of_message,
-        end_of_message,
-        application,
-        data,
-    })
-}
-
-fn encode_frame_request(fr: &FrameRequest, net_prefix: &str, out: &mut Vec<u8>) {
-    out.extend(fr.target.encoded(net_prefix));
-    out.extend(fr.inserter.encoded(net_prefix));
-    let mut index = fr.index;
-    index |= (fr.epoch_mod8 as u16) << 13;
-    out.extend(index.to_be_bytes().into_iter());
-}
-
-fn decode_frame_request(buf: &[u8], net_prefix: &str) -> Result<FrameRequest, Ax25Error> {
-    let (target, remaining) =
-        Station::try_parse(&buf, net_prefix).map_err(|_| Ax25Error::InvalidStation)?;
-    let (inserter, remaining) =
-        Station::try_parse(&remaining, net_prefix).map_err(|_| Ax25Error::InvalidStation)?;
-    if remaining.len() < 2 {
-        return Err(Ax25Error::Truncated);
-    }
-    let epoch_mod8 = remaining[0] >> 5;
-    let index = u16::from_be_bytes([remaining[0], remaining[1]]) & 0x1fff;
-    Ok(FrameRequest {
-        target,
-        inserter,
-        epoch_mod8,
-        index,
-    })
-}
-
-fn encode_contiguous_range(top: u16, bottom: u16, out: &mut Vec<u8>) {
-    let mut top = top;
-    if bottom == 0 {
-        top |= 1 << 15;
-        out.extend(top.to_be_bytes().into_iter());
-    } else {
-        out.extend(top.to_be_bytes().into_iter());
-        out.extend(bottom.to_be_bytes().into_iter());
-    }
-}
-
-fn take_contiguous_range(buf: &[u8]) -> Result<(u16, u16, &[u8]), Ax25Error> {
-    if buf.is_empty() {
-        return Err(Ax25Error::Truncated);
-    }
-    if buf[0] & 0b10000000 > 0 {
-        if buf.len() < 2 {
-            return Err(Ax25Error::Truncated);
-        }
-        let top = u16::from_be_bytes([buf[0] & 0b01111111, buf[1]]);
-        Ok((top, 0, &buf[2..]))
-    } else {
-        if buf.len() < 4 {
-            return Err(Ax25Error::Truncated);
-        }
-        let top = u16::from_be_bytes([buf[0], buf[1]]);
-        let bottom = u16::from_be_bytes([buf[2], buf[3]]);
-        Ok((top, bottom, &buf[4..]))
-    }
-}
-
-fn encode_station_summary(ss: &StationSummary, net_prefix: &str, out: &mut Vec<u8>) {
-    out.extend(ss.station.encoded(net_prefix));
-    encode_contiguous_range(ss.top, ss.bottom, out);
-    out.extend(ss.epoch_crc.to_be_bytes().into_iter());
-}
-
-fn take_station_summary<'a, 'b>(
-    buf: &'a [u8],
-    net_prefix: &'b str,
-) -> Result<(StationSummary, &'a [u8]), Ax25Error> {
-    let (station, remaining) =
-        Station::try_parse(buf, net_prefix).map_err(|_| Ax25Error::InvalidStation)?;
-    let (top, bottom, remaining) = take_contiguous_range(remaining)?;
-    let (epoch_crc, remaining) = take_crc(remaining)?;
-    Ok((
-        StationSummary {
-            station,
-            top,
-            bottom,
-            epoch_crc,
-        },
-        remaining,
-    ))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::global::{
+        BucketContentResponse, Command, PingRequest, StationSparse, StationSummary, Status,
+    };
+    use crate::protocol::test_vectors::test_vectors;
 
     #[test]
     fn basic_roundtrip() {
@@ -701,8 +341,287 @@ mod tests {
                 }],
             }),
         };
-        let encoded = encode_transmission(&t);
+        let encoded = encode_transmission(&t).unwrap();
         let decoded = decode_transmission(&encoded, net_prefix).unwrap();
         assert_eq!(t, decoded);
     }
+
+    #[test]
+    fn transmission_hex() {
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::Status(Status {
+                epoch_now_mod8: 1,
+                epoch_4_ago_crc: 0xaaaaaaaa,
+                epoch_3_ago_crc: 0xbbbbbbbb,
+                epoch_2_ago_crc: 0xcccccccc,
+                epoch_1_ago_crc: 0xdddddddd,
+                epoch_now_crc: 0xeeeeeeee,
+                epoch_next_crc: 0xffffffff,
+                recently_added: vec![],
+            }),
+        };
+        // Command byte, six epoch CRCs, then the packet CRC
+        let expected = concat!(
+            "20 ",
+            "aa aa aa aa bb bb bb bb cc cc cc cc dd dd dd dd ee ee ee ee ff ff ff ff ",
+            "0e a1 f4 d7"
+        );
+        assert_eq!(encode_transmission_hex(&t).unwrap(), expected);
+    }
+
+    #[test]
+    fn captured_fcs_stripped() {
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        let bare = encode_transmission(&t).unwrap().to_bytes();
+        assert_eq!(
+            decode_captured(&bare, CaptureFormat::Bare, "VK7").unwrap(),
+            t
+        );
+
+        let mut with_fcs = bare.clone();
+        with_fcs.extend([0x5a, 0xc3]);
+        assert_eq!(
+            decode_captured(&with_fcs, CaptureFormat::WithFcs, "VK7").unwrap(),
+            t
+        );
+        // Left in place, the FCS looks like part of the information field
+        assert!(matches!(
+            decode_captured(&with_fcs, CaptureFormat::Bare, "VK7"),
+            Err(Ax25Error::CrcMismatch)
+        ));
+    }
+
+    #[test]
+    fn destination_callsign() {
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let dest = destination_address(&ChatterooVersion::V1, &network).unwrap();
+        assert_eq!(dest, "CHTVK7-1");
+
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network,
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        let encoded = encode_transmission(&t).unwrap();
+        assert_eq!(encoded.destination.callsign, "CHTVK7");
+        assert_eq!(encoded.destination.ssid, 0);
+    }
+
+    #[test]
+    fn error_identifies_command() {
+        let summary = |callsign: &str| StationSummary {
+            station: Station::new(callsign.to_owned(), 1).unwrap(),
+            top: 10,
+            bottom: 0,
+            epoch_crc: 0x12345678,
+        };
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::BucketContentResponse(BucketContentResponse {
+                epoch_mod8: 2,
+                final_page: 0,
+                page: 0,
+                stations: vec![summary("W1AW"), summary("W2AW"), summary("W3AW")],
+            }),
+        };
+        let mut info = encode_command(&t.command, "VK7");
+        // Cut the third station's CRC short
+        info.truncate(info.len() - 2);
+        let frame = frame_with_info(&t, info).unwrap();
+        match decode_transmission(&frame, "VK7") {
+            Err(Ax25Error::InCommand { code, source }) => {
+                assert_eq!(code, 12);
+                assert!(matches!(source, WireError::Truncated));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn receiving_unsupported() {
+        let rx = Ax25Rx {};
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(ChannelError::Unsupported)
+        ));
+        let received: Vec<_> = rx.into_iter().collect();
+        assert!(matches!(received[..], [Err(ChannelError::Unsupported)]));
+    }
+
+    #[test]
+    fn invalid_source_address() {
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7ABCDE".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        match encode_transmission(&t) {
+            Err(Ax25Error::InvalidAddress { which, .. }) => {
+                assert_eq!(which, AddressField::Source)
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_destination_network() {
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        for callsign in ["CHTab", "CHTVK7A"] {
+            let mut frame = encode_transmission(&t).unwrap();
+            frame.destination.callsign = callsign.to_owned();
+            match decode_transmission(&frame, "VK7") {
+                Err(Ax25Error::InvalidAddress { which, .. }) => {
+                    assert_eq!(which, AddressField::Destination, "{}", callsign)
+                }
+                r => panic!("unexpected result {:?} for {}", r, callsign),
+            }
+        }
+    }
+
+    #[test]
+    fn crc_only_frame_has_empty_command() {
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        let frame = frame_with_info(&t, vec![]).unwrap();
+        match &frame.content {
+            FrameContent::UnnumberedInformation(ui) => assert_eq!(ui.info.len(), 4),
+            _ => panic!("expected a UI frame"),
+        }
+        assert!(matches!(
+            decode_transmission(&frame, "VK7"),
+            Err(Ax25Error::Wire(WireError::EmptyCommand))
+        ));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn decode_failure_traced() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{span, Event, Level, Metadata, Subscriber};
+
+        /// Records the level of every event.
+        #[derive(Clone, Default)]
+        struct Levels(Arc<Mutex<Vec<Level>>>);
+
+        impl Subscriber for Levels {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+            fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+            fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+            fn event(&self, event: &Event<'_>) {
+                self.0.lock().unwrap().push(*event.metadata().level());
+            }
+            fn enter(&self, _span: &span::Id) {}
+            fn exit(&self, _span: &span::Id) {}
+        }
+
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        let levels = Levels::default();
+        tracing::subscriber::with_default(levels.clone(), || {
+            let frame = frame_with_info(&t, vec![]).unwrap();
+            assert!(decode_transmission(&frame, "VK7").is_err());
+        });
+        assert_eq!(*levels.0.lock().unwrap(), vec![Level::WARN]);
+    }
+
+    #[test]
+    fn errors_convert_to_crate_error() {
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 4).unwrap(),
+            command: Command::PingRequest(PingRequest {
+                target: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            }),
+        };
+        let mut bytes = encode_transmission(&t).unwrap().to_bytes();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        let e: Error = decode_captured(&bytes, CaptureFormat::Bare, "VK7")
+            .unwrap_err()
+            .into();
+        assert!(matches!(
+            e,
+            Error::Channel(e) if matches!(e.downcast_ref(), Some(Ax25Error::CrcMismatch))
+        ));
+
+        // Cut a status short in its epoch CRCs, with a CRC which covers what's left of it
+        let status = Command::Status(Status {
+            epoch_now_mod8: 1,
+            epoch_4_ago_crc: 0xaaaaaaaa,
+            epoch_3_ago_crc: 0xbbbbbbbb,
+            epoch_2_ago_crc: 0xcccccccc,
+            epoch_1_ago_crc: 0xdddddddd,
+            epoch_now_crc: 0xeeeeeeee,
+            epoch_next_crc: 0xffffffff,
+            recently_added: vec![],
+        });
+        let mut info = encode_command(&status, "VK7");
+        info.truncate(10);
+        let bytes = frame_with_info(&t, info).unwrap().to_bytes();
+        let e: Error = decode_captured(&bytes, CaptureFormat::Bare, "VK7")
+            .unwrap_err()
+            .into();
+        assert!(matches!(e, Error::Wire(e) if matches!(*e, WireError::Truncated)));
+    }
+
+    #[test]
+    fn vectors_match_addresses() {
+        for v in test_vectors() {
+            let frame = encode_transmission(&v.transmission).unwrap();
+            assert_eq!(frame.source.to_string(), v.source, "{}", v.label);
+            assert_eq!(frame.destination.to_string(), v.destination, "{}", v.label);
+        }
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn arbitrary_transmissions_round_trip(t: Transmission) {
+            let frame = encode_transmission(&t).unwrap();
+            let decoded = decode_transmission(&frame, t.network.id()).unwrap();
+            proptest::prop_assert_eq!(decoded, t);
+        }
+    }
 }
@@ -0,0 +1,93 @@
+//! In-process channel which connects two stations directly, for testing and simulation.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::protocol::global::Transmission;
+
+use super::{ChannelError, ChannelRx, ChannelTx, Received};
+
+/// A perfect point-to-point link between two stations.
+pub struct MemoryChannel {}
+
+impl MemoryChannel {
+    /// Create a linked pair of endpoints. Whatever is sent on one is received by the other.
+    pub fn pair() -> ((MemoryTx, MemoryRx), (MemoryTx, MemoryRx)) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        (
+            (MemoryTx { tx: a_tx }, MemoryRx { rx: a_rx }),
+            (MemoryTx { tx: b_tx }, MemoryRx { rx: b_rx }),
+        )
+    }
+}
+
+pub struct MemoryTx {
+    tx: Sender<Transmission>,
+}
+
+pub struct MemoryRx {
+    rx: Receiver<Transmission>,
+}
+
+impl ChannelTx for MemoryTx {
+    fn send(&self, t: Transmission) -> Result<(), ChannelError> {
+        self.tx.send(t).map_err(|_| ChannelError::Offline)
+    }
+}
+
+impl ChannelRx for MemoryRx {
+    fn recv(&self) -> Result<Transmission, ChannelError> {
+        self.rx.recv().map_err(|_| ChannelError::Offline)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Transmission>, ChannelError> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(t) => Ok(Some(t)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(ChannelError::Offline),
+        }
+    }
+}
+
+impl IntoIterator for MemoryRx {
+    type Item = Result<Transmission, ChannelError>;
+    type IntoIter = Received<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Received::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        global::{ChatterooVersion, Command, PingRequest},
+        network::Network,
+        station::Station,
+    };
+
+    #[test]
+    fn iterate_until_offline() {
+        let ((a_tx, _a_rx), (_b_tx, b_rx)) = MemoryChannel::pair();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let sent: Vec<Transmission> = (0..3)
+            .map(|ssid| Transmission {
+                version: ChatterooVersion::Test,
+                network: Network::new("VK7".to_owned()).unwrap(),
+                sender: station.clone(),
+                command: Command::PingRequest(PingRequest {
+                    target: Station::new("VK7NTK".to_owned(), ssid).unwrap(),
+                }),
+            })
+            .collect();
+        for t in &sent {
+            a_tx.send(t.clone()).unwrap();
+        }
+        drop(a_tx);
+
+        let received: Vec<Transmission> = b_rx.into_iter().map(|t| t.unwrap()).collect();
+        assert_eq!(received, sent);
+    }
+}
@@ -1,10 +1,13 @@
 //! Sending and receiving Chatteroo messages on different radio types.
 
+use std::time::Duration;
+
 use thiserror::Error;
 
 use crate::protocol::global::Transmission;
 
 pub mod ax25;
+pub mod memory;
 
 pub trait ChannelTx {
     fn send(&self, t: Transmission) -> Result<(), ChannelError>;
@@ -12,10 +15,53 @@ pub trait ChannelTx {
 
 pub trait ChannelRx {
     fn recv(&self) -> Result<Transmission, ChannelError>;
+
+    /// Wait up to `timeout` for a transmission, returning `None` if nothing arrives.
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Transmission>, ChannelError>;
+}
+
+/// Iterator over the transmissions received by a `ChannelRx`.
+///
+/// Each item is the result of a `recv`, and the iterator ends once the channel
+/// goes `Offline`. A channel which can't receive yields `Unsupported` once and
+/// then ends.
+pub struct Received<R> {
+    rx: R,
+    done: bool,
+}
+
+impl<R: ChannelRx> Received<R> {
+    pub fn new(rx: R) -> Self {
+        Self { rx, done: false }
+    }
+}
+
+impl<R: ChannelRx> Iterator for Received<R> {
+    type Item = Result<Transmission, ChannelError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.rx.recv() {
+            Err(ChannelError::Offline) => None,
+            Err(ChannelError::Unsupported) => {
+                self.done = true;
+                Some(Err(ChannelError::Unsupported))
+            }
+            r => Some(r),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum ChannelError {
     #[error("Channel closed")]
     Offline,
+
+    #[error("Transmission cannot be encoded for this channel")]
+    Unencodable,
+
+    #[error("Channel does not support this operation")]
+    Unsupported,
 }
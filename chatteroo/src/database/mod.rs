@@ -1,8 +1,270 @@
 //! Persistence and querying of Chatteroo local data.
 
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::error::Error;
+use crate::protocol::{
+    application,
+    epoch::Epoch,
+    global::{
+        ContiguousRange, FrameWithMetadata, InsertFrame, StationSummary, MAX_FRAME_INDEX,
+        MAX_PAYLOAD_LEN,
+    },
+    station::Station,
+};
+
+use model::{Frame, NetworkDigest, SyncState};
+
 pub mod model;
 pub mod query;
+pub mod sqlite;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 
 pub trait Database {
+    /// Whether we already hold the frame inserted by `inserter` at `index` within `epoch`.
+    fn has_frame(&self, inserter: &Station, epoch: &Epoch, index: u16) -> Result<bool, DbError>;
+
+    /// Store a frame that was inserted by `inserter` during `epoch`.
+    ///
+    /// Returns `true` if the frame was new, or `false` if it was already stored.
+    fn store_frame(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        frame: &FrameWithMetadata,
+    ) -> Result<bool, DbError>;
+
+    /// Store several frames that were inserted by `inserter` during `epoch`.
+    ///
+    /// Either all of the frames are stored or, if this fails, none of them.
+    /// Returns how many were new.
+    fn store_frames(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        frames: &[FrameWithMetadata],
+    ) -> Result<usize, DbError> {
+        let mut new = 0;
+        for frame in frames {
+            if self.store_frame(inserter, epoch, frame)? {
+                new += 1;
+            }
+        }
+        Ok(new)
+    }
+
+    /// Store a frame inserted by `inserter` which was heard on the channel at `now`.
+    ///
+    /// The frame only carries its epoch modulo 8, which is resolved relative to
+    /// `now`. Frames from earlier epochs in the sync window, or from the next
+    /// epoch if the sender's clock is ahead, are stored in the epoch they
+    /// belong to. Returns that epoch and whether the frame was new.
+    fn store_received_frame(
+        &self,
+        inserter: &Station,
+        frame: &FrameWithMetadata,
+        now: OffsetDateTime,
+    ) -> Result<(Epoch, bool), DbError> {
+        let epoch = Epoch::from_mod8_at(frame.epoch_mod8, now)?;
+        Ok((epoch, self.store_frame(inserter, &epoch, frame)?))
+    }
+
+    /// The frame inserted by `inserter` at `index` within `epoch`, if we hold it.
+    fn get_frame(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        index: u16,
+    ) -> Result<Option<Frame>, DbError> {
+        let inserter = inserter.to_string();
+        Ok(self
+            .frames_in_epoch(epoch)?
+            .into_iter()
+            .find(|f| f.inserter == inserter && f.index == index as i32))
+    }
+
+    /// All frames stored for `epoch`, in no particular order.
+    fn frames_in_epoch(&self, epoch: &Epoch) -> Result<Vec<Frame>, DbError>;
+
+    /// CRC over every frame we hold in `epoch`, as defined in `query`.
+    fn epoch_crc(&self, epoch: &Epoch) -> Result<u32, DbError> {
+        Ok(query::crc_of_epoch(&self.frames_in_epoch(epoch)?))
+    }
+
+    /// Summary of each station's frames in `epoch`, ordered by station identifier.
+    fn station_summaries_for_epoch(&self, epoch: &Epoch) -> Result<Vec<StationSummary>, DbError> {
+        Ok(query::station_summaries(&self.frames_in_epoch(epoch)?))
+    }
+
+    /// Summary of the frames in `epoch` for each station allocated to `bucket`.
+    fn stations_in_bucket(
+        &self,
+        epoch: &Epoch,
+        bucket: u8,
+    ) -> Result<Vec<StationSummary>, DbError> {
+        let mut stations = self.station_summaries_for_epoch(epoch)?;
+        stations.retain(|s| s.station.bucket() == bucket);
+        Ok(stations)
+    }
+
+    /// The highest frame index we hold in `epoch` for each station which inserted any.
+    ///
+    /// This is cheaper than `station_summaries_for_epoch` when only the top is needed.
+    fn max_indices(&self, epoch: &Epoch) -> Result<HashMap<Station, u16>, DbError> {
+        let mut max = HashMap::new();
+        for frame in self.frames_in_epoch(epoch)? {
+            let Ok(station) = frame.inserter.parse::<Station>() else {
+                continue;
+            };
+            let top = max.entry(station).or_insert(0);
+            *top = (*top).max(frame.index as u16);
+        }
+        Ok(max)
+    }
+
+    /// Ranges of consecutive frame indices we hold that were inserted by `station` in `epoch`.
+    ///
+    /// Ranges are in ascending order of index.
+    fn contiguous_ranges_for_station(
+        &self,
+        epoch: &Epoch,
+        station: &Station,
+    ) -> Result<Vec<ContiguousRange>, DbError> {
+        Ok(query::contiguous_ranges(
+            &self.frames_in_epoch(epoch)?,
+            station,
+        ))
+    }
+
+    /// Index following the highest we hold that was inserted by `station` in `epoch`.
+    ///
+    /// Frames arriving late may fill gaps below this, but never change it
+    /// unless they are higher. Returns 0 if we hold no frames from `station`.
+    fn next_index(&self, epoch: &Epoch, station: &Station) -> Result<u16, DbError> {
+        Ok(self
+            .contiguous_ranges_for_station(epoch, station)?
+            .last()
+            .map(|r| r.top + 1)
+            .unwrap_or(0))
+    }
+
+    /// Fraction of the frames inserted by `station` in `epoch` which we hold, for showing progress.
+    ///
+    /// `known_top` is the highest index we have heard the station reached, for
+    /// example from a peer's `StationSummary`. If we hold a higher index than
+    /// that, it is used instead, so the result is never more than 1.
+    fn station_coverage(
+        &self,
+        epoch: &Epoch,
+        station: &Station,
+        known_top: u16,
+    ) -> Result<f32, DbError> {
+        let ranges = self.contiguous_ranges_for_station(epoch, station)?;
+        let held: u32 = ranges.iter().map(|r| (r.top - r.bottom) as u32 + 1).sum();
+        let top = ranges.last().map_or(known_top, |r| r.top.max(known_top));
+        Ok(held as f32 / (top as u32 + 1) as f32)
+    }
+
+    /// Split `data` for `application` into new frames inserted by `me` in the current epoch `now`.
+    ///
+    /// This is how `SyncEngine::insert` adds our own messages, so everything
+    /// we insert has been checked to fit the wire format.
+    ///
+    /// The frames take consecutive indices from `next_index` and are marked as
+    /// described in `application::fragment`. They are stored before being
+    /// returned ready to transmit. Fails with `EpochFull` if they would need
+    /// indices beyond the last in the epoch, in which case nothing is stored.
+    fn prepare_insert(
+        &self,
+        me: &Station,
+        now: &Epoch,
+        application: u8,
+        data: &[u8],
+    ) -> Result<Vec<InsertFrame>, DbError> {
+        let start = self.next_index(now, me)?;
+        let needed = data.len().div_ceil(MAX_PAYLOAD_LEN);
+        if start as usize + needed > MAX_FRAME_INDEX as usize + 1 {
+            return Err(DbError::EpochFull);
+        }
+        let frames = application::fragment(data, application, now.index_mod8(), start);
+        self.store_frames(me, now, &frames)?;
+        Ok(frames
+            .into_iter()
+            .map(|frame| InsertFrame { frame })
+            .collect())
+    }
+
+    /// Replace any previously saved sync engine state with `state`.
+    ///
+    /// Databases which can't persist this state may ignore it.
+    fn save_sync_state(&self, _state: &SyncState) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    /// The sync engine state most recently saved with `save_sync_state`, if any.
+    fn load_sync_state(&self) -> Result<Option<SyncState>, DbError> {
+        Ok(None)
+    }
+
+    /// Digest of the frames we hold from four weeks before `now` until the week after.
+    ///
+    /// This covers the same epochs as our `Status`.
+    fn network_digest(&self, now: &Epoch) -> Result<NetworkDigest, DbError> {
+        let mut epoch_crcs = [query::EMPTY_EPOCH_CRC; 6];
+        let mut stations = HashSet::new();
+        let mut frames = 0;
+        for (i, offset) in (-4..=1).enumerate() {
+            let abs = now.index_abs() as i64 + offset;
+            if abs < 0 {
+                continue;
+            }
+            let epoch = Epoch::from_abs(abs as u32);
+            epoch_crcs[i] = self.epoch_crc(&epoch)?;
+            for frame in self.frames_in_epoch(&epoch)? {
+                stations.insert(frame.inserter);
+                frames += 1;
+            }
+        }
+        Ok(NetworkDigest {
+            epoch: now.index_abs(),
+            epoch_crcs,
+            stations: stations.len() as u32,
+            frames,
+        })
+    }
+
+    /// Checksum of the frames in `epoch` from stations allocated to each bucket.
+    fn bucket_checksums(&self, epoch: &Epoch) -> Result<[u32; 16], DbError> {
+        Ok(query::bucket_checksums(&self.frames_in_epoch(epoch)?))
+    }
+
+    /// Number of stations with frames in `epoch` which are allocated to each bucket.
+    ///
+    /// For diagnosing whether `Station::bucket` spreads a network's stations evenly.
+    fn bucket_distribution(&self, epoch: &Epoch) -> Result<[usize; 16], DbError> {
+        let mut counts = [0; 16];
+        for station in self.max_indices(epoch)?.keys() {
+            counts[station.bucket() as usize] += 1;
+        }
+        Ok(counts)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DbError {
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] Error),
+
+    #[error("No frame indices are left in the epoch")]
+    EpochFull,
 
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
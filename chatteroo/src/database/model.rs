@@ -2,14 +2,105 @@
 
 use time::OffsetDateTime;
 
+use crate::protocol::{epoch::Epoch, global::FrameWithMetadata};
+
+/// Database row id of a stored frame.
+pub type FrameId = i32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Frame {
-    id: i32,
-    epoch: i32,
-    inserter: String,
-    index: i32,
-    is_start: bool,
-    is_end: bool,
-    application: i32,
-    data: Vec<u8>,
-    inserted: OffsetDateTime,
-}
\ No newline at end of file
+    pub id: FrameId,
+    pub epoch: i32,
+    pub inserter: String,
+    pub index: i32,
+    pub is_start: bool,
+    pub is_end: bool,
+    pub application: i32,
+    pub data: Vec<u8>,
+    pub inserted: OffsetDateTime,
+}
+
+/// State of the sync engine which should survive a restart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncState {
+    /// Absolute index of the epoch that was current when the state was saved.
+    pub epoch: Option<u32>,
+
+    /// Requests which were awaiting a response.
+    pub requests: Vec<SavedRequest>,
+
+    /// Peers which had told us they could hear us.
+    pub mutual: Vec<String>,
+
+    /// Responses we were expecting from peers.
+    pub expected: Vec<SavedExpectation>,
+}
+
+/// A request awaiting a response, as saved in `SyncState`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SavedRequest {
+    /// The request command in its compact encoding.
+    pub command: Vec<u8>,
+
+    /// When the command was most recently sent.
+    pub sent: OffsetDateTime,
+
+    /// How many times the command has been sent again after timing out.
+    pub retries: u32,
+}
+
+/// A response we were expecting from a peer, as saved in `SyncState`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SavedExpectation {
+    /// The peer we asked.
+    pub peer: String,
+
+    /// The expected response in its compact encoding.
+    pub response: Vec<u8>,
+}
+
+/// Compact description of everything a station holds in the sync window.
+///
+/// Two stations with equal digests hold the same frames, so they have nothing
+/// to sync with each other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkDigest {
+    /// Absolute index of the current epoch which the window is relative to.
+    pub epoch: u32,
+
+    /// Epoch CRCs as in our `Status`, from four weeks ago to next week.
+    pub epoch_crcs: [u32; 6],
+
+    /// Number of distinct stations with frames in the window.
+    pub stations: u32,
+
+    /// Number of frames in the window.
+    pub frames: u32,
+}
+
+/// Outcome of `Db::merge_from`, counting the frames read from the other database.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Frames which we didn't hold and were stored.
+    pub new: usize,
+
+    /// Frames which we already held with identical content.
+    pub duplicate: usize,
+
+    /// Frames for which we hold a different frame at the same position, which we kept.
+    pub conflicting: usize,
+}
+
+impl Frame {
+    /// This frame in the form it is sent over the air.
+    pub fn to_frame_with_metadata(&self) -> FrameWithMetadata {
+        FrameWithMetadata {
+            epoch_mod8: Epoch::from_abs(self.epoch as u32).index_mod8(),
+            index: self.index as u16,
+            start_of_message: self.is_start,
+            end_of_message: self.is_end,
+            application: self.application as u8,
+            data: self.data.clone(),
+        }
+    }
+}
@@ -1,7 +1,245 @@
 //! High-level queries around the database.
+//!
+//! Checksums are all CRC32 and are defined in terms of the frames a station
+//! holds, so that two stations holding the same frames will always agree:
+//!
+//! * A frame contributes its index (2 bytes big-endian), a flags byte laid out
+//!   as in `FrameWithMetadata` (start, end, then application in the low 4 bits)
+//!   and finally its data.
+//! * A station's epoch CRC covers its frames in ascending order of index.
+//! * A bucket or whole-epoch CRC covers each station in order of its string
+//!   identifier, with each contributing its identifier followed by its frames.
 
-use super::Database;
+use std::collections::BTreeMap;
 
-pub fn crc_of_epoch(db: &dyn Database) -> u32 {
-    unimplemented!()
+use crc32fast::Hasher;
+
+use crate::protocol::{
+    epoch::Epoch,
+    global::{ContiguousRange, StationSparse, StationSummary, Status},
+    station::Station,
+};
+
+use super::{model::Frame, Database, DbError};
+
+/// CRC of an epoch, bucket or station for which we hold no frames.
+pub const EMPTY_EPOCH_CRC: u32 = 0;
+
+/// CRC over all of `frames`, which should be every frame held for one epoch.
+pub fn crc_of_epoch(frames: &[Frame]) -> u32 {
+    let mut hasher = Hasher::new();
+    for (station, frames) in by_station(frames) {
+        station.hash(&mut hasher);
+        hash_frames(&frames, &mut hasher);
+    }
+    hasher.finalize()
+}
+
+/// Build our `Status` for the epoch `now`, announcing `recently_added` frames.
+///
+/// Checksums are computed afresh from the database for the current epoch,
+/// the four before it and the one after it.
+pub fn build_status(
+    db: &dyn Database,
+    now: &Epoch,
+    recently_added: Vec<StationSparse>,
+) -> Result<Status, DbError> {
+    let crc_at = |offset: i64| -> Result<u32, DbError> {
+        let abs = now.index_abs() as i64 + offset;
+        if abs < 0 {
+            return Ok(EMPTY_EPOCH_CRC);
+        }
+        db.epoch_crc(&Epoch::from_abs(abs as u32))
+    };
+    Ok(Status {
+        epoch_now_mod8: now.index_mod8(),
+        epoch_4_ago_crc: crc_at(-4)?,
+        epoch_3_ago_crc: crc_at(-3)?,
+        epoch_2_ago_crc: crc_at(-2)?,
+        epoch_1_ago_crc: crc_at(-1)?,
+        epoch_now_crc: crc_at(0)?,
+        epoch_next_crc: crc_at(1)?,
+        recently_added,
+    })
+}
+
+/// Summarise each station's frames in `epoch`, ordered by station identifier.
+pub fn station_summaries(frames: &[Frame]) -> Vec<StationSummary> {
+    by_station(frames)
+        .into_iter()
+        .map(|(station, frames)| {
+            let (top, bottom) = highest_contiguous(&frames);
+            let mut hasher = Hasher::new();
+            hash_frames(&frames, &mut hasher);
+            StationSummary {
+                station,
+                top,
+                bottom,
+                epoch_crc: hasher.finalize(),
+            }
+        })
+        .collect()
+}
+
+/// Runs of consecutive indices among the frames inserted by `station`, in ascending order.
+pub fn contiguous_ranges(frames: &[Frame], station: &Station) -> Vec<ContiguousRange> {
+    let inserter = station.to_string();
+    let mut indices: Vec<u16> = frames
+        .iter()
+        .filter(|f| f.inserter == inserter)
+        .map(|f| f.index as u16)
+        .collect();
+    indices.sort_unstable();
+    let mut ranges: Vec<ContiguousRange> = vec![];
+    for index in indices {
+        match ranges.last_mut() {
+            Some(r) if r.top + 1 == index => r.top = index,
+            _ => ranges.push(ContiguousRange {
+                top: index,
+                bottom: index,
+            }),
+        }
+    }
+    ranges
+}
+
+/// CRC of the frames from stations allocated to each of the 16 buckets.
+pub fn bucket_checksums(frames: &[Frame]) -> [u32; 16] {
+    let mut hashers: Vec<Hasher> = (0..16).map(|_| Hasher::new()).collect();
+    for (station, frames) in by_station(frames) {
+        let hasher = &mut hashers[station.bucket() as usize];
+        station.hash(hasher);
+        hash_frames(&frames, hasher);
+    }
+    let mut checksums = [EMPTY_EPOCH_CRC; 16];
+    for (c, h) in checksums.iter_mut().zip(hashers) {
+        *c = h.finalize();
+    }
+    checksums
+}
+
+/// Group frames by inserter, ordered by identifier, with each group ordered by index.
+///
+/// Frames whose inserter can't be parsed as a station are skipped.
+fn by_station(frames: &[Frame]) -> Vec<(Station, Vec<&Frame>)> {
+    let mut grouped: BTreeMap<&str, Vec<&Frame>> = BTreeMap::new();
+    for f in frames {
+        grouped.entry(&f.inserter).or_default().push(f);
+    }
+    grouped
+        .into_iter()
+        .filter_map(|(inserter, mut frames)| {
+            let station = inserter.parse().ok()?;
+            frames.sort_by_key(|f| f.index);
+            Some((station, frames))
+        })
+        .collect()
+}
+
+/// Top and bottom index of the highest contiguous run in `frames`, which must be sorted.
+fn highest_contiguous(frames: &[&Frame]) -> (u16, u16) {
+    let top = match frames.last() {
+        Some(f) => f.index,
+        None => return (0, 0),
+    };
+    let mut bottom = top;
+    for f in frames.iter().rev().skip(1) {
+        if f.index + 1 != bottom {
+            break;
+        }
+        bottom = f.index;
+    }
+    (top as u16, bottom as u16)
+}
+
+fn hash_frames(frames: &[&Frame], hasher: &mut Hasher) {
+    for f in frames {
+        let mut flags = (f.application & 0x0f) as u8;
+        if f.is_start {
+            flags |= 1 << 7;
+        }
+        if f.is_end {
+            flags |= 1 << 6;
+        }
+        hasher.update(&(f.index as u16).to_be_bytes());
+        hasher.update(&[flags]);
+        hasher.update(&f.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    fn frame(inserter: &str, index: i32, data: &[u8]) -> Frame {
+        Frame {
+            id: 0,
+            epoch: 100,
+            inserter: inserter.to_owned(),
+            index,
+            is_start: true,
+            is_end: true,
+            application: 1,
+            data: data.to_vec(),
+            inserted: OffsetDateTime::now_utc(),
+        }
+    }
+
+    #[test]
+    fn summaries_use_highest_contiguous_range() {
+        let frames = [
+            frame("VK7XT-5", 5, b"e"),
+            frame("VK7XT-5", 0, b"a"),
+            frame("VK7NTK-1", 3, b"x"),
+            frame("VK7XT-5", 4, b"d"),
+            frame("VK7XT-5", 1, b"b"),
+        ];
+        let summaries = station_summaries(&frames);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].station.to_string(), "VK7NTK-1");
+        assert_eq!((summaries[0].top, summaries[0].bottom), (3, 3));
+        assert_eq!(summaries[1].station.to_string(), "VK7XT-5");
+        assert_eq!((summaries[1].top, summaries[1].bottom), (5, 4));
+    }
+
+    #[test]
+    fn checksums_independent_of_order() {
+        let a = [frame("VK7XT-5", 0, b"a"), frame("VK7NTK-1", 0, b"b")];
+        let b = [frame("VK7NTK-1", 0, b"b"), frame("VK7XT-5", 0, b"a")];
+        assert_eq!(station_summaries(&a), station_summaries(&b));
+        assert_eq!(bucket_checksums(&a), bucket_checksums(&b));
+        assert_ne!(bucket_checksums(&a), [EMPTY_EPOCH_CRC; 16]);
+        assert_eq!(bucket_checksums(&[]), [EMPTY_EPOCH_CRC; 16]);
+    }
+
+    #[test]
+    fn status_includes_next_epoch() {
+        use crate::database::testing::memory_db;
+        use crate::protocol::global::FrameWithMetadata;
+
+        let db = memory_db();
+        let now = Epoch::from_abs(100);
+        let status = build_status(&db, &now, vec![]).unwrap();
+        assert_eq!(status.epoch_next_crc, EMPTY_EPOCH_CRC);
+
+        // A frame from a station whose clock has already ticked over
+        let next = Epoch::from_abs(101);
+        let frame = FrameWithMetadata {
+            epoch_mod8: next.index_mod8(),
+            index: 0,
+            start_of_message: true,
+            end_of_message: true,
+            application: 1,
+            data: b"early".to_vec(),
+        };
+        let station = Station::new("VK7NTK".to_owned(), 1).unwrap();
+        db.store_frame(&station, &next, &frame).unwrap();
+
+        let status = build_status(&db, &now, vec![]).unwrap();
+        assert_eq!(status.epoch_next_crc, db.epoch_crc(&next).unwrap());
+        assert_ne!(status.epoch_next_crc, EMPTY_EPOCH_CRC);
+        assert_eq!(status.epoch_now_crc, EMPTY_EPOCH_CRC);
+        assert_eq!(status.epoch_now_mod8, 100 % 8);
+    }
 }
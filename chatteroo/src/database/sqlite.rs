@@ -0,0 +1,1109 @@
+//! SQLite implementation of the Chatteroo database.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crc32fast::Hasher;
+use rusqlite::{params, Connection, OptionalExtension};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+use crate::protocol::{
+    epoch::Epoch,
+    global::{ChatterooVersion, FrameWithMetadata},
+    hex,
+    network::Network,
+    station::Station,
+};
+
+use super::{
+    model::{Frame, FrameId, MergeReport, SavedExpectation, SavedRequest, SyncState},
+    query, Database, DbError,
+};
+
+/// Definition of the frame table.
+///
+/// Frames are partitioned by `scope` so that one database can hold the data of
+/// several networks, or of the same network on different protocol versions.
+const FRAME_TABLE: &str = "CREATE TABLE IF NOT EXISTS frame (
+    id INTEGER PRIMARY KEY,
+    scope TEXT NOT NULL DEFAULT '',
+    epoch INTEGER NOT NULL,
+    inserter TEXT NOT NULL,
+    frame_index INTEGER NOT NULL,
+    is_start INTEGER NOT NULL,
+    is_end INTEGER NOT NULL,
+    application INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    crc INTEGER NOT NULL,
+    inserted TEXT NOT NULL,
+    UNIQUE (scope, epoch, inserter, frame_index)
+);";
+
+/// Definition of the tables holding sync engine state, partitioned by `scope` like frames.
+const SYNC_TABLES: &str = "CREATE TABLE IF NOT EXISTS sync_state (
+    scope TEXT PRIMARY KEY,
+    epoch INTEGER
+);
+CREATE TABLE IF NOT EXISTS sync_request (
+    id INTEGER PRIMARY KEY,
+    scope TEXT NOT NULL,
+    command BLOB NOT NULL,
+    sent TEXT NOT NULL,
+    retries INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sync_mutual (
+    scope TEXT NOT NULL,
+    station TEXT NOT NULL,
+    PRIMARY KEY (scope, station)
+);
+CREATE TABLE IF NOT EXISTS sync_expectation (
+    id INTEGER PRIMARY KEY,
+    scope TEXT NOT NULL,
+    peer TEXT NOT NULL,
+    response BLOB NOT NULL
+);";
+
+/// Database stored in a local SQLite file.
+///
+/// Frames and sync state are stored and queried within the scope the database
+/// was opened with, which is a network and protocol version, so that frames
+/// from a `Test` and a `V1` network never contribute to each other's
+/// checksums.
+pub struct Db {
+    conn: Connection,
+    scope: String,
+    crc_cache: RefCell<HashMap<u32, u32>>,
+}
+
+impl Db {
+    /// Open (or create) the database at `path` scoped to `network` on protocol `version`.
+    ///
+    /// Any frames stored before the database was scoped are moved into this
+    /// scope, since they must have come from the only network it was used for.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        network: &Network,
+        version: &ChatterooVersion,
+    ) -> Result<Self, DbError> {
+        let mut db = Self::init(Connection::open(path)?)?;
+        db.set_scope(network, version);
+        db.claim_unscoped()?;
+        Ok(db)
+    }
+
+    /// Open a temporary database which exists only in memory, scoped like `open`.
+    pub fn open_in_memory(network: &Network, version: &ChatterooVersion) -> Result<Self, DbError> {
+        let mut db = Self::init(Connection::open_in_memory()?)?;
+        db.set_scope(network, version);
+        Ok(db)
+    }
+
+    /// Store and query data for `network` on protocol `version` from now on.
+    fn set_scope(&mut self, network: &Network, version: &ChatterooVersion) {
+        self.scope = format!("{}/{}", network.id(), version);
+        self.crc_cache.borrow_mut().clear();
+    }
+
+    /// Move frames stored without a scope into the current one.
+    fn claim_unscoped(&self) -> Result<(), DbError> {
+        self.conn.execute(
+            "UPDATE OR IGNORE frame SET scope = ?1 WHERE scope = ''",
+            params![self.scope],
+        )?;
+        self.crc_cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    fn init(conn: Connection) -> Result<Self, DbError> {
+        conn.execute_batch(FRAME_TABLE)?;
+        let scoped: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('frame') WHERE name = 'scope'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !scoped {
+            // Created before frames were scoped. The uniqueness constraint has
+            // to change too, so the table is rebuilt.
+            conn.execute_batch(&format!(
+                "BEGIN;
+                ALTER TABLE frame RENAME TO frame_unscoped;
+                {FRAME_TABLE}
+                INSERT INTO frame
+                    (id, epoch, inserter, frame_index, is_start, is_end, application, data, crc, inserted)
+                    SELECT id, epoch, inserter, frame_index, is_start, is_end, application, data, crc, inserted
+                    FROM frame_unscoped;
+                DROP TABLE frame_unscoped;
+                COMMIT;"
+            ))?;
+        }
+        let unscoped_sync: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('sync_state') WHERE name = 'id'",
+            [],
+            |row| row.get(0),
+        )?;
+        if unscoped_sync {
+            // Saved before sync state was scoped. It only spares the engine
+            // some repeated requests after a restart, so it is dropped rather
+            // than guessing which network it belonged to.
+            conn.execute_batch(
+                "DROP TABLE sync_state;
+                DROP TABLE IF EXISTS sync_request;
+                DROP TABLE IF EXISTS sync_mutual;
+                DROP TABLE IF EXISTS sync_expectation;",
+            )?;
+        }
+        conn.execute_batch(SYNC_TABLES)?;
+        Ok(Self {
+            conn,
+            scope: String::new(),
+            crc_cache: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// All epochs for which at least one frame is stored, in ascending order.
+    pub fn present_epochs(&self) -> Result<Vec<Epoch>, DbError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT epoch FROM frame WHERE scope = ?1 ORDER BY epoch ASC")?;
+        let epochs = stmt
+            .query_map(params![self.scope], |row| row.get::<_, u32>(0))?
+            .map(|abs| abs.map(Epoch::from_abs))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(epochs)
+    }
+
+    /// Stations which have inserted a frame we received within the last `within`.
+    ///
+    /// Each station is paired with the time we stored its most recent frame,
+    /// and the most recently active stations come first.
+    pub fn active_stations(
+        &self,
+        within: Duration,
+    ) -> Result<Vec<(Station, OffsetDateTime)>, DbError> {
+        let cutoff = OffsetDateTime::now_utc() - within;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT inserter, inserted FROM frame WHERE scope = ?1")?;
+        let mut latest: HashMap<String, OffsetDateTime> = HashMap::new();
+        let mut rows = stmt.query(params![self.scope])?;
+        while let Some(row) = rows.next()? {
+            let inserted: OffsetDateTime = row.get(1)?;
+            if inserted < cutoff {
+                continue;
+            }
+            let entry = latest.entry(row.get(0)?).or_insert(inserted);
+            *entry = (*entry).max(inserted);
+        }
+        let mut active: Vec<(Station, OffsetDateTime)> = latest
+            .into_iter()
+            .filter_map(|(inserter, t)| Some((inserter.parse().ok()?, t)))
+            .collect();
+        active.sort_by_key(|(_, t)| std::cmp::Reverse(*t));
+        Ok(active)
+    }
+
+    /// CRC over every frame we hold in `epoch`, remembered until a frame is added to it.
+    ///
+    /// CRC32 depends on the order of its input so there is no cheap way to fold
+    /// a new frame into an existing checksum. Instead this caches the result of
+    /// a full recompute, which is thrown away whenever a frame is stored in
+    /// that epoch. This is also what `Database::epoch_crc` uses for `Db`.
+    pub fn epoch_crc_cached(&self, epoch: &Epoch) -> Result<u32, DbError> {
+        if let Some(crc) = self.crc_cache.borrow().get(&epoch.index_abs()) {
+            return Ok(*crc);
+        }
+        let crc = query::crc_of_epoch(&self.frames_in_epoch(epoch)?);
+        self.crc_cache.borrow_mut().insert(epoch.index_abs(), crc);
+        Ok(crc)
+    }
+
+    /// Check every stored frame's data against the checksum recorded when it was stored.
+    ///
+    /// This covers frames in every scope. Returns the ids of any frames which
+    /// no longer match, which suggests that the underlying storage has been
+    /// corrupted.
+    pub fn verify_integrity(&self) -> Result<Vec<FrameId>, DbError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, data, crc FROM frame ORDER BY id")?;
+        let mut corrupt = vec![];
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let data: Vec<u8> = row.get(1)?;
+            let crc: u32 = row.get(2)?;
+            if data_crc(&data) != crc {
+                corrupt.push(row.get(0)?);
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Store every frame held by `other`, such as a copy of this node which ran offline.
+    ///
+    /// Merging is idempotent. Where we already hold a frame at the same
+    /// position (epoch, inserter and index) our copy is kept, and it is
+    /// counted as a conflict if its content differs from `other`'s. Frames
+    /// whose inserter can't be read are skipped. New frames keep the time
+    /// `other` stored them.
+    pub fn merge_from(&self, other: &Db) -> Result<MergeReport, DbError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut report = MergeReport::default();
+        for epoch in other.present_epochs()? {
+            for frame in other.frames_in_epoch(&epoch)? {
+                let Ok(inserter) = frame.inserter.parse::<Station>() else {
+                    continue;
+                };
+                let incoming = frame.to_frame_with_metadata();
+                match self.get_frame(&inserter, &epoch, incoming.index)? {
+                    Some(ours) if ours.to_frame_with_metadata() == incoming => {
+                        report.duplicate += 1
+                    }
+                    Some(_) => report.conflicting += 1,
+                    None => {
+                        self.insert_frame(&inserter, &epoch, &incoming, frame.inserted)?;
+                        report.new += 1;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+
+    /// Write every frame as CSV, for analysis in other tools.
+    ///
+    /// There is a header row, then one row per frame ordered by epoch, inserter
+    /// and index. The columns are the absolute epoch, inserter, index, start
+    /// and end of message flags (as 0 or 1), application, data as lowercase hex
+    /// and the time we stored the frame in RFC 3339 format.
+    pub fn export_csv<W: Write>(&self, mut writer: W) -> Result<(), DbError> {
+        writeln!(
+            writer,
+            "epoch,inserter,index,is_start,is_end,application,data,inserted"
+        )?;
+        for epoch in self.present_epochs()? {
+            let mut frames = self.frames_in_epoch(&epoch)?;
+            frames.sort_by(|a, b| (&a.inserter, a.index).cmp(&(&b.inserter, b.index)));
+            for frame in frames {
+                let data = hex::encode(&frame.data, "");
+                let inserted = frame.inserted.format(&Rfc3339).map_err(io::Error::other)?;
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{}",
+                    frame.epoch,
+                    frame.inserter,
+                    frame.index,
+                    frame.is_start as u8,
+                    frame.is_end as u8,
+                    frame.application,
+                    data,
+                    inserted
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete frames until at most `max_frames` are stored, returning how many were deleted.
+    ///
+    /// Frames from the oldest epoch go first, and within an epoch those we
+    /// received earliest. Frames inserted by `me` are never deleted, since
+    /// other stations may be relying on us to hold them, so if those alone
+    /// exceed `max_frames` then everything else is deleted and the store
+    /// remains over the limit.
+    pub fn evict_to(&self, max_frames: usize, me: &Station) -> Result<usize, DbError> {
+        let total: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM frame WHERE scope = ?1",
+            params![self.scope],
+            |row| row.get(0),
+        )?;
+        if total <= max_frames {
+            return Ok(0);
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT id, epoch FROM frame WHERE scope = ?1 AND inserter != ?2
+                ORDER BY epoch ASC, inserted ASC, id ASC LIMIT ?3",
+        )?;
+        let victims = stmt
+            .query_map(
+                params![self.scope, me.to_string(), total - max_frames],
+                |row| Ok((row.get::<_, FrameId>(0)?, row.get::<_, u32>(1)?)),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        let tx = self.conn.unchecked_transaction()?;
+        for (id, epoch) in &victims {
+            tx.execute("DELETE FROM frame WHERE id = ?1", params![id])?;
+            self.crc_cache.borrow_mut().remove(epoch);
+        }
+        tx.commit()?;
+        Ok(victims.len())
+    }
+
+    /// Store `frame` unless we already have one at its position, recording
+    /// that it was stored at `inserted`. Returns whether it was new.
+    fn insert_frame(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        frame: &FrameWithMetadata,
+        inserted: OffsetDateTime,
+    ) -> Result<bool, DbError> {
+        let new = self.conn.execute(
+            "INSERT OR IGNORE INTO frame
+                (scope, epoch, inserter, frame_index, is_start, is_end, application, data, crc, inserted)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                self.scope,
+                epoch.index_abs(),
+                inserter.to_string(),
+                frame.index,
+                frame.start_of_message,
+                frame.end_of_message,
+                frame.application,
+                frame.data,
+                data_crc(&frame.data),
+                inserted,
+            ],
+        )?;
+        if new > 0 {
+            self.crc_cache.borrow_mut().remove(&epoch.index_abs());
+        }
+        Ok(new > 0)
+    }
+}
+
+impl Database for Db {
+    fn has_frame(&self, inserter: &Station, epoch: &Epoch, index: u16) -> Result<bool, DbError> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM frame
+                    WHERE scope = ?1 AND epoch = ?2 AND inserter = ?3 AND frame_index = ?4",
+                params![self.scope, epoch.index_abs(), inserter.to_string(), index],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    fn store_frame(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        frame: &FrameWithMetadata,
+    ) -> Result<bool, DbError> {
+        self.insert_frame(inserter, epoch, frame, OffsetDateTime::now_utc())
+    }
+
+    fn store_frames(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        frames: &[FrameWithMetadata],
+    ) -> Result<usize, DbError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut new = 0;
+        for frame in frames {
+            if self.store_frame(inserter, epoch, frame)? {
+                new += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(new)
+    }
+
+    fn max_indices(&self, epoch: &Epoch) -> Result<HashMap<Station, u16>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT inserter, MAX(frame_index) FROM frame
+                WHERE scope = ?1 AND epoch = ?2 GROUP BY inserter",
+        )?;
+        let mut max = HashMap::new();
+        let mut rows = stmt.query(params![self.scope, epoch.index_abs()])?;
+        while let Some(row) = rows.next()? {
+            if let Ok(station) = row.get::<_, String>(0)?.parse() {
+                max.insert(station, row.get(1)?);
+            }
+        }
+        Ok(max)
+    }
+
+    fn get_frame(
+        &self,
+        inserter: &Station,
+        epoch: &Epoch,
+        index: u16,
+    ) -> Result<Option<Frame>, DbError> {
+        let frame = self
+            .conn
+            .query_row(
+                "SELECT id, epoch, inserter, frame_index, is_start, is_end, application, data, inserted
+                    FROM frame
+                    WHERE scope = ?1 AND epoch = ?2 AND inserter = ?3 AND frame_index = ?4",
+                params![self.scope, epoch.index_abs(), inserter.to_string(), index],
+                row_to_frame,
+            )
+            .optional()?;
+        Ok(frame)
+    }
+
+    fn epoch_crc(&self, epoch: &Epoch) -> Result<u32, DbError> {
+        self.epoch_crc_cached(epoch)
+    }
+
+    fn save_sync_state(&self, state: &SyncState) -> Result<(), DbError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO sync_state (scope, epoch) VALUES (?1, ?2)",
+            params![self.scope, state.epoch],
+        )?;
+        tx.execute(
+            "DELETE FROM sync_request WHERE scope = ?1",
+            params![self.scope],
+        )?;
+        for r in &state.requests {
+            tx.execute(
+                "INSERT INTO sync_request (scope, command, sent, retries) VALUES (?1, ?2, ?3, ?4)",
+                params![self.scope, r.command, r.sent, r.retries],
+            )?;
+        }
+        tx.execute(
+            "DELETE FROM sync_mutual WHERE scope = ?1",
+            params![self.scope],
+        )?;
+        for station in &state.mutual {
+            tx.execute(
+                "INSERT OR IGNORE INTO sync_mutual (scope, station) VALUES (?1, ?2)",
+                params![self.scope, station],
+            )?;
+        }
+        tx.execute(
+            "DELETE FROM sync_expectation WHERE scope = ?1",
+            params![self.scope],
+        )?;
+        for e in &state.expected {
+            tx.execute(
+                "INSERT INTO sync_expectation (scope, peer, response) VALUES (?1, ?2, ?3)",
+                params![self.scope, e.peer, e.response],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_sync_state(&self) -> Result<Option<SyncState>, DbError> {
+        let epoch = self
+            .conn
+            .query_row(
+                "SELECT epoch FROM sync_state WHERE scope = ?1",
+                params![self.scope],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let epoch = match epoch {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT command, sent, retries FROM sync_request WHERE scope = ?1 ORDER BY id",
+        )?;
+        let requests = stmt
+            .query_map(params![self.scope], |row| {
+                Ok(SavedRequest {
+                    command: row.get(0)?,
+                    sent: row.get(1)?,
+                    retries: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT station FROM sync_mutual WHERE scope = ?1 ORDER BY station")?;
+        let mutual = stmt
+            .query_map(params![self.scope], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT peer, response FROM sync_expectation WHERE scope = ?1 ORDER BY id")?;
+        let expected = stmt
+            .query_map(params![self.scope], |row| {
+                Ok(SavedExpectation {
+                    peer: row.get(0)?,
+                    response: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(SyncState {
+            epoch,
+            requests,
+            mutual,
+            expected,
+        }))
+    }
+
+    fn frames_in_epoch(&self, epoch: &Epoch) -> Result<Vec<Frame>, DbError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, epoch, inserter, frame_index, is_start, is_end, application, data, inserted
+                FROM frame WHERE scope = ?1 AND epoch = ?2",
+        )?;
+        let frames = stmt
+            .query_map(params![self.scope, epoch.index_abs()], row_to_frame)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(frames)
+    }
+}
+
+/// Checksum stored alongside each frame's data to detect corruption.
+fn data_crc(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn row_to_frame(row: &rusqlite::Row) -> rusqlite::Result<Frame> {
+    Ok(Frame {
+        id: row.get(0)?,
+        epoch: row.get(1)?,
+        inserter: row.get(2)?,
+        index: row.get(3)?,
+        is_start: row.get(4)?,
+        is_end: row.get(5)?,
+        application: row.get(6)?,
+        data: row.get(7)?,
+        inserted: row.get(8)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing::{frame, memory_db};
+    use crate::protocol::global::{MAX_FRAME_INDEX, MAX_PAYLOAD_LEN};
+
+    #[test]
+    fn store_and_find() {
+        let db = memory_db();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let epoch = Epoch::now();
+        let hello = frame(&epoch, 3, b"hello");
+        assert!(!db.has_frame(&station, &epoch, 3).unwrap());
+        assert!(db.store_frame(&station, &epoch, &hello).unwrap());
+        assert!(db.has_frame(&station, &epoch, 3).unwrap());
+        assert!(!db.has_frame(&station, &epoch, 4).unwrap());
+        let stored = db.get_frame(&station, &epoch, 3).unwrap().unwrap();
+        assert_eq!(stored.to_frame_with_metadata(), hello);
+        assert!(db.get_frame(&station, &epoch, 4).unwrap().is_none());
+        // Storing the same frame again is a no-op
+        assert!(!db.store_frame(&station, &epoch, &hello).unwrap());
+    }
+
+    #[test]
+    fn present_epochs() {
+        let db = memory_db();
+        assert!(db.present_epochs().unwrap().is_empty());
+
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        for (abs, index) in [(53, 0), (50, 0), (51, 0), (50, 1), (53, 1)] {
+            let epoch = Epoch::from_abs(abs);
+            db.store_frame(&station, &epoch, &frame(&epoch, index, &[]))
+                .unwrap();
+        }
+        let present: Vec<u32> = db
+            .present_epochs()
+            .unwrap()
+            .iter()
+            .map(Epoch::index_abs)
+            .collect();
+        assert_eq!(present, vec![50, 51, 53]);
+    }
+
+    #[test]
+    fn received_frame_stored_in_its_epoch() {
+        let db = memory_db();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let now = OffsetDateTime::now_utc();
+        let current = Epoch::at(now);
+        let previous = Epoch::from_abs(current.index_abs() - 1);
+        let backfill = frame(&previous, 0, b"backfill");
+        let (epoch, is_new) = db.store_received_frame(&station, &backfill, now).unwrap();
+        assert_eq!(epoch, previous);
+        assert!(is_new);
+        assert_eq!(db.present_epochs().unwrap(), vec![previous]);
+        assert!(db.has_frame(&station, &previous, 0).unwrap());
+        assert!(!db.has_frame(&station, &current, 0).unwrap());
+    }
+
+    #[test]
+    fn max_indices() {
+        let db = memory_db();
+        let epoch = Epoch::from_abs(300);
+        let other = Epoch::from_abs(301);
+        for (e, callsign, index) in [
+            (&epoch, "VK7XT", 0),
+            (&epoch, "VK7XT", 7),
+            (&epoch, "VK7XT", 3),
+            (&epoch, "VK7NTK", 2),
+            (&epoch, "VK7FDAE", 0),
+            (&other, "VK7FDAE", 9),
+        ] {
+            let station = Station::new(callsign.to_owned(), 0).unwrap();
+            db.store_frame(&station, e, &frame(e, index, &[])).unwrap();
+        }
+        let station = |callsign: &str| Station::new(callsign.to_owned(), 0).unwrap();
+        let expected = HashMap::from([
+            (station("VK7XT"), 7),
+            (station("VK7NTK"), 2),
+            (station("VK7FDAE"), 0),
+        ]);
+        assert_eq!(db.max_indices(&epoch).unwrap(), expected);
+
+        assert!(db.max_indices(&Epoch::from_abs(299)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn export_csv() {
+        let db = memory_db();
+        let epoch = Epoch::from_abs(300);
+        let xt = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let ntk = Station::new("VK7NTK".to_owned(), 1).unwrap();
+        for (station, index, data) in [(&xt, 4, vec![0xab, 0x01]), (&ntk, 9, vec![])] {
+            let frame = FrameWithMetadata {
+                end_of_message: index == 9,
+                application: 2,
+                ..frame(&epoch, index, &data)
+            };
+            db.store_frame(station, &epoch, &frame).unwrap();
+        }
+        let mut out = vec![];
+        db.export_csv(&mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[0],
+            [
+                "epoch",
+                "inserter",
+                "index",
+                "is_start",
+                "is_end",
+                "application",
+                "data",
+                "inserted"
+            ]
+        );
+        assert_eq!(rows[1][..7], ["300", "VK7NTK-1", "9", "1", "1", "2", ""]);
+        assert_eq!(rows[2][..7], ["300", "VK7XT-5", "4", "1", "0", "2", "ab01"]);
+        for row in &rows[1..] {
+            let inserted = OffsetDateTime::parse(row[7], &Rfc3339).unwrap();
+            assert!(OffsetDateTime::now_utc() - inserted < Duration::minutes(1));
+        }
+    }
+
+    #[test]
+    fn prepare_insert() {
+        let db = memory_db();
+        let me = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let now = Epoch::from_abs(300);
+        let first = db.prepare_insert(&me, &now, 1, b"hello").unwrap();
+        assert_eq!(first.len(), 1);
+
+        let data = vec![7; MAX_PAYLOAD_LEN * 2 + 1];
+        let inserts = db.prepare_insert(&me, &now, 1, &data).unwrap();
+        let frames: Vec<_> = inserts.into_iter().map(|i| i.frame).collect();
+        assert_eq!(
+            frames.iter().map(|f| f.index).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert!(frames.iter().all(|f| f.epoch_mod8 == now.index_mod8()));
+        assert_eq!(
+            frames
+                .iter()
+                .map(|f| (f.start_of_message, f.end_of_message))
+                .collect::<Vec<_>>(),
+            [(true, false), (false, false), (false, true)]
+        );
+        for frame in &frames {
+            assert!(db.has_frame(&me, &now, frame.index).unwrap());
+        }
+        assert_eq!(db.next_index(&now, &me).unwrap(), 4);
+
+        let mut last = frames[0].clone();
+        last.index = MAX_FRAME_INDEX - 1;
+        db.store_frame(&me, &now, &last).unwrap();
+        assert!(matches!(
+            db.prepare_insert(&me, &now, 1, &[7; MAX_PAYLOAD_LEN + 1]),
+            Err(DbError::EpochFull)
+        ));
+        assert!(!db.has_frame(&me, &now, MAX_FRAME_INDEX).unwrap());
+        let inserts = db.prepare_insert(&me, &now, 1, b"bye").unwrap();
+        assert_eq!(inserts[0].frame.index, MAX_FRAME_INDEX);
+    }
+
+    #[test]
+    fn bucket_distribution() {
+        let db = memory_db();
+        let epoch = Epoch::from_abs(300);
+        let stations: Vec<Station> = (0..40)
+            .map(|i| Station::new(format!("VK7A{}", i), i % 10).unwrap())
+            .collect();
+        for station in &stations {
+            for index in 0..2 {
+                db.store_frame(station, &epoch, &frame(&epoch, index, &[]))
+                    .unwrap();
+            }
+        }
+        let counts = db.bucket_distribution(&epoch).unwrap();
+        assert_eq!(counts.iter().sum::<usize>(), stations.len());
+        for station in &stations {
+            assert!(counts[station.bucket() as usize] > 0);
+        }
+        assert_eq!(
+            db.bucket_distribution(&Epoch::from_abs(301)).unwrap(),
+            [0; 16]
+        );
+    }
+
+    #[test]
+    fn verify_integrity() {
+        let db = memory_db();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let epoch = Epoch::now();
+        for index in 0..3 {
+            db.store_frame(&station, &epoch, &frame(&epoch, index, &[index as u8; 10]))
+                .unwrap();
+        }
+        assert!(db.verify_integrity().unwrap().is_empty());
+
+        let id = db.get_frame(&station, &epoch, 1).unwrap().unwrap().id;
+        db.conn
+            .execute(
+                "UPDATE frame SET data = ?1 WHERE id = ?2",
+                params![vec![0xffu8; 10], id],
+            )
+            .unwrap();
+        assert_eq!(db.verify_integrity().unwrap(), vec![id]);
+    }
+
+    #[test]
+    fn active_stations() {
+        let db = memory_db();
+        let epoch = Epoch::now();
+        let now = OffsetDateTime::now_utc();
+        for (callsign, index, hours_ago) in [
+            ("VK7XT", 0, 30),
+            ("VK7NTK", 0, 5),
+            ("VK7FDAE", 0, 10),
+            ("VK7FDAE", 1, 1),
+        ] {
+            let station = Station::new(callsign.to_owned(), 1).unwrap();
+            db.store_frame(&station, &epoch, &frame(&epoch, index, &[]))
+                .unwrap();
+            db.conn
+                .execute(
+                    "UPDATE frame SET inserted = ?1 WHERE inserter = ?2 AND frame_index = ?3",
+                    params![now - Duration::hours(hours_ago), station.to_string(), index],
+                )
+                .unwrap();
+        }
+
+        let active = db.active_stations(Duration::days(1)).unwrap();
+        let names: Vec<String> = active.iter().map(|(s, _)| s.to_string()).collect();
+        assert_eq!(names, vec!["VK7FDAE-1", "VK7NTK-1"]);
+        assert_eq!(active[0].1, now - Duration::hours(1));
+    }
+
+    #[test]
+    fn cached_epoch_crc() {
+        let db = memory_db();
+        let epoch = Epoch::from_abs(100);
+        let other = Epoch::from_abs(99);
+        let fresh = |e: &Epoch| query::crc_of_epoch(&db.frames_in_epoch(e).unwrap());
+        assert_eq!(db.epoch_crc_cached(&epoch).unwrap(), fresh(&epoch));
+
+        for (callsign, index) in [("VK7XT", 0), ("VK7NTK", 0), ("VK7XT", 1), ("VK7XT", 1)] {
+            let station = Station::new(callsign.to_owned(), 1).unwrap();
+            let before = db.epoch_crc_cached(&epoch).unwrap();
+            let other_before = db.epoch_crc_cached(&other).unwrap();
+            let is_new = db
+                .store_frame(&station, &epoch, &frame(&epoch, index, &[index as u8]))
+                .unwrap();
+            let after = db.epoch_crc_cached(&epoch).unwrap();
+            assert_eq!(after, fresh(&epoch));
+            assert_eq!(after != before, is_new);
+            assert_eq!(db.epoch_crc_cached(&other).unwrap(), other_before);
+        }
+    }
+
+    #[test]
+    fn network_digest() {
+        use crate::database::testing::{populate, Coverage, PopulateSpec};
+
+        let now = Epoch::from_abs(300);
+        let spec = PopulateSpec {
+            epoch: Epoch::from_abs(299),
+            stations: 5,
+            frames_per_station: 10,
+            coverage: Coverage::Gapped,
+        };
+        let a = memory_db();
+        let b = memory_db();
+        populate(&a, &spec).unwrap();
+        populate(&b, &spec).unwrap();
+
+        let digest = a.network_digest(&now).unwrap();
+        assert_eq!(digest, b.network_digest(&now).unwrap());
+        assert_eq!(digest.stations, 5);
+        assert_eq!(digest.frames as usize, spec.frame_count());
+
+        // Fill in one of the gaps
+        b.store_frame(
+            &spec.stations()[0],
+            &spec.epoch,
+            &frame(&spec.epoch, 3, b"extra"),
+        )
+        .unwrap();
+        assert_ne!(digest, b.network_digest(&now).unwrap());
+    }
+
+    #[test]
+    fn merge_from() {
+        let main = memory_db();
+        let offline = memory_db();
+        let epoch = Epoch::from_abs(300);
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        main.store_frame(&station, &epoch, &frame(&epoch, 0, b"both"))
+            .unwrap();
+        main.store_frame(&station, &epoch, &frame(&epoch, 1, b"main"))
+            .unwrap();
+        offline
+            .store_frame(&station, &epoch, &frame(&epoch, 0, b"both"))
+            .unwrap();
+        offline
+            .store_frame(&station, &epoch, &frame(&epoch, 1, b"offline"))
+            .unwrap();
+        offline
+            .store_frame(&station, &epoch, &frame(&epoch, 2, b"new"))
+            .unwrap();
+        let other_epoch = Epoch::from_abs(299);
+        offline
+            .store_frame(&station, &other_epoch, &frame(&other_epoch, 0, b"old"))
+            .unwrap();
+
+        let report = main.merge_from(&offline).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                new: 2,
+                duplicate: 1,
+                conflicting: 1,
+            }
+        );
+        assert_eq!(
+            main.get_frame(&station, &epoch, 1).unwrap().unwrap().data,
+            b"main"
+        );
+        let merged = main.get_frame(&station, &epoch, 2).unwrap().unwrap();
+        assert_eq!(merged.data, b"new");
+        assert_eq!(
+            merged.inserted,
+            offline
+                .get_frame(&station, &epoch, 2)
+                .unwrap()
+                .unwrap()
+                .inserted
+        );
+        assert!(main.has_frame(&station, &other_epoch, 0).unwrap());
+
+        let again = main.merge_from(&offline).unwrap();
+        assert_eq!((again.new, again.duplicate, again.conflicting), (0, 3, 1));
+    }
+
+    #[test]
+    fn evict_to() {
+        let db = memory_db();
+        let me = Station::new("VK7XT".to_owned(), 1).unwrap();
+        let other = Station::new("VK7NTK".to_owned(), 1).unwrap();
+        let now = OffsetDateTime::now_utc();
+        // epoch, inserter, index, hours ago received
+        let frames = [
+            (50, &me, 0, 40),
+            (50, &other, 0, 30),
+            (50, &other, 1, 50),
+            (51, &other, 0, 10),
+            (51, &other, 1, 20),
+            (52, &me, 0, 5),
+            (52, &other, 0, 1),
+        ];
+        for (abs, station, index, hours_ago) in frames {
+            let epoch = Epoch::from_abs(abs);
+            db.store_frame(station, &epoch, &frame(&epoch, index, &[index as u8]))
+                .unwrap();
+            db.conn
+                .execute(
+                    "UPDATE frame SET inserted = ?1 WHERE epoch = ?2 AND inserter = ?3 AND frame_index = ?4",
+                    params![now - Duration::hours(hours_ago), abs, station.to_string(), index],
+                )
+                .unwrap();
+        }
+        let crc_before = db.epoch_crc_cached(&Epoch::from_abs(51)).unwrap();
+
+        assert_eq!(db.evict_to(10, &me).unwrap(), 0);
+        assert_eq!(db.evict_to(4, &me).unwrap(), 3);
+        let held = |abs| {
+            let mut held: Vec<(String, i32)> = db
+                .frames_in_epoch(&Epoch::from_abs(abs))
+                .unwrap()
+                .into_iter()
+                .map(|f| (f.inserter, f.index))
+                .collect();
+            held.sort();
+            held
+        };
+        assert_eq!(held(50), vec![("VK7XT-1".to_owned(), 0)]);
+        assert_eq!(held(51), vec![("VK7NTK-1".to_owned(), 0)]);
+        assert_ne!(
+            db.epoch_crc_cached(&Epoch::from_abs(51)).unwrap(),
+            crc_before
+        );
+
+        // Our own frames survive even when they alone are over the limit
+        assert_eq!(db.evict_to(1, &me).unwrap(), 2);
+        assert_eq!(held(50), vec![("VK7XT-1".to_owned(), 0)]);
+        assert_eq!(held(52), vec![("VK7XT-1".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn scopes_kept_apart() {
+        let mut db = memory_db();
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let epoch = Epoch::from_abs(50);
+
+        db.set_scope(&network, &ChatterooVersion::Test);
+        assert!(db
+            .store_frame(&station, &epoch, &frame(&epoch, 0, b"hello"))
+            .unwrap());
+        let test_crc = db.epoch_crc(&epoch).unwrap();
+
+        db.set_scope(&network, &ChatterooVersion::V1);
+        assert!(!db.has_frame(&station, &epoch, 0).unwrap());
+        assert_eq!(db.epoch_crc(&epoch).unwrap(), query::EMPTY_EPOCH_CRC);
+        // The same frame is new on this network version
+        assert!(db
+            .store_frame(&station, &epoch, &frame(&epoch, 0, b"hello"))
+            .unwrap());
+        assert_eq!(db.epoch_crc(&epoch).unwrap(), test_crc);
+        assert!(db
+            .store_frame(&station, &epoch, &frame(&epoch, 1, b"again"))
+            .unwrap());
+        let v1_crc = db.epoch_crc(&epoch).unwrap();
+        assert_ne!(v1_crc, test_crc);
+        assert_eq!(db.frames_in_epoch(&epoch).unwrap().len(), 2);
+
+        db.set_scope(&network, &ChatterooVersion::Test);
+        assert_eq!(db.epoch_crc(&epoch).unwrap(), test_crc);
+        assert_eq!(db.frames_in_epoch(&epoch).unwrap().len(), 1);
+        assert!(!db.has_frame(&station, &epoch, 1).unwrap());
+        assert_eq!(db.present_epochs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sync_state_scoped() {
+        let mut db = memory_db();
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let state = SyncState {
+            epoch: Some(300),
+            requests: vec![SavedRequest {
+                command: vec![0x0f, 0x35, 0x32, 0xaf],
+                sent: time::macros::datetime!(2024-01-01 0:00 UTC),
+                retries: 1,
+            }],
+            mutual: vec!["VK7NTK-1".to_owned()],
+            expected: vec![SavedExpectation {
+                peer: "VK7NTK-1".to_owned(),
+                response: vec![1, 0, 0, 1, 44],
+            }],
+        };
+        db.save_sync_state(&state).unwrap();
+
+        db.set_scope(&network, &ChatterooVersion::V1);
+        assert_eq!(db.load_sync_state().unwrap(), None);
+        let other = SyncState {
+            epoch: Some(301),
+            requests: vec![],
+            mutual: vec![],
+            expected: vec![],
+        };
+        db.save_sync_state(&other).unwrap();
+        assert_eq!(db.load_sync_state().unwrap(), Some(other));
+
+        db.set_scope(&network, &ChatterooVersion::Test);
+        assert_eq!(db.load_sync_state().unwrap(), Some(state));
+    }
+
+    #[test]
+    fn unscoped_frames_migrated() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE frame (
+                id INTEGER PRIMARY KEY,
+                epoch INTEGER NOT NULL,
+                inserter TEXT NOT NULL,
+                frame_index INTEGER NOT NULL,
+                is_start INTEGER NOT NULL,
+                is_end INTEGER NOT NULL,
+                application INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                crc INTEGER NOT NULL,
+                inserted TEXT NOT NULL,
+                UNIQUE (epoch, inserter, frame_index)
+            );
+            INSERT INTO frame
+                (epoch, inserter, frame_index, is_start, is_end, application, data, crc, inserted)
+                VALUES (50, 'VK7XT-5', 0, 1, 1, 1, x'00', 0, '2024-01-01T00:00:00Z');
+            CREATE TABLE sync_state (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                epoch INTEGER
+            );
+            INSERT INTO sync_state (id, epoch) VALUES (0, 50);",
+        )
+        .unwrap();
+        let mut db = Db::init(conn).unwrap();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let epoch = Epoch::from_abs(50);
+        assert!(db.has_frame(&station, &epoch, 0).unwrap());
+
+        db.set_scope(
+            &Network::new("VK7".to_owned()).unwrap(),
+            &ChatterooVersion::V1,
+        );
+        assert!(!db.has_frame(&station, &epoch, 0).unwrap());
+        db.claim_unscoped().unwrap();
+        assert!(db.has_frame(&station, &epoch, 0).unwrap());
+        // Sync state from before scoping is dropped
+        assert_eq!(db.load_sync_state().unwrap(), None);
+    }
+
+    #[test]
+    fn station_coverage() {
+        let db = memory_db();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let epoch = Epoch::from_abs(50);
+        assert_eq!(db.station_coverage(&epoch, &station, 9).unwrap(), 0.0);
+        for index in [0, 1, 2, 3, 4, 7] {
+            db.store_frame(&station, &epoch, &frame(&epoch, index, &[]))
+                .unwrap();
+        }
+        assert_eq!(db.station_coverage(&epoch, &station, 11).unwrap(), 0.5);
+        // A peer hasn't heard as much as we hold
+        assert_eq!(db.station_coverage(&epoch, &station, 3).unwrap(), 0.75);
+
+        for index in [5, 6] {
+            db.store_frame(&station, &epoch, &frame(&epoch, index, &[]))
+                .unwrap();
+        }
+        assert_eq!(db.station_coverage(&epoch, &station, 7).unwrap(), 1.0);
+    }
+}
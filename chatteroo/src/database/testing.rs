@@ -0,0 +1,127 @@
+//! Fixtures for tests which need a database holding a realistic spread of frames.
+//!
+//! Everything generated here is deterministic, so the CRCs of a populated
+//! database are the same from one run to the next.
+
+use crate::protocol::{
+    epoch::Epoch,
+    global::{ChatterooVersion, FrameWithMetadata},
+    network::Network,
+    station::Station,
+};
+
+use super::{sqlite::Db, Database, DbError};
+
+/// Which of each station's frame indices `populate` stores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coverage {
+    /// Every index, with no gaps.
+    Full,
+    /// Every index except each fourth one, so the frames form several short ranges.
+    Gapped,
+    /// Only each third index, so no two frames are adjacent.
+    Sparse,
+}
+
+/// Description of the frames `populate` should store.
+#[derive(Clone, Debug)]
+pub struct PopulateSpec {
+    /// Epoch which the frames belong to.
+    pub epoch: Epoch,
+
+    /// Number of inserting stations, at most 676.
+    pub stations: usize,
+
+    /// Indices `0..frames_per_station` are candidates for each station.
+    pub frames_per_station: u16,
+
+    /// Which of the candidate indices are stored.
+    pub coverage: Coverage,
+}
+
+impl PopulateSpec {
+    /// The stations which `populate` stores frames for, in order.
+    pub fn stations(&self) -> Vec<Station> {
+        (0..self.stations).map(station).collect()
+    }
+
+    /// The indices which `populate` stores for every station, in ascending order.
+    pub fn indices(&self) -> Vec<u16> {
+        (0..self.frames_per_station)
+            .filter(|i| match self.coverage {
+                Coverage::Full => true,
+                Coverage::Gapped => i % 4 != 3,
+                Coverage::Sparse => i % 3 == 0,
+            })
+            .collect()
+    }
+
+    /// Total number of frames that `populate` stores.
+    pub fn frame_count(&self) -> usize {
+        self.stations * self.indices().len()
+    }
+}
+
+/// Open an empty database in memory, scoped to the `VK7` network on the `Test` version.
+pub fn memory_db() -> Db {
+    let network = Network::new("VK7".to_owned()).unwrap();
+    Db::open_in_memory(&network, &ChatterooVersion::Test).unwrap()
+}
+
+/// A frame at `index` in `epoch` holding all of a message for application 1.
+pub fn frame(epoch: &Epoch, index: u16, data: &[u8]) -> FrameWithMetadata {
+    FrameWithMetadata {
+        epoch_mod8: epoch.index_mod8(),
+        index,
+        start_of_message: true,
+        end_of_message: true,
+        application: 1,
+        data: data.to_vec(),
+    }
+}
+
+/// Store the frames described by `spec` in `db`.
+pub fn populate(db: &Db, spec: &PopulateSpec) -> Result<(), DbError> {
+    let indices = spec.indices();
+    for (n, inserter) in spec.stations().iter().enumerate() {
+        for &index in &indices {
+            let data = format!("{} {}", n, index);
+            db.store_frame(
+                inserter,
+                &spec.epoch,
+                &frame(&spec.epoch, index, data.as_bytes()),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The `n`th generated station: `VK7AA-0`, `VK7AB-0` and so on.
+fn station(n: usize) -> Station {
+    let letters: String = [n / 26, n % 26]
+        .iter()
+        .map(|l| (b'A' + *l as u8) as char)
+        .collect();
+    Station::new(format!("VK7{}", letters), 0).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_count_matches_spec() {
+        for coverage in [Coverage::Full, Coverage::Gapped, Coverage::Sparse] {
+            let db = memory_db();
+            let spec = PopulateSpec {
+                epoch: Epoch::from_abs(300),
+                stations: 30,
+                frames_per_station: 20,
+                coverage,
+            };
+            populate(&db, &spec).unwrap();
+            let frames = db.frames_in_epoch(&spec.epoch).unwrap();
+            assert_eq!(frames.len(), spec.frame_count(), "{:?}", coverage);
+        }
+    }
+}
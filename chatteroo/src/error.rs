@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::protocol::wire::WireError;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Tried to restore a mod-8 epoch value that makes no sense - likely clock skew")]
@@ -16,4 +18,22 @@ pub enum Error {
 
     #[error("SSID is not between 0 and 9")]
     InvalidSsid,
+
+    #[error("Block index is not between 0 and 167")]
+    InvalidBlock,
+
+    #[error("Heard frame {index} in epoch {epoch} claiming to be ours, but we never inserted it")]
+    SpoofedSelfInsertion { epoch: u32, index: u16 },
+
+    #[error("Unknown command kind {0:?}")]
+    UnknownCommandKind(String),
+
+    #[error("Reassembled message does not match its checksum")]
+    MessageChecksumMismatch,
+
+    #[error("Wire format error: {0}")]
+    Wire(Box<WireError>),
+
+    #[error("Channel error: {0}")]
+    Channel(Box<dyn std::error::Error + Send + Sync>),
 }
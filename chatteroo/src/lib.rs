@@ -4,6 +4,10 @@ pub mod channel;
 
 pub mod database;
 
+pub mod node;
+
 pub mod protocol;
 
+pub mod sync;
+
 pub mod error;
@@ -0,0 +1,210 @@
+//! A complete Chatteroo station: channel, database and sync engine wired together.
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+use crate::channel::{ChannelError, ChannelRx, ChannelTx};
+use crate::database::{sqlite::Db, Database, DbError};
+use crate::protocol::{
+    global::{ChatterooVersion, Transmission},
+    network::Network,
+    station::Station,
+};
+use crate::sync::{SyncConfig, SyncEngine};
+
+/// Default time between unprompted `Status` transmissions.
+pub const DEFAULT_BEACON_INTERVAL: Duration = Duration::minutes(10);
+
+/// Longest we wait for a transmission before checking whether there is other work to do.
+const MAX_POLL_WAIT: Duration = Duration::seconds(1);
+
+/// Everything needed to construct a `Node`, apart from its channel.
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    /// Identity and tuning of the sync protocol.
+    pub sync: SyncConfig,
+
+    /// Location of the SQLite database, which is created if it doesn't exist.
+    pub db_path: PathBuf,
+
+    /// How often to transmit our `Status` when nothing else prompts it.
+    pub beacon_interval: Duration,
+}
+
+impl NodeConfig {
+    /// Configuration for `station` on `network`, storing data at `db_path`, with default tuning.
+    pub fn new(
+        station: Station,
+        network: Network,
+        version: ChatterooVersion,
+        db_path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            sync: SyncConfig::new(station, network, version),
+            db_path: db_path.into(),
+            beacon_interval: DEFAULT_BEACON_INTERVAL,
+        }
+    }
+}
+
+/// A working Chatteroo station.
+///
+/// The node receives transmissions from its channel, lets its `SyncEngine`
+/// decide how to respond, and sends whatever results. It also beacons its
+/// `Status` periodically so that neighbours can tell what it holds.
+pub struct Node {
+    tx: Box<dyn ChannelTx>,
+    rx: Box<dyn ChannelRx>,
+    db: Box<dyn Database>,
+    engine: SyncEngine,
+    beacon_interval: Duration,
+    last_beacon: Option<OffsetDateTime>,
+}
+
+impl Node {
+    /// Open the database and prepare to communicate over `tx` and `rx`.
+    pub fn new(
+        config: NodeConfig,
+        tx: Box<dyn ChannelTx>,
+        rx: Box<dyn ChannelRx>,
+    ) -> Result<Self, NodeError> {
+        let db = Db::open(&config.db_path, &config.sync.network, &config.sync.version)?;
+        let mut engine = SyncEngine::new(config.sync);
+        engine.load_state(&db)?;
+        Ok(Self {
+            tx,
+            rx,
+            db: Box::new(db),
+            engine,
+            beacon_interval: config.beacon_interval,
+            last_beacon: None,
+        })
+    }
+
+    /// Run the station until its channel goes offline.
+    ///
+    /// Sync progress is saved to the database on the way out so that a node
+    /// created later with the same database can pick up where this one left off.
+    pub fn run(&mut self) -> Result<(), NodeError> {
+        loop {
+            match self.poll() {
+                Err(NodeError::Channel(ChannelError::Offline)) => break,
+                r => r?,
+            }
+        }
+        self.engine.save_state(self.db.as_ref())?;
+        Ok(())
+    }
+
+    /// Wait briefly for a transmission and do any work which is due.
+    pub fn poll(&mut self) -> Result<(), NodeError> {
+        let wait = self.next_wakeup(OffsetDateTime::now_utc());
+        let received = self.rx.recv_timeout(wait.unsigned_abs())?;
+        let now = OffsetDateTime::now_utc();
+        if let Some(t) = received {
+            let out = self.engine.handle(self.db.as_ref(), &t, now)?;
+            self.send_all(out)?;
+        }
+        let out = self.engine.tick(now);
+        self.send_all(out)?;
+        let beacon_stale = match self.last_beacon {
+            Some(last) => now - last >= self.beacon_interval,
+            None => true,
+        };
+        if self.engine.beacon_due() || beacon_stale {
+            let out = self.engine.beacon(self.db.as_ref(), now)?;
+            self.last_beacon = Some(now);
+            self.send_all(out)?;
+        }
+        Ok(())
+    }
+
+    /// Insert a message of our own for `application` and transmit its frames.
+    ///
+    /// See `SyncEngine::insert`.
+    pub fn insert(&mut self, application: u8, data: Vec<u8>) -> Result<(), NodeError> {
+        let now = OffsetDateTime::now_utc();
+        let out = self
+            .engine
+            .insert(self.db.as_ref(), application, data, now)?;
+        self.send_all(out)
+    }
+
+    /// The database holding everything this station knows.
+    pub fn database(&self) -> &dyn Database {
+        self.db.as_ref()
+    }
+
+    /// The sync engine, for inspecting the state of the protocol.
+    pub fn engine(&self) -> &SyncEngine {
+        &self.engine
+    }
+
+    /// How long we can wait for a transmission before something else needs doing.
+    fn next_wakeup(&self, now: OffsetDateTime) -> Duration {
+        let mut wait = MAX_POLL_WAIT;
+        if let Some(due) = self.engine.next_due() {
+            wait = wait.min(due - now);
+        }
+        if let Some(last) = self.last_beacon {
+            wait = wait.min(last + self.beacon_interval - now);
+        }
+        wait.max(Duration::ZERO)
+    }
+
+    fn send_all(&self, out: Vec<Transmission>) -> Result<(), NodeError> {
+        for t in out {
+            self.tx.send(t)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum NodeError {
+    #[error("Database error: {0}")]
+    Database(#[from] DbError),
+
+    #[error("Channel error: {0}")]
+    Channel(#[from] ChannelError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::memory::MemoryChannel;
+    use crate::protocol::epoch::Epoch;
+
+    fn node(callsign: &str, tx: Box<dyn ChannelTx>, rx: Box<dyn ChannelRx>) -> Node {
+        let mut config = NodeConfig::new(
+            Station::new(callsign.to_owned(), 0).unwrap(),
+            Network::new("VK7".to_owned()).unwrap(),
+            ChatterooVersion::Test,
+            ":memory:",
+        );
+        config.sync.response_gap = Duration::ZERO;
+        config.sync.response_jitter = Duration::ZERO;
+        Node::new(config, tx, rx).unwrap()
+    }
+
+    #[test]
+    fn frame_reaches_other_node() {
+        let ((a_tx, a_rx), (b_tx, b_rx)) = MemoryChannel::pair();
+        let mut a = node("VK7XT", Box::new(a_tx), Box::new(a_rx));
+        let mut b = node("VK7NTK", Box::new(b_tx), Box::new(b_rx));
+
+        a.insert(1, b"hello".to_vec()).unwrap();
+        b.poll().unwrap();
+
+        let inserter = Station::new("VK7XT".to_owned(), 0).unwrap();
+        let frame = b
+            .database()
+            .get_frame(&inserter, &Epoch::now(), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.data, b"hello");
+        assert_eq!(frame.application, 1);
+    }
+}
@@ -0,0 +1,448 @@
+//! Identification of the applications which frames belong to, and routing of their messages.
+//!
+//! Each frame carries an application id from 0 to 15 and the application
+//! defines how the data of a message spanning one or more frames is decoded.
+//! Splitting that data into frames and joining it back up is the same for
+//! every application, and is done by `fragment` and `assemble`.
+//!
+//! Frames are checked individually on the wire but a message assembled from
+//! them isn't. Applications may optionally carry a checksum of a message in
+//! its first frame, added by `checksummed` and verified by `take_checksum`.
+//!
+//! Besides messages, an application may define other kinds of data which
+//! each fit in a single frame, such as a chat `Presence` or a forum
+//! `Moderation`. These are marked by a header of the application's format
+//! version, a flags byte of exactly `FLAG_OTHER`, which no message sets, and
+//! a kind byte saying what the payload which follows is. A dedicated kind
+//! byte means that new kinds never take up bits which messages need for
+//! their flags. Each kind implements `SingleFrameKind`, which writes and
+//! checks the header and builds and recognises its frames.
+
+use crc32fast::Hasher;
+use thiserror::Error;
+
+use crate::error::Error;
+
+use super::{
+    chat::{self, Ack, ChatError, ChatMessage, Presence, Reaction, SetNickname, CHAT_APPLICATION},
+    epoch::Epoch,
+    forum::{self, ForumError, ForumPost, Moderation, FORUM_APPLICATION},
+    global::{FrameWithMetadata, MAX_PAYLOAD_LEN},
+    station::Station,
+};
+
+/// Application which a frame belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ApplicationId {
+    /// Real-time chat, in `protocol::chat`.
+    Chat,
+    /// Forum posts, in `protocol::forum`.
+    Forum,
+    /// An id which is reserved or not understood by this version of Chatteroo.
+    Unknown(u8),
+}
+
+impl ApplicationId {
+    /// Interpret the id carried in `FrameWithMetadata::application`.
+    pub fn from_u8(id: u8) -> Self {
+        match id {
+            CHAT_APPLICATION => Self::Chat,
+            FORUM_APPLICATION => Self::Forum,
+            other => Self::Unknown(other),
+        }
+    }
+
+    /// The id to carry in `FrameWithMetadata::application`.
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Chat => CHAT_APPLICATION,
+            Self::Forum => FORUM_APPLICATION,
+            Self::Unknown(id) => *id,
+        }
+    }
+}
+
+/// Flags byte, following an application's format version, which marks data that isn't a message.
+pub const FLAG_OTHER: u8 = 0b10000000;
+
+/// A kind of data other than a message, carried by itself in a single frame.
+///
+/// Implementors only encode and decode their payload. The header described
+/// in the module documentation is added and checked here.
+pub trait SingleFrameKind: Sized {
+    /// Application whose frames carry this kind of data.
+    const APPLICATION: u8;
+
+    /// Version of the application's encoding, which begins the data.
+    const VERSION: u8;
+
+    /// Kind byte following `FLAG_OTHER`, unique within the application.
+    const KIND: u8;
+
+    type Error: From<KindError>;
+
+    /// Append the payload which follows the header to `out`.
+    fn encode_payload(&self, out: &mut Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Decode the payload which followed the header in a frame inserted in `epoch`.
+    fn parse_payload(payload: &[u8], epoch: &Epoch) -> Result<Self, Self::Error>;
+
+    /// Check that this is suitable to send, including that it fits in one frame.
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Produce the bytes to be carried in frame data, starting with the header.
+    fn encoded(&self) -> Result<Vec<u8>, Self::Error> {
+        let mut out = vec![Self::VERSION, FLAG_OTHER, Self::KIND];
+        self.encode_payload(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decode the data of a frame inserted in `epoch`.
+    ///
+    /// The epoch is context for any `Block` in the payload.
+    fn try_parse(encoded: &[u8], epoch: &Epoch) -> Result<Self, Self::Error> {
+        match encoded {
+            [] => Err(KindError::Truncated.into()),
+            [version, ..] if *version != Self::VERSION => {
+                Err(KindError::UnsupportedVersion(*version).into())
+            }
+            [_] | [_, FLAG_OTHER] => Err(KindError::Truncated.into()),
+            [_, FLAG_OTHER, kind, payload @ ..] if *kind == Self::KIND => {
+                Self::parse_payload(payload, epoch)
+            }
+            [_, FLAG_OTHER, kind, ..] => Err(KindError::UnknownKind(*kind).into()),
+            [_, flags, ..] => Err(KindError::NotOther(*flags).into()),
+        }
+    }
+
+    /// Build the frame carrying this, inserted at `index` within the epoch `epoch_mod8`.
+    fn frame(&self, epoch_mod8: u8, index: u16) -> Result<FrameWithMetadata, Self::Error> {
+        self.validate()?;
+        Ok(FrameWithMetadata {
+            epoch_mod8,
+            index,
+            start_of_message: true,
+            end_of_message: true,
+            application: Self::APPLICATION,
+            data: self.encoded()?,
+        })
+    }
+
+    /// Whether `frame` carries this kind of data rather than part of a message.
+    fn is_carried_by(frame: &FrameWithMetadata) -> bool {
+        frame.application == Self::APPLICATION
+            && frame.data.get(..3) == Some(&[Self::VERSION, FLAG_OTHER, Self::KIND][..])
+    }
+}
+
+/// Receiver of complete messages, each handed to the method for its application.
+///
+/// Only `chat` and `forum` must be implemented. Every other kind of data is
+/// ignored unless its method is overridden.
+pub trait ApplicationDispatcher {
+    /// Handle a chat message.
+    fn chat(&mut self, message: ChatMessage);
+
+    /// Handle a forum post.
+    fn forum(&mut self, post: ForumPost);
+
+    /// Handle an acknowledgement of a direct message.
+    fn ack(&mut self, _ack: Ack) {}
+
+    /// Handle a reaction to a chat message.
+    fn reaction(&mut self, _reaction: Reaction) {}
+
+    /// Handle a station setting its nickname.
+    fn nickname(&mut self, _nickname: SetNickname) {}
+
+    /// Handle a request to hide a forum post, which clients may choose to honour.
+    fn moderation(&mut self, _moderation: Moderation) {}
+
+    /// Handle a station announcing that it is online.
+    fn presence(&mut self, _presence: Presence) {}
+
+    /// Handle the frames of a message for an application we don't understand.
+    fn unknown(&mut self, _application: u8, _frames: &[FrameWithMetadata]) {}
+
+    /// Decode the message carried by `frames` and pass it to the method for its application.
+    ///
+    /// The frames were inserted by `inserter` in `epoch`, which identify chat
+    /// messages and give the context for any blocks they carry. The
+    /// application is taken from the first frame. Does nothing if `frames` is empty.
+    fn dispatch(
+        &mut self,
+        inserter: &Station,
+        epoch: &Epoch,
+        frames: &[FrameWithMetadata],
+    ) -> Result<(), DispatchError> {
+        let Some(first) = frames.first() else {
+            return Ok(());
+        };
+        match ApplicationId::from_u8(first.application) {
+            ApplicationId::Chat if Presence::is_carried_by(first) => {
+                self.presence(Presence::try_parse(&first.data, epoch)?)
+            }
+            ApplicationId::Chat if Ack::is_carried_by(first) => {
+                self.ack(Ack::try_parse(&first.data, epoch)?)
+            }
+            ApplicationId::Chat if SetNickname::is_carried_by(first) => {
+                self.nickname(SetNickname::try_parse(&first.data, epoch)?)
+            }
+            ApplicationId::Chat if Reaction::is_carried_by(first) => {
+                self.reaction(Reaction::try_parse(&first.data, epoch)?)
+            }
+            ApplicationId::Chat => self.chat(chat::reassemble(inserter, epoch, frames)?),
+            ApplicationId::Forum if Moderation::is_carried_by(first) => {
+                self.moderation(Moderation::try_parse(&first.data, epoch)?)
+            }
+            ApplicationId::Forum => self.forum(forum::reassemble(frames)?),
+            ApplicationId::Unknown(id) => self.unknown(id, frames),
+        }
+        Ok(())
+    }
+}
+
+/// Split the `data` of a message for `application` into frames for the epoch `epoch_mod8`.
+///
+/// The frames have consecutive indices beginning at `start_index` and each
+/// carries up to `MAX_PAYLOAD_LEN` bytes of `data`. The first is marked as the
+/// start of the message and the last as the end, so a message which fits in
+/// one frame has both flags set. Empty `data` produces no frames.
+///
+/// Panics if the indices of the frames would go beyond `u16::MAX`.
+pub fn fragment(
+    data: &[u8],
+    application: u8,
+    epoch_mod8: u8,
+    start_index: u16,
+) -> Vec<FrameWithMetadata> {
+    fragment_at(data, application, epoch_mod8, start_index, |_| true)
+}
+
+/// Like `fragment`, but only ending frames at offsets in `data` where `is_boundary` is true.
+///
+/// Each frame is made as long as possible. If there is no boundary within
+/// `MAX_PAYLOAD_LEN` bytes then the frame is cut at that length anyway.
+pub fn fragment_at(
+    data: &[u8],
+    application: u8,
+    epoch_mod8: u8,
+    start_index: u16,
+    is_boundary: impl Fn(usize) -> bool,
+) -> Vec<FrameWithMetadata> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let limit = (start + MAX_PAYLOAD_LEN).min(data.len());
+        let end = (start + 1..=limit)
+            .rev()
+            .find(|&end| end == data.len() || is_boundary(end))
+            .unwrap_or(limit);
+        chunks.push(&data[start..end]);
+        start = end;
+    }
+    let last = chunks.len().saturating_sub(1);
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| FrameWithMetadata {
+            epoch_mod8,
+            index: u16::try_from(i)
+                .ok()
+                .and_then(|i| start_index.checked_add(i))
+                .expect("frame index overflowed u16"),
+            start_of_message: i == 0,
+            end_of_message: i == last,
+            application,
+            data: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// Join up the data of a message for `application` which was split by `fragment`.
+///
+/// `frames` should be in order of index. The message begins at the first frame
+/// marked as the start of a message and must continue through consecutive
+/// indices until a frame marked as the end. Any frames after that are ignored.
+pub fn assemble(frames: &[FrameWithMetadata], application: u8) -> Result<Vec<u8>, AssemblyError> {
+    let start = frames
+        .iter()
+        .position(|f| f.start_of_message)
+        .ok_or(AssemblyError::MissingStart)?;
+    let mut data = vec![];
+    let mut prev: Option<&FrameWithMetadata> = None;
+    for frame in &frames[start..] {
+        if frame.application != application {
+            return Err(AssemblyError::WrongApplication(frame.application));
+        }
+        if let Some(prev) = prev {
+            if frame.start_of_message {
+                return Err(AssemblyError::MissingEnd);
+            }
+            if frame.epoch_mod8 != prev.epoch_mod8 || frame.index != prev.index + 1 {
+                return Err(AssemblyError::Gap { after: prev.index });
+            }
+        }
+        data.extend_from_slice(&frame.data);
+        if frame.end_of_message {
+            return Ok(data);
+        }
+        prev = Some(frame);
+    }
+    Err(AssemblyError::MissingEnd)
+}
+
+/// Prefix `data` with a 4-byte CRC32 of its contents.
+pub(crate) fn checksummed(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    let mut out = hasher.finalize().to_be_bytes().to_vec();
+    out.extend_from_slice(data);
+    out
+}
+
+/// Remove the checksum added by `checksummed` from the start of `data`, verifying the rest.
+///
+/// Fails with `MessageChecksumMismatch` if the frames of the message were
+/// assembled incorrectly, or if `data` is too short to hold the checksum.
+pub(crate) fn take_checksum(data: &[u8]) -> Result<&[u8], Error> {
+    if data.len() < 4 {
+        return Err(Error::MessageChecksumMismatch);
+    }
+    let (crc, rest) = data.split_at(4);
+    let mut hasher = Hasher::new();
+    hasher.update(rest);
+    if hasher.finalize().to_be_bytes() != crc {
+        return Err(Error::MessageChecksumMismatch);
+    }
+    Ok(rest)
+}
+
+#[derive(Error, Debug)]
+pub enum AssemblyError {
+    #[error("Frame belongs to application {0}")]
+    WrongApplication(u8),
+
+    #[error("No frame marks the start of a message")]
+    MissingStart,
+
+    #[error("Message is missing the frame after index {after}")]
+    Gap { after: u16 },
+
+    #[error("Message ended without a frame marking its end")]
+    MissingEnd,
+}
+
+#[derive(Error, Debug)]
+pub enum KindError {
+    #[error("Data ended unexpectedly")]
+    Truncated,
+
+    #[error("Data is in format version {0}, which isn't understood")]
+    UnsupportedVersion(u8),
+
+    #[error("Data has flags {0:#04x}, so is not of another kind")]
+    NotOther(u8),
+
+    #[error("Data is of kind {0}, which isn't understood")]
+    UnknownKind(u8),
+}
+
+#[derive(Error, Debug)]
+pub enum DispatchError {
+    #[error("Chat error: {0}")]
+    Chat(#[from] ChatError),
+
+    #[error("Forum error: {0}")]
+    Forum(#[from] ForumError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::epoch::Block;
+
+    #[test]
+    fn ids_map_to_variants() {
+        assert_eq!(
+            ApplicationId::from_u8(CHAT_APPLICATION),
+            ApplicationId::Chat
+        );
+        assert_eq!(
+            ApplicationId::from_u8(FORUM_APPLICATION),
+            ApplicationId::Forum
+        );
+        assert_eq!(ApplicationId::from_u8(0), ApplicationId::Unknown(0));
+        assert_eq!(ApplicationId::from_u8(15), ApplicationId::Unknown(15));
+        for id in 0..16 {
+            assert_eq!(ApplicationId::from_u8(id).to_u8(), id);
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        chats: Vec<ChatMessage>,
+        posts: Vec<ForumPost>,
+        others: Vec<u8>,
+    }
+
+    impl ApplicationDispatcher for Recorder {
+        fn chat(&mut self, message: ChatMessage) {
+            self.chats.push(message);
+        }
+
+        fn forum(&mut self, post: ForumPost) {
+            self.posts.push(post);
+        }
+
+        fn unknown(&mut self, application: u8, _frames: &[FrameWithMetadata]) {
+            self.others.push(application);
+        }
+    }
+
+    #[test]
+    fn dispatch_routes_by_application() {
+        let now = Epoch::now();
+        let message = ChatMessage {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            recipient: None,
+            room: None,
+            nickname: None,
+            ttl: None,
+            text: "G'day all".to_owned(),
+            sent: Block::new(now, 42).unwrap(),
+            inserted: Some((Station::new("VK7XT".to_owned(), 5).unwrap(), now, 0)),
+        };
+        let post = ForumPost {
+            author: Station::new("VK7NTK".to_owned(), 0).unwrap(),
+            parent: None,
+            subject: "Net tonight".to_owned(),
+            body: "7pm on the usual repeater".to_owned(),
+        };
+        let inserter = message.author.clone();
+        let mut recorder = Recorder::default();
+        recorder
+            .dispatch(
+                &inserter,
+                &now,
+                &chat::fragment(&message, now.index_mod8(), 0).unwrap(),
+            )
+            .unwrap();
+        recorder
+            .dispatch(
+                &inserter,
+                &now,
+                &forum::fragment(&post, now.index_mod8(), 1).unwrap(),
+            )
+            .unwrap();
+        let mut unknown = chat::fragment(&message, now.index_mod8(), 2).unwrap();
+        unknown[0].application = 9;
+        recorder.dispatch(&inserter, &now, &unknown).unwrap();
+
+        assert_eq!(recorder.chats, vec![message]);
+        assert_eq!(recorder.posts, vec![post]);
+        assert_eq!(recorder.others, vec![9]);
+    }
+}
@@ -0,0 +1,320 @@
+//! `proptest` strategies for protocol types, for property testing against the crate.
+//!
+//! Every generated value is valid: callsigns fit in an AX.25 address, epochs,
+//! frame indices, applications, buckets and pages are in range, frame data is
+//! at most `MAX_PAYLOAD_LEN` bytes and lists are short enough to be sent in a
+//! single transmission. Any generated `Transmission` can be encoded and
+//! decoded again without change.
+
+use proptest::{collection::vec, prelude::*};
+
+use super::{
+    epoch::Epoch,
+    global::{
+        BucketContentRequest, BucketContentResponse, ChatterooVersion, Command, ContiguousRange,
+        EpochRequest, EpochResponse, FrameDefinition, FrameRequest, FrameWithMetadata, InsertFrame,
+        PingRequest, PingResponse, QuickEpochResponse, Range, StationDataRequest,
+        StationDataResponse, StationHeard, StationSparse, StationSummary, Status, Transmission,
+        MAX_PAYLOAD_LEN, MAX_RECENTLY_ADDED,
+    },
+    network::Network,
+    station::Station,
+};
+
+/// Implement `Arbitrary` for `$ty` using the strategy `$strategy`.
+macro_rules! arbitrary {
+    ($ty:ty, $strategy:expr) => {
+        impl Arbitrary for $ty {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                $strategy.boxed()
+            }
+        }
+    };
+}
+
+/// A mod-8 epoch.
+fn epoch_mod8() -> impl Strategy<Value = u8> {
+    0..8u8
+}
+
+/// A frame index within an epoch.
+fn frame_index() -> impl Strategy<Value = u16> {
+    0..8192u16
+}
+
+/// A page number and the final page number, with the page not after the final one.
+fn pages() -> impl Strategy<Value = (u8, u8)> {
+    (0..16u8).prop_flat_map(|final_page| (0..=final_page, Just(final_page)))
+}
+
+/// The top and bottom of a range of frame indices, with `bottom <= top`.
+fn index_range() -> impl Strategy<Value = (u16, u16)> {
+    frame_index().prop_flat_map(|top| (Just(top), 0..=top))
+}
+
+arbitrary!(
+    Station,
+    ("[A-Z0-9]{3,6}", 0..=9u8).prop_map(|(callsign, ssid)| Station::new(callsign, ssid).unwrap())
+);
+
+arbitrary!(
+    Network,
+    "[A-Z0-9]{1,3}".prop_map(|id| Network::new(id).unwrap())
+);
+
+arbitrary!(Epoch, (0..=u16::MAX as u32).prop_map(Epoch::from_abs));
+
+arbitrary!(
+    ChatterooVersion,
+    prop_oneof![Just(ChatterooVersion::Test), Just(ChatterooVersion::V1)]
+);
+
+arbitrary!(
+    FrameWithMetadata,
+    (
+        epoch_mod8(),
+        frame_index(),
+        any::<bool>(),
+        any::<bool>(),
+        0..16u8,
+        vec(any::<u8>(), 0..=MAX_PAYLOAD_LEN),
+    )
+        .prop_map(
+            |(epoch_mod8, index, start_of_message, end_of_message, application, data)| {
+                FrameWithMetadata {
+                    epoch_mod8,
+                    index,
+                    start_of_message,
+                    end_of_message,
+                    application,
+                    data,
+                }
+            }
+        )
+);
+
+arbitrary!(
+    StationSparse,
+    (any::<Station>(), index_range()).prop_map(|(station, (top, bottom))| StationSparse {
+        station,
+        top,
+        bottom,
+    })
+);
+
+arbitrary!(
+    Status,
+    (
+        epoch_mod8(),
+        any::<[u32; 6]>(),
+        vec(any::<StationSparse>(), 0..=MAX_RECENTLY_ADDED)
+    )
+        .prop_map(|(epoch_now_mod8, crcs, recently_added)| Status {
+            epoch_now_mod8,
+            epoch_4_ago_crc: crcs[0],
+            epoch_3_ago_crc: crcs[1],
+            epoch_2_ago_crc: crcs[2],
+            epoch_1_ago_crc: crcs[3],
+            epoch_now_crc: crcs[4],
+            epoch_next_crc: crcs[5],
+            recently_added,
+        })
+);
+
+arbitrary!(
+    StationHeard,
+    (any::<Station>(), any::<bool>())
+        .prop_map(|(station, is_mutual)| StationHeard { station, is_mutual })
+);
+
+arbitrary!(
+    Range,
+    (pages(), vec(any::<StationHeard>(), 0..=10)).prop_map(|((page, final_page), stations)| {
+        Range {
+            final_page,
+            page,
+            stations,
+        }
+    })
+);
+
+arbitrary!(
+    InsertFrame,
+    any::<FrameWithMetadata>().prop_map(|frame| InsertFrame { frame })
+);
+
+arbitrary!(
+    FrameDefinition,
+    (any::<Station>(), any::<FrameWithMetadata>())
+        .prop_map(|(station, frame)| FrameDefinition { station, frame })
+);
+
+arbitrary!(
+    FrameRequest,
+    (
+        any::<Station>(),
+        any::<Station>(),
+        epoch_mod8(),
+        frame_index()
+    )
+        .prop_map(|(target, inserter, epoch_mod8, index)| FrameRequest {
+            target,
+            inserter,
+            epoch_mod8,
+            index,
+        })
+);
+
+arbitrary!(
+    EpochRequest,
+    (any::<Station>(), epoch_mod8())
+        .prop_map(|(target, epoch_mod8)| EpochRequest { target, epoch_mod8 })
+);
+
+arbitrary!(
+    StationSummary,
+    (any::<Station>(), index_range(), any::<u32>()).prop_map(
+        |(station, (top, bottom), epoch_crc)| StationSummary {
+            station,
+            top,
+            bottom,
+            epoch_crc,
+        }
+    )
+);
+
+arbitrary!(
+    QuickEpochResponse,
+    (epoch_mod8(), vec(any::<StationSummary>(), 0..=5)).prop_map(|(epoch_mod8, stations)| {
+        QuickEpochResponse {
+            epoch_mod8,
+            stations,
+        }
+    })
+);
+
+arbitrary!(
+    EpochResponse,
+    (epoch_mod8(), any::<[u32; 16]>()).prop_map(|(epoch_mod8, checksums)| EpochResponse {
+        epoch_mod8,
+        checksums,
+    })
+);
+
+arbitrary!(
+    BucketContentRequest,
+    (any::<Station>(), epoch_mod8(), 0..16u8, 0..16u8).prop_map(
+        |(target, epoch_mod8, bucket, page)| BucketContentRequest {
+            target,
+            epoch_mod8,
+            bucket,
+            page,
+        }
+    )
+);
+
+arbitrary!(
+    BucketContentResponse,
+    (epoch_mod8(), pages(), vec(any::<StationSummary>(), 0..=5)).prop_map(
+        |(epoch_mod8, (page, final_page), stations)| BucketContentResponse {
+            epoch_mod8,
+            final_page,
+            page,
+            stations,
+        }
+    )
+);
+
+arbitrary!(
+    StationDataRequest,
+    (
+        any::<Station>(),
+        any::<Station>(),
+        epoch_mod8(),
+        frame_index()
+    )
+        .prop_map(
+            |(target, station, epoch_mod8, from_index)| StationDataRequest {
+                target,
+                station,
+                epoch_mod8,
+                from_index,
+            }
+        )
+);
+
+arbitrary!(
+    ContiguousRange,
+    index_range().prop_map(|(top, bottom)| ContiguousRange { top, bottom })
+);
+
+arbitrary!(
+    StationDataResponse,
+    (
+        any::<Station>(),
+        epoch_mod8(),
+        any::<bool>(),
+        vec(any::<ContiguousRange>(), 0..=10)
+    )
+        .prop_map(
+            |(station, epoch_mod8, end_of_data, ranges)| StationDataResponse {
+                station,
+                epoch_mod8,
+                end_of_data,
+                ranges,
+            }
+        )
+);
+
+arbitrary!(
+    PingRequest,
+    any::<Station>().prop_map(|target| PingRequest { target })
+);
+
+arbitrary!(
+    PingResponse,
+    (any::<Station>(), "[ -~]{0,40}")
+        .prop_map(|(target, diagnostic)| PingResponse { target, diagnostic })
+);
+
+arbitrary!(
+    Command,
+    prop_oneof![
+        any::<Status>().prop_map(Command::Status),
+        any::<Range>().prop_map(Command::Range),
+        any::<InsertFrame>().prop_map(Command::InsertFrame),
+        any::<FrameDefinition>().prop_map(Command::RepeatFrame),
+        any::<FrameRequest>().prop_map(Command::QuickSyncFrameRequest),
+        any::<FrameDefinition>().prop_map(Command::QuickSyncFrameResponse),
+        any::<FrameRequest>().prop_map(Command::BackfillFrameRequest),
+        any::<FrameDefinition>().prop_map(Command::BackfillFrameResponse),
+        any::<EpochRequest>().prop_map(Command::EpochRequest),
+        any::<QuickEpochResponse>().prop_map(Command::QuickEpochResponse),
+        any::<EpochResponse>().prop_map(Command::EpochResponse),
+        any::<BucketContentRequest>().prop_map(Command::BucketContentRequest),
+        any::<BucketContentResponse>().prop_map(Command::BucketContentResponse),
+        any::<StationDataRequest>().prop_map(Command::StationDataRequest),
+        any::<StationDataResponse>().prop_map(Command::StationDataResponse),
+        any::<PingRequest>().prop_map(Command::PingRequest),
+        any::<PingResponse>().prop_map(Command::PingResponse),
+    ]
+);
+
+arbitrary!(
+    Transmission,
+    (
+        any::<ChatterooVersion>(),
+        any::<Network>(),
+        any::<Station>(),
+        any::<Command>()
+    )
+        .prop_map(|(version, network, sender, command)| Transmission {
+            version,
+            network,
+            sender,
+            command,
+        })
+);
@@ -1 +1,1350 @@
 //! Messages related to the real-time chat application.
+//!
+//! A chat message is carried in the data of one or more frames with the
+//! `CHAT_APPLICATION` id. Its encoding is:
+//!
+//! * The version of this encoding, `CHAT_FORMAT_VERSION` (1 byte)
+//! * A flags byte, where `FLAG_DIRECT` indicates a message for one station,
+//!   `FLAG_ROOM` a message for a room other than the default,
+//!   `FLAG_NICKNAME` a message carrying its author's nickname, `FLAG_TTL`
+//!   a message which may be forgotten after a while and `FLAG_CHECKSUM` a
+//!   message carrying a checksum
+//! * If `FLAG_CHECKSUM` is set, a CRC32 of the rest of the data (4 bytes)
+//! * The author's station identifier, in compact form without a net prefix
+//! * For direct messages, the recipient's station identifier in the same form
+//! * If `FLAG_ROOM` is set, the length of the room name (1 byte) then the name
+//! * If `FLAG_NICKNAME` is set, the length of the nickname (1 byte) then the
+//!   nickname as UTF-8
+//! * If `FLAG_TTL` is set, the number of blocks the message is useful for (2 bytes)
+//! * The `Block` in which the message was written (2 bytes)
+//! * The text of the message as UTF-8, running to the end of the data
+//!
+//! Messages too long for a single frame are split across consecutive frames,
+//! taking care not to split a character of the text between two frames.
+//!
+//! Chat also has these other kinds of data, each in a single frame with the
+//! header described in the `application` module:
+//!
+//! * `Presence` (`KIND_PRESENCE`), announcing that a station is online: the
+//!   author, the `Block` it was sent in and an optional status line as UTF-8
+//!   running to the end of the data
+//! * `Ack` (`KIND_ACK`), sent by the recipient of a direct message to say
+//!   that its node has stored it: the acknowledging station, then the station
+//!   which inserted the message, the absolute index of the epoch it was
+//!   inserted in (2 bytes) and the index of its first frame (2 bytes)
+//! * `SetNickname` (`KIND_SET_NICKNAME`), giving a nickname without sending a
+//!   message: the author and the nickname as UTF-8 running to the end of the
+//!   data, where an empty nickname clears it
+//! * `Reaction` (`KIND_REACTION`) to a message: the reacting station, a
+//!   reference to the message in the same form as an `Ack` and the reaction
+//!   as UTF-8 running to the end of the data
+//!
+//! An `Activity` indicator, saying that a station is typing in a room, is too
+//! transient to insert. It's sent instead as an unsolicited `PingResponse`,
+//! which nodes never store or relay, with the diagnostic `ACTIVITY_DIAGNOSTIC`
+//! optionally followed by a space and the name of the room.
+
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+use crate::error::Error;
+
+use super::{
+    application::{self, AssemblyError, KindError, SingleFrameKind},
+    epoch::Block,
+    epoch::Epoch,
+    global::{FrameWithMetadata, PingResponse, MAX_PAYLOAD_LEN},
+    station::Station,
+};
+
+/// Application id of chat in `FrameWithMetadata::application`.
+pub const CHAT_APPLICATION: u8 = 1;
+
+/// Version of the encoding described above, carried in the first byte of the data.
+///
+/// Data with any other version was written by a newer client and can't be decoded.
+pub const CHAT_FORMAT_VERSION: u8 = 1;
+
+/// Flag set on a message addressed to a single station.
+const FLAG_DIRECT: u8 = 0b00000001;
+
+/// Flag set on a message which names the room it belongs to.
+const FLAG_ROOM: u8 = 0b00000010;
+
+/// Flag set on a message which carries its author's nickname.
+const FLAG_NICKNAME: u8 = 0b00000100;
+
+/// Flag set on a message which carries a hint of how long it's useful for.
+const FLAG_TTL: u8 = 0b00001000;
+
+/// Flag set on a message which carries a checksum to verify its reassembly.
+const FLAG_CHECKSUM: u8 = 0b00010000;
+
+/// All flags understood by this version of the encoding.
+const KNOWN_FLAGS: u8 = FLAG_DIRECT | FLAG_ROOM | FLAG_NICKNAME | FLAG_TTL | FLAG_CHECKSUM;
+
+/// Kind byte of a presence announcement.
+const KIND_PRESENCE: u8 = 0;
+
+/// Kind byte of an acknowledgement of a direct message.
+const KIND_ACK: u8 = 1;
+
+/// Kind byte of a station setting its nickname.
+const KIND_SET_NICKNAME: u8 = 2;
+
+/// Kind byte of a reaction to a message.
+const KIND_REACTION: u8 = 3;
+
+/// Longest reaction in bytes, enough for any single emoji.
+pub const MAX_REACTION_LEN: usize = 32;
+
+/// Longest nickname in bytes which may be attached to a station.
+pub const MAX_NICKNAME_LEN: usize = 16;
+
+/// Longest text in bytes that a single message may carry, so one can't take over an epoch.
+pub const MAX_TEXT_LEN: usize = 800;
+
+/// Topic channel within a network which chat messages may be sent to.
+///
+/// Room names follow the same rules as network identifiers, except that they
+/// may not be empty: up to 3 characters which are uppercase ASCII or numerals.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Room(String);
+
+impl Room {
+    pub fn new(room: String) -> Result<Self, ChatError> {
+        if !room.is_empty()
+            && room.len() <= 3
+            && room
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            Ok(Self(room))
+        } else {
+            Err(ChatError::InvalidRoom)
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A line of text sent to everybody on the network, or to one station.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// Station which wrote the message.
+    pub author: Station,
+
+    /// Station the message is addressed to, or `None` if it is for everybody.
+    ///
+    /// Direct messages are flooded and stored like any other, so every station
+    /// can read them. Clients should use this to decide whom to show them to.
+    pub recipient: Option<Station>,
+
+    /// Room the message was sent to, or `None` for the network's default room.
+    pub room: Option<Room>,
+
+    /// Name the author would like to be shown by, if any.
+    ///
+    /// At most `MAX_NICKNAME_LEN` bytes. Clients may remember it for the
+    /// author's later messages, as they would one from a `SetNickname`.
+    pub nickname: Option<String>,
+
+    /// Number of blocks after `sent` for which the message is worth keeping.
+    ///
+    /// This is only a hint for chatter which soon goes stale. Frames are still
+    /// flooded and synced like any other, but clients and nodes may prune
+    /// the message after `expiry`. `None` means it doesn't expire.
+    pub ttl: Option<u16>,
+
+    /// Text of the message.
+    pub text: String,
+
+    /// Hour in which the message was written.
+    pub sent: Block,
+
+    /// Where the message was inserted, if it has been.
+    ///
+    /// This isn't part of the encoding. It is filled in by `reassemble` from
+    /// the station and epoch that inserted the frames, and ignored by `fragment`.
+    pub inserted: Option<MessageRef>,
+}
+
+impl ChatMessage {
+    /// Produce the bytes to be carried in frame data for this message.
+    pub fn encoded(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.recipient.is_some() {
+            flags |= FLAG_DIRECT;
+        }
+        if self.room.is_some() {
+            flags |= FLAG_ROOM;
+        }
+        if self.nickname.is_some() {
+            flags |= FLAG_NICKNAME;
+        }
+        if self.ttl.is_some() {
+            flags |= FLAG_TTL;
+        }
+        let mut out = vec![CHAT_FORMAT_VERSION, flags];
+        out.extend(self.author.encoded(""));
+        if let Some(recipient) = &self.recipient {
+            out.extend(recipient.encoded(""));
+        }
+        if let Some(room) = &self.room {
+            out.push(room.name().len() as u8);
+            out.extend(room.name().as_bytes());
+        }
+        if let Some(nickname) = &self.nickname {
+            out.push(nickname.len() as u8);
+            out.extend(nickname.as_bytes());
+        }
+        if let Some(ttl) = self.ttl {
+            out.extend(ttl.to_be_bytes());
+        }
+        out.extend(self.sent.encoded());
+        out.extend(self.text.as_bytes());
+        out
+    }
+
+    /// Check that the message is suitable to send.
+    ///
+    /// The text may be at most `MAX_TEXT_LEN` bytes and must not contain
+    /// control characters other than newlines. Any nickname must pass
+    /// `validate_nickname`.
+    pub fn validate(&self) -> Result<(), ChatError> {
+        if self.text.len() > MAX_TEXT_LEN {
+            return Err(ChatError::TextTooLong);
+        }
+        if self.text.chars().any(|c| c.is_control() && c != '\n') {
+            return Err(ChatError::ControlCharacter);
+        }
+        if let Some(nickname) = &self.nickname {
+            validate_nickname(nickname)?;
+        }
+        Ok(())
+    }
+
+    /// Key which identifies this message across the network.
+    ///
+    /// Frames are flooded, so the same message may be heard and reassembled
+    /// more than once. Clients should show only one message for each id. The
+    /// id is where the message was inserted, so it is only known once the
+    /// message is inserted. The author and block written in the message are
+    /// whatever the sender chose, so they play no part.
+    pub fn id(&self) -> Option<&MessageRef> {
+        self.inserted.as_ref()
+    }
+
+    /// Time after which the message may be pruned, if it has a `ttl`.
+    pub fn expiry(&self) -> Option<OffsetDateTime> {
+        Some(self.approx_time() + Duration::hours(self.ttl?.into()))
+    }
+
+    /// When the message was written, to the nearest hour.
+    ///
+    /// This comes from the block recorded in the message rather than a
+    /// wall-clock time, so every station agrees on it.
+    pub fn approx_time(&self) -> OffsetDateTime {
+        self.sent.start_time()
+    }
+
+    /// Decode a complete message from the concatenated data of its frames.
+    ///
+    /// `now` is the epoch used as context to restore the block the message was sent in.
+    pub fn try_parse(encoded: &[u8], now: &Epoch) -> Result<Self, ChatError> {
+        let encoded = take_version(encoded)?;
+        let (&flags, encoded) = encoded.split_first().ok_or(ChatError::Truncated)?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(ChatError::UnknownFlags(flags));
+        }
+        let encoded = if flags & FLAG_CHECKSUM != 0 {
+            application::take_checksum(encoded)?
+        } else {
+            encoded
+        };
+        let (author, mut encoded) = Station::try_parse(encoded, "")?;
+        let mut recipient = None;
+        if flags & FLAG_DIRECT != 0 {
+            let (station, remaining) = Station::try_parse(encoded, "")?;
+            recipient = Some(station);
+            encoded = remaining;
+        }
+        let mut room = None;
+        if flags & FLAG_ROOM != 0 {
+            let (&len, remaining) = encoded.split_first().ok_or(ChatError::Truncated)?;
+            if remaining.len() < len as usize {
+                return Err(ChatError::Truncated);
+            }
+            let (name, remaining) = remaining.split_at(len as usize);
+            let name = std::str::from_utf8(name).map_err(|_| ChatError::InvalidRoom)?;
+            room = Some(Room::new(name.to_owned())?);
+            encoded = remaining;
+        }
+        let mut nickname = None;
+        if flags & FLAG_NICKNAME != 0 {
+            let (&len, remaining) = encoded.split_first().ok_or(ChatError::Truncated)?;
+            if remaining.len() < len as usize {
+                return Err(ChatError::Truncated);
+            }
+            let (name, remaining) = remaining.split_at(len as usize);
+            let name = std::str::from_utf8(name).map_err(|_| ChatError::InvalidText)?;
+            validate_nickname(name)?;
+            nickname = Some(name.to_owned());
+            encoded = remaining;
+        }
+        let mut ttl = None;
+        if flags & FLAG_TTL != 0 {
+            if encoded.len() < 2 {
+                return Err(ChatError::Truncated);
+            }
+            ttl = Some(u16::from_be_bytes([encoded[0], encoded[1]]));
+            encoded = &encoded[2..];
+        }
+        let (sent, encoded) = Block::try_parse(encoded, now)?;
+        let text = std::str::from_utf8(encoded)
+            .map_err(|_| ChatError::InvalidText)?
+            .to_owned();
+        Ok(Self {
+            author,
+            recipient,
+            room,
+            nickname,
+            ttl,
+            text,
+            sent,
+            inserted: None,
+        })
+    }
+}
+
+/// Announcement that a station is online, for clients to build a list of who is around.
+///
+/// Clients should treat a station as online if they have heard a presence
+/// from it recently, showing the status from the latest one. It is sent in
+/// the epoch of `sent`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Presence {
+    /// Station which is online.
+    pub author: Station,
+
+    /// Short line describing what the station is up to, if any.
+    pub status: Option<String>,
+
+    /// Block in which the presence was announced.
+    pub sent: Block,
+}
+
+impl SingleFrameKind for Presence {
+    const APPLICATION: u8 = CHAT_APPLICATION;
+    const VERSION: u8 = CHAT_FORMAT_VERSION;
+    const KIND: u8 = KIND_PRESENCE;
+    type Error = ChatError;
+
+    fn encode_payload(&self, out: &mut Vec<u8>) -> Result<(), ChatError> {
+        out.extend(self.author.encoded(""));
+        out.extend(self.sent.encoded());
+        if let Some(status) = &self.status {
+            out.extend(status.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn parse_payload(payload: &[u8], epoch: &Epoch) -> Result<Self, ChatError> {
+        let (author, payload) = Station::try_parse(payload, "")?;
+        let (sent, payload) = Block::try_parse(payload, epoch)?;
+        let status = match payload {
+            [] => None,
+            s => Some(
+                std::str::from_utf8(s)
+                    .map_err(|_| ChatError::InvalidText)?
+                    .to_owned(),
+            ),
+        };
+        Ok(Self {
+            author,
+            status,
+            sent,
+        })
+    }
+
+    /// The status must not contain control characters, nor be too long for the frame.
+    fn validate(&self) -> Result<(), ChatError> {
+        if self.encoded()?.len() > MAX_PAYLOAD_LEN {
+            return Err(ChatError::StatusTooLong);
+        }
+        if let Some(status) = &self.status {
+            if status.chars().any(|c| c.is_control()) {
+                return Err(ChatError::ControlCharacter);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Acknowledgement that a direct message was stored by its recipient's node.
+///
+/// Acks are opportunistic. A sender which doesn't hear one can't conclude
+/// that its message wasn't delivered, since the ack itself may be lost or the
+/// recipient's client may not send them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ack {
+    /// Station acknowledging the message.
+    pub author: Station,
+
+    /// Station which inserted the acknowledged message, to whom the ack is addressed.
+    pub recipient: Station,
+
+    /// Epoch in which the acknowledged message was inserted.
+    pub epoch: Epoch,
+
+    /// Index of the acknowledged message's first frame.
+    pub start_index: u16,
+}
+
+impl Ack {
+    /// Acknowledgement from `author` of `message`.
+    ///
+    /// Returns `None` if the message hasn't been inserted.
+    pub fn for_message(author: Station, message: &ChatMessage) -> Option<Self> {
+        let (recipient, epoch, start_index) = message.id()?.clone();
+        Some(Self {
+            author,
+            recipient,
+            epoch,
+            start_index,
+        })
+    }
+
+    /// Whether this acknowledges `message`, comparing it by `ChatMessage::id`.
+    pub fn acknowledges(&self, message: &ChatMessage) -> bool {
+        message.id() == Some(&(self.recipient.clone(), self.epoch, self.start_index))
+    }
+}
+
+impl SingleFrameKind for Ack {
+    const APPLICATION: u8 = CHAT_APPLICATION;
+    const VERSION: u8 = CHAT_FORMAT_VERSION;
+    const KIND: u8 = KIND_ACK;
+    type Error = ChatError;
+
+    fn encode_payload(&self, out: &mut Vec<u8>) -> Result<(), ChatError> {
+        out.extend(self.author.encoded(""));
+        encode_message_ref(&self.recipient, &self.epoch, self.start_index, out)
+    }
+
+    fn parse_payload(payload: &[u8], _epoch: &Epoch) -> Result<Self, ChatError> {
+        let (author, payload) = Station::try_parse(payload, "")?;
+        let ((recipient, epoch, start_index), _) = take_message_ref(payload)?;
+        Ok(Self {
+            author,
+            recipient,
+            epoch,
+            start_index,
+        })
+    }
+}
+
+/// Announcement of the nickname a station would like to be shown by.
+///
+/// Clients can collect these, along with nicknames carried in messages, into
+/// a map from callsign to nickname, keeping the most recent for each station.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetNickname {
+    /// Station whose nickname this is.
+    pub author: Station,
+
+    /// The new nickname, or `None` to stop using one.
+    pub nickname: Option<String>,
+}
+
+impl SingleFrameKind for SetNickname {
+    const APPLICATION: u8 = CHAT_APPLICATION;
+    const VERSION: u8 = CHAT_FORMAT_VERSION;
+    const KIND: u8 = KIND_SET_NICKNAME;
+    type Error = ChatError;
+
+    fn encode_payload(&self, out: &mut Vec<u8>) -> Result<(), ChatError> {
+        out.extend(self.author.encoded(""));
+        if let Some(nickname) = &self.nickname {
+            out.extend(nickname.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn parse_payload(payload: &[u8], _epoch: &Epoch) -> Result<Self, ChatError> {
+        let (author, payload) = Station::try_parse(payload, "")?;
+        let nickname = match payload {
+            [] => None,
+            n => {
+                let n = std::str::from_utf8(n).map_err(|_| ChatError::InvalidText)?;
+                validate_nickname(n)?;
+                Some(n.to_owned())
+            }
+        };
+        Ok(Self { author, nickname })
+    }
+
+    /// Any nickname must pass `validate_nickname`.
+    fn validate(&self) -> Result<(), ChatError> {
+        match &self.nickname {
+            Some(nickname) => validate_nickname(nickname),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A short reaction, such as an emoji, to a chat message.
+///
+/// Clients should show the reactions to each message together, counting
+/// each station's reaction once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reaction {
+    /// Station reacting to the message.
+    pub author: Station,
+
+    /// Station which inserted the message reacted to.
+    pub target_author: Station,
+
+    /// Epoch in which the message reacted to was inserted.
+    pub epoch: Epoch,
+
+    /// Index of the first frame of the message reacted to.
+    pub start_index: u16,
+
+    /// The reaction, at most `MAX_REACTION_LEN` bytes.
+    pub reaction: String,
+}
+
+impl Reaction {
+    /// Reaction from `author` to `message`.
+    ///
+    /// Returns `None` if the message hasn't been inserted.
+    pub fn for_message(author: Station, message: &ChatMessage, reaction: String) -> Option<Self> {
+        let (target_author, epoch, start_index) = message.id()?.clone();
+        Some(Self {
+            author,
+            target_author,
+            epoch,
+            start_index,
+            reaction,
+        })
+    }
+
+    /// Whether this is a reaction to `message`, comparing it by `ChatMessage::id`.
+    pub fn reacts_to(&self, message: &ChatMessage) -> bool {
+        message.id() == Some(&(self.target_author.clone(), self.epoch, self.start_index))
+    }
+}
+
+impl SingleFrameKind for Reaction {
+    const APPLICATION: u8 = CHAT_APPLICATION;
+    const VERSION: u8 = CHAT_FORMAT_VERSION;
+    const KIND: u8 = KIND_REACTION;
+    type Error = ChatError;
+
+    fn encode_payload(&self, out: &mut Vec<u8>) -> Result<(), ChatError> {
+        out.extend(self.author.encoded(""));
+        encode_message_ref(&self.target_author, &self.epoch, self.start_index, out)?;
+        out.extend(self.reaction.as_bytes());
+        Ok(())
+    }
+
+    fn parse_payload(payload: &[u8], _epoch: &Epoch) -> Result<Self, ChatError> {
+        let (author, payload) = Station::try_parse(payload, "")?;
+        let ((target_author, epoch, start_index), payload) = take_message_ref(payload)?;
+        let reaction = Self {
+            author,
+            target_author,
+            epoch,
+            start_index,
+            reaction: std::str::from_utf8(payload)
+                .map_err(|_| ChatError::InvalidText)?
+                .to_owned(),
+        };
+        reaction.validate()?;
+        Ok(reaction)
+    }
+
+    /// The reaction must be between 1 and `MAX_REACTION_LEN` bytes with no control characters.
+    fn validate(&self) -> Result<(), ChatError> {
+        if self.reaction.is_empty()
+            || self.reaction.len() > MAX_REACTION_LEN
+            || self.reaction.chars().any(|c| c.is_control())
+        {
+            return Err(ChatError::InvalidReaction);
+        }
+        Ok(())
+    }
+}
+
+/// Diagnostic of a `PingResponse` carrying an `Activity`.
+pub const ACTIVITY_DIAGNOSTIC: &str = "ACTIVE";
+
+/// Indication that a station is typing or otherwise active in a room.
+///
+/// These are transient. Clients should show them briefly and never persist
+/// them, nor use them to decide whether a station is online.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Activity {
+    /// Station which is active, the sender of the transmission.
+    pub author: Station,
+
+    /// Room the station is active in, or `None` for the default room.
+    pub room: Option<Room>,
+}
+
+impl Activity {
+    /// The `PingResponse` which announces this activity.
+    ///
+    /// Like `PingResponse::goodbye`, it answers no ping and is addressed to
+    /// the author.
+    pub fn response(&self) -> PingResponse {
+        let diagnostic = match &self.room {
+            Some(room) => format!("{} {}", ACTIVITY_DIAGNOSTIC, room.name()),
+            None => ACTIVITY_DIAGNOSTIC.to_owned(),
+        };
+        PingResponse {
+            target: self.author.clone(),
+            diagnostic,
+        }
+    }
+
+    /// The activity announced by `response`, heard from `sender`.
+    ///
+    /// Returns `None` if the response is anything else, including an
+    /// activity in a room with an invalid name.
+    pub fn from_response(sender: &Station, response: &PingResponse) -> Option<Self> {
+        let room = match response.diagnostic.strip_prefix(ACTIVITY_DIAGNOSTIC)? {
+            "" => None,
+            rest => Some(Room::new(rest.strip_prefix(' ')?.to_owned()).ok()?),
+        };
+        Some(Self {
+            author: sender.clone(),
+            room,
+        })
+    }
+}
+
+/// The station which inserted a message, the epoch it was inserted in and the
+/// index of its first frame.
+pub type MessageRef = (Station, Epoch, u16);
+
+/// Append a reference to the message inserted by `inserter` at `start_index` in `epoch` to `out`.
+fn encode_message_ref(
+    inserter: &Station,
+    epoch: &Epoch,
+    start_index: u16,
+    out: &mut Vec<u8>,
+) -> Result<(), ChatError> {
+    let epoch = u16::try_from(epoch.index_abs()).map_err(|_| ChatError::InvalidReference)?;
+    out.extend(inserter.encoded(""));
+    out.extend(epoch.to_be_bytes());
+    out.extend(start_index.to_be_bytes());
+    Ok(())
+}
+
+/// Decode a reference written by `encode_message_ref`, returning the data following it.
+fn take_message_ref(encoded: &[u8]) -> Result<(MessageRef, &[u8]), ChatError> {
+    let (inserter, encoded) = Station::try_parse(encoded, "")?;
+    if encoded.len() < 4 {
+        return Err(ChatError::Truncated);
+    }
+    let epoch = Epoch::from_abs(u16::from_be_bytes([encoded[0], encoded[1]]) as u32);
+    let start_index = u16::from_be_bytes([encoded[2], encoded[3]]);
+    Ok(((inserter, epoch, start_index), &encoded[4..]))
+}
+
+/// Check that `nickname` is suitable to send.
+///
+/// It must be between 1 and `MAX_NICKNAME_LEN` bytes and contain no control characters.
+pub fn validate_nickname(nickname: &str) -> Result<(), ChatError> {
+    if nickname.is_empty()
+        || nickname.len() > MAX_NICKNAME_LEN
+        || nickname.chars().any(|c| c.is_control())
+    {
+        return Err(ChatError::InvalidNickname);
+    }
+    Ok(())
+}
+
+/// Check the version byte at the start of `encoded` and return the data following it.
+fn take_version(encoded: &[u8]) -> Result<&[u8], ChatError> {
+    match encoded.split_first() {
+        Some((&CHAT_FORMAT_VERSION, encoded)) => Ok(encoded),
+        Some((&version, _)) => Err(ChatError::UnsupportedVersion(version)),
+        None => Err(ChatError::Truncated),
+    }
+}
+
+/// Split `message` into chat frames for the epoch `epoch_mod8`, starting at frame `start_index`.
+///
+/// The message must pass `ChatMessage::validate`. See `application::fragment`
+/// for how the frames are marked.
+pub fn fragment(
+    message: &ChatMessage,
+    epoch_mod8: u8,
+    start_index: u16,
+) -> Result<Vec<FrameWithMetadata>, ChatError> {
+    message.validate()?;
+    Ok(fragment_encoded(
+        message,
+        message.encoded(),
+        epoch_mod8,
+        start_index,
+    ))
+}
+
+/// Like `fragment`, but adding a checksum which `reassemble` verifies.
+///
+/// This lets a receiver detect frames which were put together wrongly, at the
+/// cost of 4 bytes in the first frame.
+pub fn fragment_with_checksum(
+    message: &ChatMessage,
+    epoch_mod8: u8,
+    start_index: u16,
+) -> Result<Vec<FrameWithMetadata>, ChatError> {
+    message.validate()?;
+    let encoded = message.encoded();
+    let mut data = vec![encoded[0], encoded[1] | FLAG_CHECKSUM];
+    data.extend(application::checksummed(&encoded[2..]));
+    Ok(fragment_encoded(message, data, epoch_mod8, start_index))
+}
+
+/// Split the `encoded` form of `message` into frames, keeping characters of the text whole.
+fn fragment_encoded(
+    message: &ChatMessage,
+    encoded: Vec<u8>,
+    epoch_mod8: u8,
+    start_index: u16,
+) -> Vec<FrameWithMetadata> {
+    let text_start = encoded.len() - message.text.len();
+    application::fragment_at(&encoded, CHAT_APPLICATION, epoch_mod8, start_index, |i| {
+        i <= text_start || message.text.is_char_boundary(i - text_start)
+    })
+}
+
+/// Decode the chat message carried by `frames`, which `inserter` inserted in `epoch`.
+///
+/// The frames are those produced by `fragment`, and `epoch` is the context
+/// used to restore the block the message was sent in. See
+/// `application::assemble` for which frames make up the message.
+pub fn reassemble(
+    inserter: &Station,
+    epoch: &Epoch,
+    frames: &[FrameWithMetadata],
+) -> Result<ChatMessage, ChatError> {
+    let data = application::assemble(frames, CHAT_APPLICATION)?;
+    let mut message = ChatMessage::try_parse(&data, epoch)?;
+    message.inserted = frames
+        .iter()
+        .find(|f| f.start_of_message)
+        .map(|f| (inserter.clone(), *epoch, f.index));
+    Ok(message)
+}
+
+/// Put `msgs` in the order they were inserted, which is the same on every station.
+///
+/// Messages are ordered by the epoch they were inserted in, then by inserter,
+/// then by the index of their first frame. Messages which haven't been
+/// inserted come last, ordered by the epoch and author they claim.
+pub fn sort_messages(msgs: &mut [ChatMessage]) {
+    msgs.sort_by_key(|m| match &m.inserted {
+        Some((inserter, epoch, index)) => (false, epoch.index_abs(), inserter.to_string(), *index),
+        None => (true, m.sent.epoch().index_abs(), m.author.to_string(), 0),
+    });
+}
+
+#[derive(Error, Debug)]
+pub enum ChatError {
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] Error),
+
+    #[error("Message text is not valid UTF-8")]
+    InvalidText,
+
+    #[error("Message ended unexpectedly")]
+    Truncated,
+
+    #[error("Room name is not up to 3 uppercase ASCII characters or numerals")]
+    InvalidRoom,
+
+    #[error(
+        "Nickname is empty, longer than {MAX_NICKNAME_LEN} bytes or contains a control character"
+    )]
+    InvalidNickname,
+
+    #[error("Message has flags {0:#04x} which aren't understood")]
+    UnknownFlags(u8),
+
+    #[error("Not chat data of the expected kind: {0}")]
+    Kind(#[from] KindError),
+
+    #[error("Message is in format version {0}, which isn't understood")]
+    UnsupportedVersion(u8),
+
+    #[error("Message text is longer than {MAX_TEXT_LEN} bytes")]
+    TextTooLong,
+
+    #[error("Message text contains a control character")]
+    ControlCharacter,
+
+    #[error("Presence status is too long to fit in one frame")]
+    StatusTooLong,
+
+    #[error("Referenced message's epoch is too far in the future to encode")]
+    InvalidReference,
+
+    #[error(
+        "Reaction is empty, longer than {MAX_REACTION_LEN} bytes or contains a control character"
+    )]
+    InvalidReaction,
+
+    #[error("Unable to reassemble message: {0}")]
+    Assembly(#[from] AssemblyError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::global::MAX_PAYLOAD_LEN;
+    use time::macros::datetime;
+
+    fn inserter() -> Station {
+        Station::new("VK7XT".to_owned(), 5).unwrap()
+    }
+
+    fn message(text: &str) -> ChatMessage {
+        ChatMessage {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            recipient: None,
+            room: None,
+            nickname: None,
+            ttl: None,
+            text: text.to_owned(),
+            sent: Block::new(Epoch::from_abs(300), 42).unwrap(),
+            inserted: None,
+        }
+    }
+
+    #[test]
+    fn short_message_single_frame() {
+        let now = Epoch::from_abs(300);
+        let message = ChatMessage {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            recipient: None,
+            room: None,
+            nickname: None,
+            ttl: None,
+            text: "G'day all".to_owned(),
+            sent: Block::new(now, 42).unwrap(),
+            inserted: None,
+        };
+        let encoded = message.encoded();
+        assert!(encoded.len() <= MAX_PAYLOAD_LEN);
+
+        let frame = FrameWithMetadata {
+            epoch_mod8: now.index_mod8(),
+            index: 0,
+            start_of_message: true,
+            end_of_message: true,
+            application: CHAT_APPLICATION,
+            data: encoded,
+        };
+        assert_eq!(ChatMessage::try_parse(&frame.data, &now).unwrap(), message);
+    }
+
+    #[test]
+    fn fragment_single_frame() {
+        let message = message("G'day all");
+        let frames = fragment(&message, 4, 10).unwrap();
+        assert_eq!(frames.len(), 1);
+        let frame = &frames[0];
+        assert_eq!((frame.epoch_mod8, frame.index), (4, 10));
+        assert!(frame.start_of_message && frame.end_of_message);
+        assert_eq!(frame.application, CHAT_APPLICATION);
+        assert_eq!(frame.data, message.encoded());
+    }
+
+    #[test]
+    fn fragment_three_frames() {
+        let message = message(&"abcdefghij".repeat(20));
+        let frames = fragment(&message, 4, 10).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames.iter().map(|f| f.index).collect::<Vec<_>>(),
+            vec![10, 11, 12]
+        );
+        assert_eq!(
+            frames
+                .iter()
+                .map(|f| (f.start_of_message, f.end_of_message))
+                .collect::<Vec<_>>(),
+            vec![(true, false), (false, false), (false, true)]
+        );
+        assert!(frames.iter().all(|f| f.data.len() <= MAX_PAYLOAD_LEN));
+        let data: Vec<u8> = frames.iter().flat_map(|f| f.data.clone()).collect();
+        assert_eq!(data, message.encoded());
+    }
+
+    #[test]
+    fn reassemble_clean() {
+        let now = Epoch::from_abs(300);
+        let mut message = message(&"abcdefghij".repeat(20));
+        let frames = fragment(&message, now.index_mod8(), 10).unwrap();
+        message.inserted = Some((inserter(), now, 10));
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+    }
+
+    #[test]
+    fn reassemble_failures() {
+        let now = Epoch::from_abs(300);
+        let message = message(&"abcdefghij".repeat(20));
+        let frames = fragment(&message, now.index_mod8(), 10).unwrap();
+
+        let mut gapped = frames.clone();
+        gapped.remove(1);
+        assert!(matches!(
+            reassemble(&inserter(), &now, &gapped),
+            Err(ChatError::Assembly(AssemblyError::Gap { after: 10 }))
+        ));
+
+        assert!(matches!(
+            reassemble(&inserter(), &now, &frames[..2]),
+            Err(ChatError::Assembly(AssemblyError::MissingEnd))
+        ));
+        assert!(matches!(
+            reassemble(&inserter(), &now, &frames[1..]),
+            Err(ChatError::Assembly(AssemblyError::MissingStart))
+        ));
+
+        let mut other_app = frames.clone();
+        other_app[2].application = 2;
+        assert!(matches!(
+            reassemble(&inserter(), &now, &other_app),
+            Err(ChatError::Assembly(AssemblyError::WrongApplication(2)))
+        ));
+
+        let mut bad_text = frames.clone();
+        *bad_text[2].data.last_mut().unwrap() = 0xff;
+        assert!(matches!(
+            reassemble(&inserter(), &now, &bad_text),
+            Err(ChatError::InvalidText)
+        ));
+    }
+
+    #[test]
+    fn approx_time_from_block() {
+        // Epoch 300 begins on 2025-10-01, and block 42 is 18:00 on its second day
+        let message = message("G'day all");
+        let time = message.approx_time();
+        assert!(time >= datetime!(2025-10-02 18:00 UTC));
+        assert!(time < datetime!(2025-10-02 19:00 UTC));
+    }
+
+    #[test]
+    fn direct_message_round_trip() {
+        let now = Epoch::from_abs(300);
+        let mut message = message("Are you on 2m tonight?");
+        message.recipient = Some(Station::new("VK7NTK".to_owned(), 0).unwrap());
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        assert_eq!(frames[0].data[1], FLAG_DIRECT);
+        message.inserted = Some((inserter(), now, 0));
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+
+        let mut unknown = message.encoded();
+        unknown[1] |= 0b10000000;
+        assert!(matches!(
+            ChatMessage::try_parse(&unknown, &now),
+            Err(ChatError::UnknownFlags(0b10000001))
+        ));
+    }
+
+    #[test]
+    fn messages_sorted() {
+        let at = |callsign: &str, epoch, index| ChatMessage {
+            author: Station::new(callsign.to_owned(), 0).unwrap(),
+            recipient: None,
+            room: None,
+            nickname: None,
+            ttl: None,
+            text: String::new(),
+            sent: Block::new(Epoch::from_abs(epoch), 0).unwrap(),
+            inserted: Some((
+                Station::new(callsign.to_owned(), 0).unwrap(),
+                Epoch::from_abs(epoch),
+                index,
+            )),
+        };
+        let expected = vec![
+            at("VK7NTK", 300, 2),
+            at("VK7NTK", 300, 10),
+            at("VK7XT", 300, 0),
+            at("VK7NTK", 301, 0),
+            at("VK7XT", 301, 7),
+        ];
+        let mut msgs = vec![
+            expected[4].clone(),
+            expected[1].clone(),
+            expected[3].clone(),
+            expected[0].clone(),
+            expected[2].clone(),
+        ];
+        sort_messages(&mut msgs);
+        assert_eq!(msgs, expected);
+
+        // A message claiming an earlier block is still ordered by where it was inserted
+        let mut backdated = at("VK7XT", 301, 8);
+        backdated.sent = Block::new(Epoch::from_abs(200), 0).unwrap();
+        let mut pending = at("VK7AA", 200, 0);
+        pending.inserted = None;
+        msgs.extend([pending.clone(), backdated.clone()]);
+        sort_messages(&mut msgs);
+        assert_eq!(msgs[5], backdated);
+        assert_eq!(msgs[6], pending);
+    }
+
+    #[test]
+    fn fragment_on_char_boundaries() {
+        let now = Epoch::from_abs(300);
+        // Three-byte characters, so some would straddle an 80 byte split
+        let mut message = message(&"€".repeat(100));
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        assert!(frames.len() > 1);
+        for frame in &frames[1..] {
+            assert!(std::str::from_utf8(&frame.data).is_ok());
+        }
+        message.inserted = Some((inserter(), now, 0));
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+    }
+
+    #[test]
+    fn invalid_text_rejected() {
+        let mut message = message(&"a".repeat(MAX_TEXT_LEN));
+        assert!(fragment(&message, 4, 0).is_ok());
+        message.text.push('a');
+        assert!(matches!(
+            fragment(&message, 4, 0),
+            Err(ChatError::TextTooLong)
+        ));
+
+        message.text = "two\nlines".to_owned();
+        assert!(fragment(&message, 4, 0).is_ok());
+        message.text = "ring\x07ring".to_owned();
+        assert!(matches!(
+            fragment(&message, 4, 0),
+            Err(ChatError::ControlCharacter)
+        ));
+    }
+
+    #[test]
+    fn reassembled_ids_equal() {
+        let now = Epoch::from_abs(300);
+        let message = message("G'day all");
+        assert_eq!(message.id(), None);
+
+        let frames = fragment(&message, now.index_mod8(), 7).unwrap();
+        let first = reassemble(&inserter(), &now, &frames).unwrap();
+        let second = reassemble(&inserter(), &now, &frames).unwrap();
+        assert_eq!(first.id(), Some(&(inserter(), now, 7)));
+        assert_eq!(first.id(), second.id());
+
+        // The same data inserted by another station is a different message,
+        // whatever author it claims
+        let other = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        let copied = reassemble(&other, &now, &frames).unwrap();
+        assert_eq!(copied.author, first.author);
+        assert_ne!(copied.id(), first.id());
+    }
+
+    #[test]
+    fn room_round_trip() {
+        let now = Epoch::from_abs(300);
+        let mut message = message("CQ CQ");
+        message.inserted = Some((inserter(), now, 0));
+
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        assert_eq!(frames[0].data[1] & FLAG_ROOM, 0);
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+
+        message.room = Some(Room::new("HF".to_owned()).unwrap());
+        message.recipient = Some(Station::new("VK7NTK".to_owned(), 0).unwrap());
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+    }
+
+    #[test]
+    fn room_names_validated() {
+        assert!(Room::new("VK7".to_owned()).is_ok());
+        assert!(Room::new(String::new()).is_err());
+        assert!(Room::new("LONG".to_owned()).is_err());
+        assert!(Room::new("hf".to_owned()).is_err());
+    }
+
+    #[test]
+    fn presence_round_trip() {
+        let now = Epoch::from_abs(300);
+        let sent = Block::new(now, 20).unwrap();
+        let author = Station::new("VK7XT".to_owned(), 5).unwrap();
+        for status in [None, Some("Mobile on the Tasman Hwy".to_owned())] {
+            let presence = Presence {
+                author: author.clone(),
+                status: status.clone(),
+                sent: sent.clone(),
+            };
+            let frame = presence.frame(sent.epoch().index_mod8(), 7).unwrap();
+            assert!(Presence::is_carried_by(&frame));
+            assert_eq!(frame.epoch_mod8, now.index_mod8());
+            assert!(frame.start_of_message && frame.end_of_message);
+
+            let decoded = Presence::try_parse(&frame.data, &now).unwrap();
+            assert_eq!(decoded.author, author);
+            assert_eq!(decoded.status, status);
+            assert_eq!(decoded.sent, sent);
+            assert!(ChatMessage::try_parse(&frame.data, &now).is_err());
+        }
+
+        let long = Presence {
+            author,
+            status: Some("x".repeat(MAX_PAYLOAD_LEN)),
+            sent,
+        };
+        assert!(matches!(
+            long.frame(now.index_mod8(), 8),
+            Err(ChatError::StatusTooLong)
+        ));
+    }
+
+    #[test]
+    fn other_kinds_keep_flags_free() {
+        let now = Epoch::from_abs(300);
+        let author = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let nickname = SetNickname {
+            author,
+            nickname: Some("Tom".to_owned()),
+        };
+        let frame = nickname.frame(now.index_mod8(), 0).unwrap();
+        assert_eq!(
+            frame.data[..3],
+            [
+                CHAT_FORMAT_VERSION,
+                application::FLAG_OTHER,
+                KIND_SET_NICKNAME
+            ]
+        );
+
+        // Every flag a message can carry leaves it distinct from the other kinds
+        let mut message = message("CQ CQ");
+        message.ttl = Some(6);
+        message.nickname = Some("Tom".to_owned());
+        message.recipient = Some(Station::new("VK7NTK".to_owned(), 0).unwrap());
+        let frames = fragment_with_checksum(&message, now.index_mod8(), 0).unwrap();
+        assert!(!Presence::is_carried_by(&frames[0]) && !Ack::is_carried_by(&frames[0]));
+        assert!(!SetNickname::is_carried_by(&frames[0]) && !Reaction::is_carried_by(&frames[0]));
+
+        let mut unknown = frame.data.clone();
+        unknown[2] = 0x7f;
+        assert!(matches!(
+            SetNickname::try_parse(&unknown, &now),
+            Err(ChatError::Kind(KindError::UnknownKind(0x7f)))
+        ));
+        assert!(matches!(
+            SetNickname::try_parse(&frame.data[..2], &now),
+            Err(ChatError::Kind(KindError::Truncated))
+        ));
+    }
+
+    #[test]
+    fn format_version_checked() {
+        let now = Epoch::from_abs(300);
+        let message = message("Hello");
+        let mut encoded = message.encoded();
+        assert_eq!(encoded[0], CHAT_FORMAT_VERSION);
+        assert_eq!(ChatMessage::try_parse(&encoded, &now).unwrap(), message);
+
+        encoded[0] = CHAT_FORMAT_VERSION + 1;
+        assert!(matches!(
+            ChatMessage::try_parse(&encoded, &now),
+            Err(ChatError::UnsupportedVersion(v)) if v == CHAT_FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn ack_round_trip() {
+        let now = Epoch::from_abs(300);
+        let mut message = message("Are you on 2m tonight?");
+        let recipient = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        message.recipient = Some(recipient.clone());
+        let frames = fragment(&message, now.index_mod8(), 17).unwrap();
+        let received = reassemble(&inserter(), &now, &frames).unwrap();
+        assert!(Ack::for_message(recipient.clone(), &message).is_none());
+
+        let ack = Ack::for_message(recipient, &received).unwrap();
+        assert_eq!(ack.recipient, message.author);
+        let frame = ack.frame(now.index_mod8(), 3).unwrap();
+        assert!(Ack::is_carried_by(&frame));
+        assert!(!Presence::is_carried_by(&frame));
+
+        let decoded = Ack::try_parse(&frame.data, &now).unwrap();
+        assert_eq!(decoded, ack);
+        assert!(decoded.acknowledges(&received));
+        assert!(!decoded.acknowledges(&message));
+        assert!(ChatMessage::try_parse(&frame.data, &now).is_err());
+    }
+
+    #[test]
+    fn nickname_round_trip() {
+        let now = Epoch::from_abs(300);
+        let mut message = message("Hello from the shack");
+        message.inserted = Some((inserter(), now, 0));
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        assert_eq!(frames[0].data[1] & FLAG_NICKNAME, 0);
+        assert_eq!(
+            reassemble(&inserter(), &now, &frames).unwrap().nickname,
+            None
+        );
+
+        message.nickname = Some("Tom".to_owned());
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+
+        message.nickname = Some("n".repeat(MAX_NICKNAME_LEN + 1));
+        assert!(matches!(
+            fragment(&message, now.index_mod8(), 0),
+            Err(ChatError::InvalidNickname)
+        ));
+
+        let author = message.author.clone();
+        for nickname in [Some("Tom".to_owned()), None] {
+            let set = SetNickname {
+                author: author.clone(),
+                nickname: nickname.clone(),
+            };
+            let frame = set.frame(now.index_mod8(), 1).unwrap();
+            assert!(SetNickname::is_carried_by(&frame));
+            let decoded = SetNickname::try_parse(&frame.data, &now).unwrap();
+            assert_eq!(decoded.author, author);
+            assert_eq!(decoded.nickname, nickname);
+        }
+    }
+
+    #[test]
+    fn reaction_round_trip() {
+        let now = Epoch::from_abs(300);
+        let message = message("Worked VK3 on 6m just now");
+        let frames = fragment(&message, now.index_mod8(), 4).unwrap();
+        let received = reassemble(&inserter(), &now, &frames).unwrap();
+        let author = Station::new("VK7NTK".to_owned(), 0).unwrap();
+
+        let reaction = Reaction::for_message(author, &received, "🎉".to_owned()).unwrap();
+        let frame = reaction.frame(now.index_mod8(), 9).unwrap();
+        assert!(Reaction::is_carried_by(&frame));
+        assert!(!Ack::is_carried_by(&frame));
+
+        let decoded = Reaction::try_parse(&frame.data, &now).unwrap();
+        assert_eq!(decoded, reaction);
+        assert!(decoded.reacts_to(&received));
+
+        let mut empty = reaction;
+        empty.reaction.clear();
+        assert!(matches!(
+            empty.frame(now.index_mod8(), 9),
+            Err(ChatError::InvalidReaction)
+        ));
+    }
+
+    #[test]
+    fn activity_round_trip() {
+        use crate::protocol::{
+            global::Command,
+            wire::{decode_command, encode_command},
+        };
+
+        let author = inserter();
+        for room in [None, Some(Room::new("HF".to_owned()).unwrap())] {
+            let activity = Activity {
+                author: author.clone(),
+                room,
+            };
+            let command = Command::PingResponse(activity.response());
+            let decoded = decode_command(&encode_command(&command, "VK7"), "VK7").unwrap();
+            let Command::PingResponse(response) = decoded else {
+                panic!("expected a ping response, got {decoded:?}");
+            };
+            assert!(!response.is_goodbye());
+            assert_eq!(Activity::from_response(&author, &response), Some(activity));
+        }
+
+        let response = |diagnostic: &str| PingResponse {
+            target: author.clone(),
+            diagnostic: diagnostic.to_owned(),
+        };
+        assert_eq!(Activity::from_response(&author, &response("GOODBYE")), None);
+        assert_eq!(
+            Activity::from_response(&author, &response("ACTIVEHF")),
+            None
+        );
+        assert_eq!(
+            Activity::from_response(&author, &response("ACTIVE hf")),
+            None
+        );
+    }
+
+    #[test]
+    fn ttl_round_trip() {
+        let now = Epoch::from_abs(300);
+        let mut message = message("Anyone on the repeater?");
+        message.inserted = Some((inserter(), now, 0));
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        let received = reassemble(&inserter(), &now, &frames).unwrap();
+        assert_eq!(received.ttl, None);
+        assert_eq!(received.expiry(), None);
+
+        message.ttl = Some(6);
+        message.nickname = Some("Tom".to_owned());
+        let frames = fragment(&message, now.index_mod8(), 0).unwrap();
+        let received = reassemble(&inserter(), &now, &frames).unwrap();
+        assert_eq!(received, message);
+        assert_eq!(received.expiry(), Some(datetime!(2025-10-03 00:00 UTC)));
+    }
+
+    #[test]
+    fn checksum_detects_swapped_frames() {
+        let now = Epoch::now();
+        let message = ChatMessage {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            recipient: None,
+            room: None,
+            nickname: None,
+            ttl: None,
+            text: (0..MAX_PAYLOAD_LEN * 3)
+                .map(|i| char::from(b'a' + (i / MAX_PAYLOAD_LEN) as u8))
+                .collect(),
+            sent: Block::new(now, 12).unwrap(),
+            inserted: Some((inserter(), now, 3)),
+        };
+        let frames = fragment_with_checksum(&message, now.index_mod8(), 3).unwrap();
+        assert!(frames.len() > 2);
+        assert_eq!(reassemble(&inserter(), &now, &frames).unwrap(), message);
+
+        let mut swapped = frames.clone();
+        let data = swapped[1].data.clone();
+        swapped[1].data = swapped[2].data.clone();
+        swapped[2].data = data;
+        assert!(matches!(
+            reassemble(&inserter(), &now, &swapped),
+            Err(ChatError::Protocol(Error::MessageChecksumMismatch))
+        ));
+
+        // Without a checksum the wrong text goes unnoticed
+        let mut unchecked = fragment(&message, now.index_mod8(), 3).unwrap();
+        let data = unchecked[1].data.clone();
+        unchecked[1].data = unchecked[2].data.clone();
+        unchecked[2].data = data;
+        assert_ne!(
+            reassemble(&inserter(), &now, &unchecked).unwrap().text,
+            message.text
+        );
+    }
+}
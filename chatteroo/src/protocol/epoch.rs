@@ -2,7 +2,7 @@
 
 use crate::error::Error;
 use time::macros::datetime;
-use time::OffsetDateTime;
+use time::{Duration, OffsetDateTime};
 
 /// Beginning of time in the chatteroo universe
 const START: OffsetDateTime = datetime!(2020-01-01 0:00 UTC);
@@ -17,7 +17,9 @@ const START: OffsetDateTime = datetime!(2020-01-01 0:00 UTC);
 /// * Epoch 0 lasts from 2020-01-01 00:00:00 to 2020-01-07 23:59:59.
 /// * Epoch 1 lasts from 2020-01-08 00:00:00 to 2020-01-14 23:59:59.
 /// * And so on.
-#[derive(Debug, Eq, PartialEq)]
+///
+/// Epochs are ordered by their absolute index, so earlier epochs compare as less than later ones.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct Epoch {
     abs: u32,
 }
@@ -52,7 +54,19 @@ impl Epoch {
     /// something has gone terribly wrong with our relative clocks since it should never happen.
     /// Therefore if we hit this "dead value", `from_mod8` will return an error.
     pub fn from_mod8(mod8: u8) -> Result<Self, Error> {
-        let now_abs = Self::now().abs;
+        Self::from_mod8_at(mod8, OffsetDateTime::now_utc())
+    }
+
+    /// Restore an `Epoch` from the abbreviated mod-8 format as if the current time were `now`.
+    ///
+    /// See `from_mod8` for details.
+    pub fn from_mod8_at(mod8: u8, now: OffsetDateTime) -> Result<Self, Error> {
+        Self::from_mod8_relative(mod8, &Self::at(now))
+    }
+
+    /// Restore an `Epoch` from the abbreviated mod-8 format as if `now` were the current epoch.
+    fn from_mod8_relative(mod8: u8, now: &Epoch) -> Result<Self, Error> {
+        let now_abs = now.abs;
         let curr_candidate = (now_abs & 0xfffffff8) + mod8 as u32;
         let upper_candidate = curr_candidate + 8;
         let lower_candidate = curr_candidate - 8;
@@ -73,6 +87,11 @@ impl Epoch {
         }
     }
 
+    /// Restore an `Epoch` from its absolute numeric index, as returned by `index_abs`.
+    pub fn from_abs(abs: u32) -> Self {
+        Self { abs }
+    }
+
     /// Absolute numeric index of this `Epoch`
     pub fn index_abs(&self) -> u32 {
         self.abs
@@ -93,12 +112,32 @@ impl Epoch {
     /// May be negative if this epoch is from the future - particularly possible if talking to
     /// another station with some clock skew.
     pub fn age(&self) -> i32 {
-        let now = Self::now();
-        now.abs as i32 - self.abs as i32
+        self.weeks_between(&Self::now())
+    }
+
+    /// Number of weeks from this epoch until `other`.
+    ///
+    /// Negative if `other` is earlier than this epoch.
+    pub fn weeks_between(&self, other: &Epoch) -> i32 {
+        other.abs as i32 - self.abs as i32
+    }
+
+    /// Whether this epoch is one that stations should still be actively syncing.
+    ///
+    /// This is the same window of epochs that can be restored by `from_mod8`:
+    /// from 5 weeks ago up to one week in the future, to allow for clock skew.
+    /// Data for epochs outside this window should not be processed at all.
+    pub fn in_sync_window(&self) -> bool {
+        (-1..=5).contains(&self.age())
+    }
+
+    /// The moment this epoch begins.
+    pub fn start_time(&self) -> OffsetDateTime {
+        START + Duration::weeks(self.abs as i64)
     }
 
     /// Returns an Epoch for a particular given time
-    fn at(dt: OffsetDateTime) -> Self {
+    pub fn at(dt: OffsetDateTime) -> Self {
         let diff = dt - START;
         Self {
             abs: diff.whole_weeks() as u32,
@@ -113,12 +152,21 @@ impl Epoch {
 ///
 /// Blocks are a subdivision of epochs - in a given epoch (week) there are 168 hours, so the block
 /// index can be from 0 to 167 inclusive.
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Block {
     epoch: Epoch,
     index: u32,
 }
 
 impl Block {
+    /// Construct a `Block` for hour `index` (0-167) of `epoch`.
+    pub fn new(epoch: Epoch, index: u32) -> Result<Self, Error> {
+        if index > 167 {
+            return Err(Error::InvalidBlock);
+        }
+        Ok(Self { epoch, index })
+    }
+
     pub fn epoch(&self) -> &Epoch {
         &self.epoch
     }
@@ -126,6 +174,34 @@ impl Block {
     pub fn index(&self) -> u32 {
         self.index
     }
+
+    /// The moment this block begins.
+    pub fn start_time(&self) -> OffsetDateTime {
+        self.epoch.start_time() + Duration::hours(self.index as i64)
+    }
+
+    /// Produce compact binary encoding for this block.
+    ///
+    /// This is two bytes: the epoch's mod-8 index in the top 3 bits, then 5
+    /// unused bits, then the hour index. As with `Epoch::index_mod8`, the block
+    /// must be from the current epoch or one of the 4 before it to be decoded.
+    pub fn encoded(&self) -> Vec<u8> {
+        vec![self.epoch.index_mod8() << 5, self.index as u8]
+    }
+
+    /// Try to parse a block from the beginning of the encoded data.
+    ///
+    /// `now` is the epoch used as context to restore the block's absolute epoch.
+    /// If successful, returns a `Block` instance and the remainder of `encoded`
+    /// which follows. Otherwise returns an error.
+    pub fn try_parse<'a>(encoded: &'a [u8], now: &Epoch) -> Result<(Self, &'a [u8]), Error> {
+        if encoded.len() < 2 {
+            return Err(Error::InvalidBlock);
+        }
+        let epoch = Epoch::from_mod8_relative(encoded[0] >> 5, now)?;
+        let block = Self::new(epoch, encoded[1] as u32)?;
+        Ok((block, &encoded[2..]))
+    }
 }
 
 #[cfg(test)]
@@ -179,4 +255,62 @@ mod tests {
         assert_eq!(curr_count, 1);
         assert_eq!(future_count, 1);
     }
+
+    #[test]
+    fn block_round_trip() {
+        let now = Epoch::from_abs(300);
+        for abs in 295..=300 {
+            let epoch = Epoch::from_abs(abs);
+            for hour in [0, 1, 23, 100, 166, 167] {
+                let block = Block::new(epoch, hour).unwrap();
+                let encoded = block.encoded();
+                assert_eq!(encoded.len(), 2);
+                let (decoded, remaining) = Block::try_parse(&encoded, &now).unwrap();
+                assert_eq!(decoded, block, "epoch {} hour {}", abs, hour);
+                assert!(remaining.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn start_times() {
+        assert_eq!(Epoch::from_abs(0).start_time(), START);
+        assert_eq!(
+            Epoch::from_abs(1).start_time(),
+            datetime!(2020-01-08 0:00 UTC)
+        );
+        let block = Block::new(Epoch::from_abs(1), 25).unwrap();
+        assert_eq!(block.start_time(), datetime!(2020-01-09 1:00 UTC));
+        assert_eq!(Epoch::at(block.start_time()), Epoch::from_abs(1));
+    }
+
+    #[test]
+    fn block_invalid() {
+        let now = Epoch::from_abs(300);
+        assert!(Block::new(now, 168).is_err());
+        assert!(Block::try_parse(&[now.index_mod8() << 5, 168], &now).is_err());
+        assert!(Block::try_parse(&[now.index_mod8() << 5], &now).is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        let a = Epoch::from_abs(300);
+        let b = Epoch::from_abs(303);
+        assert!(a < b);
+        assert!(b > a);
+        assert_eq!(a.max(b), b);
+        assert_eq!(a.weeks_between(&b), 3);
+        assert_eq!(b.weeks_between(&a), -3);
+        assert_eq!(a.weeks_between(&a), 0);
+    }
+
+    #[test]
+    fn sync_window() {
+        let now = Epoch::now();
+        assert!(now.in_sync_window());
+        assert!(Epoch::from_abs(now.index_abs() + 1).in_sync_window());
+        assert!(!Epoch::from_abs(now.index_abs() + 2).in_sync_window());
+        assert!(Epoch::from_abs(now.index_abs() - 5).in_sync_window());
+        assert!(!Epoch::from_abs(now.index_abs() - 6).in_sync_window());
+    }
 }
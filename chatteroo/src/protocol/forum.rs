@@ -1 +1,557 @@
 //! Messages related to the forum (bulletin board) application.
+//!
+//! A forum post is carried in the data of one or more frames with the
+//! `FORUM_APPLICATION` id. Its encoding is:
+//!
+//! * The version of this encoding, `FORUM_FORMAT_VERSION` (1 byte)
+//! * A flags byte, where `FLAG_REPLY` indicates the post has a parent and
+//!   `FLAG_CHECKSUM` that it carries a checksum
+//! * If `FLAG_CHECKSUM` is set, a CRC32 of the rest of the data (4 bytes)
+//! * The author's station identifier, in compact form without a net prefix
+//! * For replies, the parent's inserter in the same form, then the absolute
+//!   index of its epoch (2 bytes) and its frame index (2 bytes)
+//! * The length in bytes of the subject (1 byte)
+//! * The subject as UTF-8
+//! * The body as UTF-8, running to the end of the data
+//!
+//! A `Moderation` asking clients to hide a post (`KIND_HIDE`) is carried in a
+//! single frame with the header described in the `application` module. Its
+//! payload is the moderator's station identifier and a reference to the
+//! hidden post in the same form as a reply's parent.
+
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::error::Error;
+
+use super::{
+    application::{self, AssemblyError, KindError, SingleFrameKind},
+    epoch::Epoch,
+    global::FrameWithMetadata,
+    station::Station,
+};
+
+/// Application id of the forum in `FrameWithMetadata::application`.
+pub const FORUM_APPLICATION: u8 = 2;
+
+/// Version of the encoding described above, carried in the first byte of the data.
+///
+/// Data with any other version was written by a newer client and can't be decoded.
+pub const FORUM_FORMAT_VERSION: u8 = 1;
+
+/// Flag set on a post which replies to another.
+const FLAG_REPLY: u8 = 0b00000001;
+
+/// Flag set on a post which carries a checksum to verify its reassembly.
+const FLAG_CHECKSUM: u8 = 0b00000010;
+
+/// All flags understood by this version of the encoding.
+const KNOWN_FLAGS: u8 = FLAG_REPLY | FLAG_CHECKSUM;
+
+/// Kind byte of a request to hide a post.
+const KIND_HIDE: u8 = 0;
+
+/// The first frame of a post: its inserter, epoch and index within the epoch.
+///
+/// Unlike the sync protocol, posts refer to epochs by absolute index since
+/// threads may continue long after the epoch of the post they began with.
+pub type PostRef = (Station, Epoch, u16);
+
+/// A post on the network's bulletin board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForumPost {
+    /// Station which wrote the post.
+    pub author: Station,
+
+    /// The post this one replies to, or `None` if it begins a new thread.
+    pub parent: Option<PostRef>,
+
+    /// Short description of the post. At most 255 bytes of UTF-8.
+    pub subject: String,
+
+    /// Full text of the post.
+    pub body: String,
+}
+
+impl ForumPost {
+    /// Produce the bytes to be carried in frame data for this post.
+    pub fn encoded(&self) -> Result<Vec<u8>, ForumError> {
+        let subject_len =
+            u8::try_from(self.subject.len()).map_err(|_| ForumError::SubjectTooLong)?;
+        let flags = if self.parent.is_some() { FLAG_REPLY } else { 0 };
+        let mut out = vec![FORUM_FORMAT_VERSION, flags];
+        out.extend(self.author.encoded(""));
+        if let Some(parent) = &self.parent {
+            encode_post_ref(parent, &mut out)?;
+        }
+        out.push(subject_len);
+        out.extend(self.subject.as_bytes());
+        out.extend(self.body.as_bytes());
+        Ok(out)
+    }
+
+    /// Decode a complete post from the concatenated data of its frames.
+    pub fn try_parse(encoded: &[u8]) -> Result<Self, ForumError> {
+        let encoded = take_version(encoded)?;
+        let (&flags, encoded) = encoded.split_first().ok_or(ForumError::Truncated)?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(ForumError::UnknownFlags(flags));
+        }
+        let encoded = if flags & FLAG_CHECKSUM != 0 {
+            application::take_checksum(encoded)?
+        } else {
+            encoded
+        };
+        let (author, mut encoded) = Station::try_parse(encoded, "")?;
+        let mut parent = None;
+        if flags & FLAG_REPLY != 0 {
+            let (post_ref, remaining) = take_post_ref(encoded)?;
+            parent = Some(post_ref);
+            encoded = remaining;
+        }
+        let (&subject_len, encoded) = encoded.split_first().ok_or(ForumError::Truncated)?;
+        if encoded.len() < subject_len as usize {
+            return Err(ForumError::Truncated);
+        }
+        let (subject, body) = encoded.split_at(subject_len as usize);
+        Ok(Self {
+            author,
+            parent,
+            subject: text(subject)?,
+            body: text(body)?,
+        })
+    }
+}
+
+/// Request from a moderator that clients stop showing a post.
+///
+/// There is no authority on a Chatteroo network, so this is only advisory.
+/// Anybody can send one, and whether to honour it (for example, only from
+/// stations the user trusts) is up to each client. Clients which do should
+/// hide the target post when rendering, but nothing removes it from storage
+/// or stops it being synced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Moderation {
+    /// Station asking for the post to be hidden.
+    pub moderator: Station,
+
+    /// The first frame of the post to hide.
+    pub target: PostRef,
+}
+
+impl SingleFrameKind for Moderation {
+    const APPLICATION: u8 = FORUM_APPLICATION;
+    const VERSION: u8 = FORUM_FORMAT_VERSION;
+    const KIND: u8 = KIND_HIDE;
+    type Error = ForumError;
+
+    fn encode_payload(&self, out: &mut Vec<u8>) -> Result<(), ForumError> {
+        out.extend(self.moderator.encoded(""));
+        encode_post_ref(&self.target, out)
+    }
+
+    fn parse_payload(payload: &[u8], _epoch: &Epoch) -> Result<Self, ForumError> {
+        let (moderator, payload) = Station::try_parse(payload, "")?;
+        let (target, _) = take_post_ref(payload)?;
+        Ok(Self { moderator, target })
+    }
+}
+
+/// A post which began a discussion, together with every reply in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForumThread {
+    /// Where the first post of the thread was inserted.
+    pub root_ref: PostRef,
+
+    /// The first post of the thread, or `None` if we don't hold it.
+    ///
+    /// Replies whose parent we don't hold are grouped under a thread like this
+    /// until the missing post arrives.
+    pub root: Option<ForumPost>,
+
+    /// Every reply in the thread at any depth, in the order they were inserted.
+    pub replies: Vec<(PostRef, ForumPost)>,
+}
+
+/// Group `posts`, each paired with where it was inserted, into threads.
+///
+/// Posts only learn where they were inserted once their frames are stored, so
+/// this isn't part of `ForumPost` itself. Threads are ordered by where their
+/// root was inserted, and replies within them likewise. Ties between stations
+/// inserting in the same epoch at the same index are broken by callsign.
+pub fn build_threads(posts: &[(PostRef, ForumPost)]) -> Vec<ForumThread> {
+    let by_ref: HashMap<&PostRef, &ForumPost> = posts.iter().map(|(r, p)| (r, p)).collect();
+    let mut threads: HashMap<&PostRef, ForumThread> = HashMap::new();
+    for (post_ref, post) in posts {
+        // Follow parents up as far as we can, guarding against reference cycles
+        let mut root = post_ref;
+        for _ in 0..posts.len() {
+            match by_ref.get(root).and_then(|p| p.parent.as_ref()) {
+                Some(parent) => root = parent,
+                None => break,
+            }
+        }
+        let thread = threads.entry(root).or_insert_with(|| ForumThread {
+            root_ref: root.clone(),
+            root: None,
+            replies: vec![],
+        });
+        if root == post_ref {
+            thread.root = Some(post.clone());
+        } else {
+            thread.replies.push((post_ref.clone(), post.clone()));
+        }
+    }
+    let mut threads: Vec<ForumThread> = threads.into_values().collect();
+    for thread in &mut threads {
+        thread.replies.sort_by_key(|(r, _)| order(r));
+    }
+    threads.sort_by_key(|t| order(&t.root_ref));
+    threads
+}
+
+/// Key putting posts in the order they were inserted.
+fn order((station, epoch, index): &PostRef) -> (Epoch, u16, String) {
+    (*epoch, *index, station.to_string())
+}
+
+/// Split `post` into forum frames for the epoch `epoch_mod8`, starting at frame `start_index`.
+///
+/// See `application::fragment` for how the frames are marked.
+pub fn fragment(
+    post: &ForumPost,
+    epoch_mod8: u8,
+    start_index: u16,
+) -> Result<Vec<FrameWithMetadata>, ForumError> {
+    Ok(application::fragment(
+        &post.encoded()?,
+        FORUM_APPLICATION,
+        epoch_mod8,
+        start_index,
+    ))
+}
+
+/// Like `fragment`, but adding a checksum which `reassemble` verifies.
+///
+/// This lets a receiver detect frames which were put together wrongly, at the
+/// cost of 4 bytes in the first frame.
+pub fn fragment_with_checksum(
+    post: &ForumPost,
+    epoch_mod8: u8,
+    start_index: u16,
+) -> Result<Vec<FrameWithMetadata>, ForumError> {
+    let encoded = post.encoded()?;
+    let mut data = vec![encoded[0], encoded[1] | FLAG_CHECKSUM];
+    data.extend(application::checksummed(&encoded[2..]));
+    Ok(application::fragment(
+        &data,
+        FORUM_APPLICATION,
+        epoch_mod8,
+        start_index,
+    ))
+}
+
+/// Decode the forum post carried by `frames`, which were produced by `fragment`.
+///
+/// Long posts may be received over a long time as their frames are backfilled.
+/// If the frames marking the start and end of the post are both present but
+/// some between them aren't, this fails with `ForumError::Incomplete`. See
+/// `application::assemble` for which frames make up the post.
+pub fn reassemble(frames: &[FrameWithMetadata]) -> Result<ForumPost, ForumError> {
+    match missing_frames(frames) {
+        Some(missing) if missing > 0 => Err(ForumError::Incomplete { missing }),
+        _ => ForumPost::try_parse(&application::assemble(frames, FORUM_APPLICATION)?),
+    }
+}
+
+/// Number of frames of the post in `frames` which haven't been received yet.
+///
+/// `fragment` gives a post's frames consecutive indices, so this counts the
+/// indices between the start and end frames which aren't in `frames`. Returns
+/// `None` if either of those is missing, since then the length of the post
+/// isn't known.
+pub fn missing_frames(frames: &[FrameWithMetadata]) -> Option<u16> {
+    let start = frames.iter().find(|f| f.start_of_message)?;
+    let in_post =
+        |f: &&FrameWithMetadata| f.epoch_mod8 == start.epoch_mod8 && f.index >= start.index;
+    let end = frames
+        .iter()
+        .filter(in_post)
+        .filter(|f| f.end_of_message)
+        .map(|f| f.index)
+        .min()?;
+    let held: HashSet<u16> = frames
+        .iter()
+        .filter(in_post)
+        .map(|f| f.index)
+        .filter(|&i| i <= end)
+        .collect();
+    Some(end - start.index + 1 - held.len() as u16)
+}
+
+/// Check the version byte at the start of `encoded` and return the data following it.
+fn take_version(encoded: &[u8]) -> Result<&[u8], ForumError> {
+    match encoded.split_first() {
+        Some((&FORUM_FORMAT_VERSION, encoded)) => Ok(encoded),
+        Some((&version, _)) => Err(ForumError::UnsupportedVersion(version)),
+        None => Err(ForumError::Truncated),
+    }
+}
+
+/// Append the inserter, absolute epoch and index of a post to `out`.
+fn encode_post_ref(
+    (inserter, epoch, index): &PostRef,
+    out: &mut Vec<u8>,
+) -> Result<(), ForumError> {
+    let epoch = u16::try_from(epoch.index_abs()).map_err(|_| ForumError::InvalidParent)?;
+    out.extend(inserter.encoded(""));
+    out.extend(epoch.to_be_bytes());
+    out.extend(index.to_be_bytes());
+    Ok(())
+}
+
+/// Decode a reference to a post written by `encode_post_ref`, returning the data following it.
+fn take_post_ref(encoded: &[u8]) -> Result<(PostRef, &[u8]), ForumError> {
+    let (inserter, remaining) = Station::try_parse(encoded, "")?;
+    if remaining.len() < 4 {
+        return Err(ForumError::Truncated);
+    }
+    let epoch = u16::from_be_bytes([remaining[0], remaining[1]]);
+    let index = u16::from_be_bytes([remaining[2], remaining[3]]);
+    Ok((
+        (inserter, Epoch::from_abs(epoch as u32), index),
+        &remaining[4..],
+    ))
+}
+
+fn text(bytes: &[u8]) -> Result<String, ForumError> {
+    Ok(std::str::from_utf8(bytes)
+        .map_err(|_| ForumError::InvalidText)?
+        .to_owned())
+}
+
+#[derive(Error, Debug)]
+pub enum ForumError {
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] Error),
+
+    #[error("Unable to reassemble post: {0}")]
+    Assembly(#[from] AssemblyError),
+
+    #[error("Post is missing {missing} frames which haven't been received yet")]
+    Incomplete { missing: u16 },
+
+    #[error("Post text is not valid UTF-8")]
+    InvalidText,
+
+    #[error("Post subject is longer than 255 bytes")]
+    SubjectTooLong,
+
+    #[error("Post ended unexpectedly")]
+    Truncated,
+
+    #[error("Post has flags {0:#04x} which aren't understood")]
+    UnknownFlags(u8),
+
+    #[error("Not forum data of the expected kind: {0}")]
+    Kind(#[from] KindError),
+
+    #[error("Post is in format version {0}, which isn't understood")]
+    UnsupportedVersion(u8),
+
+    #[error("Parent post's epoch is too far in the future to encode")]
+    InvalidParent,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::global::MAX_PAYLOAD_LEN;
+
+    #[test]
+    fn post_round_trip() {
+        let post = ForumPost {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            parent: None,
+            subject: "Antenna party".to_owned(),
+            body: "Bring a ladder and coax. ".repeat(10),
+        };
+        let frames = fragment(&post, 3, 20).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|f| f.application == FORUM_APPLICATION));
+        assert_eq!(reassemble(&frames).unwrap(), post);
+    }
+
+    #[test]
+    fn subject_length_checked() {
+        let mut post = ForumPost {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            parent: None,
+            subject: "s".repeat(256),
+            body: String::new(),
+        };
+        assert!(matches!(post.encoded(), Err(ForumError::SubjectTooLong)));
+
+        post.subject.truncate(255);
+        let encoded = post.encoded().unwrap();
+        assert_eq!(ForumPost::try_parse(&encoded).unwrap(), post);
+        assert!(matches!(
+            ForumPost::try_parse(&encoded[..encoded.len() - 1]),
+            Err(ForumError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn format_version_checked() {
+        let post = ForumPost {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            parent: None,
+            subject: "Antenna party".to_owned(),
+            body: "Saturday".to_owned(),
+        };
+        let mut encoded = post.encoded().unwrap();
+        assert_eq!(encoded[0], FORUM_FORMAT_VERSION);
+        encoded[0] = 0;
+        assert!(matches!(
+            ForumPost::try_parse(&encoded),
+            Err(ForumError::UnsupportedVersion(0))
+        ));
+    }
+
+    #[test]
+    fn partial_post_reported() {
+        let post = ForumPost {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            parent: None,
+            subject: "Antenna party".to_owned(),
+            body: "Bring a ladder and coax. ".repeat(20),
+        };
+        let frames = fragment(&post, 3, 20).unwrap();
+        let indices: Vec<u16> = frames.iter().map(|f| f.index).collect();
+        assert_eq!(indices, (20..20 + frames.len() as u16).collect::<Vec<_>>());
+        assert_eq!(missing_frames(&frames), Some(0));
+
+        let partial: Vec<FrameWithMetadata> = frames
+            .iter()
+            .filter(|f| f.index != 21 && f.index != 23)
+            .cloned()
+            .collect();
+        assert_eq!(missing_frames(&partial), Some(2));
+        assert!(matches!(
+            reassemble(&partial),
+            Err(ForumError::Incomplete { missing: 2 })
+        ));
+
+        // Without the end frame we can't tell how long the post is
+        assert_eq!(missing_frames(&frames[..frames.len() - 1]), None);
+        assert_eq!(reassemble(&frames).unwrap(), post);
+    }
+
+    #[test]
+    fn parent_round_trip() {
+        let top = ForumPost {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            parent: None,
+            subject: "Antenna party".to_owned(),
+            body: "Saturday".to_owned(),
+        };
+        let reply = ForumPost {
+            author: Station::new("VK7NTK".to_owned(), 0).unwrap(),
+            parent: Some((top.author.clone(), Epoch::from_abs(300), 12)),
+            subject: "Re: Antenna party".to_owned(),
+            body: "I'll bring the ladder".to_owned(),
+        };
+        for post in [top, reply] {
+            let frames = fragment(&post, 3, 20).unwrap();
+            assert_eq!(reassemble(&frames).unwrap(), post);
+        }
+    }
+
+    #[test]
+    fn moderation_round_trip() {
+        let moderator = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        let target = (
+            Station::new("VK7XT".to_owned(), 5).unwrap(),
+            Epoch::from_abs(300),
+            12,
+        );
+        let moderation = Moderation { moderator, target };
+        let frame = moderation.frame(3, 40).unwrap();
+        assert!(Moderation::is_carried_by(&frame));
+        assert!(frame.start_of_message && frame.end_of_message);
+        assert_eq!(frame.application, FORUM_APPLICATION);
+        assert!(frame.data.len() <= MAX_PAYLOAD_LEN);
+
+        let decoded = Moderation::try_parse(&frame.data, &Epoch::from_abs(300)).unwrap();
+        assert_eq!(decoded, moderation);
+        assert!(matches!(
+            ForumPost::try_parse(&frame.data),
+            Err(ForumError::UnknownFlags(application::FLAG_OTHER))
+        ));
+    }
+
+    #[test]
+    fn threads_built() {
+        let post = |callsign: &str, parent: Option<&PostRef>| ForumPost {
+            author: Station::new(callsign.to_owned(), 0).unwrap(),
+            parent: parent.cloned(),
+            subject: "Antenna party".to_owned(),
+            body: String::new(),
+        };
+        let at = |callsign: &str, index| {
+            (
+                Station::new(callsign.to_owned(), 0).unwrap(),
+                Epoch::from_abs(300),
+                index,
+            )
+        };
+        let root = at("VK7XT", 1);
+        let first = at("VK7NTK", 4);
+        let second = at("VK7FDAE", 2);
+        let missing = at("VK7ABC", 0);
+        let orphan = at("VK7XT", 9);
+        let posts = vec![
+            (first.clone(), post("VK7NTK", Some(&root))),
+            (orphan.clone(), post("VK7XT", Some(&missing))),
+            (second.clone(), post("VK7FDAE", Some(&first))),
+            (root.clone(), post("VK7XT", None)),
+        ];
+
+        let threads = build_threads(&posts);
+        assert_eq!(threads.len(), 2);
+
+        assert_eq!(threads[0].root_ref, missing);
+        assert!(threads[0].root.is_none());
+        assert_eq!(threads[0].replies, vec![posts[1].clone()]);
+
+        assert_eq!(threads[1].root_ref, root);
+        assert_eq!(threads[1].root, Some(posts[3].1.clone()));
+        let replies: Vec<&PostRef> = threads[1].replies.iter().map(|(r, _)| r).collect();
+        assert_eq!(replies, vec![&second, &first]);
+    }
+
+    #[test]
+    fn checksum_detects_swapped_frames() {
+        let post = ForumPost {
+            author: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            parent: None,
+            subject: "Antenna party".to_owned(),
+            body: (0..MAX_PAYLOAD_LEN * 3)
+                .map(|i| char::from(b'a' + (i / MAX_PAYLOAD_LEN) as u8))
+                .collect(),
+        };
+        let frames = fragment_with_checksum(&post, 1, 0).unwrap();
+        assert!(frames.len() > 2);
+        assert_eq!(reassemble(&frames).unwrap(), post);
+
+        let mut swapped = frames.clone();
+        let data = swapped[1].data.clone();
+        swapped[1].data = swapped[2].data.clone();
+        swapped[2].data = data;
+        assert!(matches!(
+            reassemble(&swapped),
+            Err(ForumError::Protocol(Error::MessageChecksumMismatch))
+        ));
+    }
+}
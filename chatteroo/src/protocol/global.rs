@@ -1,9 +1,26 @@
 //! Global messages related to data frame sync, not app-specific.
 
-use super::{network::Network, station::Station};
+use time::OffsetDateTime;
+
+use crate::error::Error;
+
+use super::{epoch::Epoch, network::Network, station::Station, wire};
+
+/// Maximum size in bytes of the variable-length part of a command's payload.
+///
+/// Commands carrying lists of stations or frame data should be split into pages
+/// or multiple messages so that this part stays within the limit.
+pub const MAX_PAYLOAD_LEN: usize = 80;
+
+/// Highest index a frame can have within an epoch.
+pub const MAX_FRAME_INDEX: u16 = 8191;
+
+/// Most stations a `Status` can list in `recently_added`.
+pub const MAX_RECENTLY_ADDED: usize = 4;
 
 /// Entire Chatteroo message sent or received on a radio channel.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transmission {
     pub version: ChatterooVersion,
     pub network: Network,
@@ -11,6 +28,52 @@ pub struct Transmission {
     pub command: Command,
 }
 
+impl Transmission {
+    /// Encode this transmission independently of any channel, such as for saving to a file.
+    ///
+    /// See `wire::encode_transmission` for the layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode_transmission(self)
+    }
+
+    /// Decode a transmission written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(wire::decode_transmission(bytes)?)
+    }
+
+    /// Like `Display`, but showing the sender without the prefix of `network` when it is local.
+    ///
+    /// For example `XT-5@VK7 v1 status(epoch 3)`. The compact form is only
+    /// used if this transmission is on `network`, so that the network shown
+    /// after `@` is always the prefix which was removed.
+    pub fn display_for_network(&self, network: &Network) -> String {
+        if network != &self.network {
+            return self.to_string();
+        }
+        format!(
+            "{}@{} {} {}",
+            self.sender.display_compact(network.id()),
+            self.network.id(),
+            self.version,
+            self.command
+        )
+    }
+}
+
+impl std::fmt::Display for Transmission {
+    /// One-line summary such as `VK7XT-5@VK7 v1 status(epoch 3)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}@{} {} {}",
+            self.sender,
+            self.network.id(),
+            self.version,
+            self.command
+        )
+    }
+}
+
 /// Chatteroo protocol version.
 ///
 /// This is intended to be used when breaking changes are made to the
@@ -20,6 +83,7 @@ pub struct Transmission {
 /// network layer. (If possible, the offline database will be rolled
 /// forward however since it would be a shame to lose old messages.)
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChatterooVersion {
     /// Development, experimentation, etc.
     Test,
@@ -27,11 +91,21 @@ pub enum ChatterooVersion {
     V1,
 }
 
+impl std::fmt::Display for ChatterooVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatterooVersion::Test => write!(f, "test"),
+            ChatterooVersion::V1 => write!(f, "v1"),
+        }
+    }
+}
+
 /// Payload variant inside `Transmission`.
 ///
 /// Note that some payloads are identical but have different semantic meanings
 /// based on the command.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
     Status(Status),
 
@@ -60,8 +134,173 @@ pub enum Command {
     PingResponse(PingResponse),
 }
 
+impl Command {
+    /// Name of this command's kind, such as `ping-request`, for logging.
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    /// Which variant this command is.
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Command::Status(_) => CommandKind::Status,
+            Command::Range(_) => CommandKind::Range,
+            Command::InsertFrame(_) => CommandKind::InsertFrame,
+            Command::RepeatFrame(_) => CommandKind::RepeatFrame,
+            Command::QuickSyncFrameRequest(_) => CommandKind::QuickSyncFrameRequest,
+            Command::QuickSyncFrameResponse(_) => CommandKind::QuickSyncFrameResponse,
+            Command::BackfillFrameRequest(_) => CommandKind::BackfillFrameRequest,
+            Command::BackfillFrameResponse(_) => CommandKind::BackfillFrameResponse,
+            Command::EpochRequest(_) => CommandKind::EpochRequest,
+            Command::QuickEpochResponse(_) => CommandKind::QuickEpochResponse,
+            Command::EpochResponse(_) => CommandKind::EpochResponse,
+            Command::BucketContentRequest(_) => CommandKind::BucketContentRequest,
+            Command::BucketContentResponse(_) => CommandKind::BucketContentResponse,
+            Command::StationDataRequest(_) => CommandKind::StationDataRequest,
+            Command::StationDataResponse(_) => CommandKind::StationDataResponse,
+            Command::PingRequest(_) => CommandKind::PingRequest,
+            Command::PingResponse(_) => CommandKind::PingResponse,
+        }
+    }
+
+    /// The mod-8 epoch this command refers to, if it carries one.
+    pub fn epoch_mod8(&self) -> Option<u8> {
+        match self {
+            Command::Status(s) => Some(s.epoch_now_mod8),
+            Command::InsertFrame(InsertFrame { frame })
+            | Command::RepeatFrame(FrameDefinition { frame, .. })
+            | Command::QuickSyncFrameResponse(FrameDefinition { frame, .. })
+            | Command::BackfillFrameResponse(FrameDefinition { frame, .. }) => {
+                Some(frame.epoch_mod8)
+            }
+            Command::QuickSyncFrameRequest(r) | Command::BackfillFrameRequest(r) => {
+                Some(r.epoch_mod8)
+            }
+            Command::EpochRequest(r) => Some(r.epoch_mod8),
+            Command::QuickEpochResponse(r) => Some(r.epoch_mod8),
+            Command::EpochResponse(r) => Some(r.epoch_mod8),
+            Command::BucketContentRequest(r) => Some(r.epoch_mod8),
+            Command::BucketContentResponse(r) => Some(r.epoch_mod8),
+            Command::StationDataRequest(r) => Some(r.epoch_mod8),
+            Command::StationDataResponse(r) => Some(r.epoch_mod8),
+            Command::Range(_) | Command::PingRequest(_) | Command::PingResponse(_) => None,
+        }
+    }
+
+    /// The absolute epoch this command refers to, if it carries one, according to the current clock.
+    ///
+    /// Returns `UnreadableEpoch` if the mod-8 epoch can't be resolved; see `Epoch::from_mod8`.
+    pub fn resolve_epoch(&self) -> Result<Option<Epoch>, Error> {
+        self.resolve_epoch_at(OffsetDateTime::now_utc())
+    }
+
+    /// The absolute epoch this command refers to, if it carries one, as if the current time were `now`.
+    pub fn resolve_epoch_at(&self, now: OffsetDateTime) -> Result<Option<Epoch>, Error> {
+        self.epoch_mod8()
+            .map(|mod8| Epoch::from_mod8_at(mod8, now))
+            .transpose()
+    }
+}
+
+impl std::fmt::Display for Command {
+    /// The command's name followed by the epoch it refers to, if any, e.g. `status(epoch 3)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.epoch_mod8() {
+            Some(epoch) => write!(f, "{}(epoch {})", self.name(), epoch),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Which variant a `Command` is, without its content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CommandKind {
+    Status,
+    Range,
+    InsertFrame,
+    RepeatFrame,
+    QuickSyncFrameRequest,
+    QuickSyncFrameResponse,
+    BackfillFrameRequest,
+    BackfillFrameResponse,
+    EpochRequest,
+    QuickEpochResponse,
+    EpochResponse,
+    BucketContentRequest,
+    BucketContentResponse,
+    StationDataRequest,
+    StationDataResponse,
+    PingRequest,
+    PingResponse,
+}
+
+impl CommandKind {
+    /// Every kind of command, in order of their command codes.
+    pub const ALL: [CommandKind; 17] = [
+        CommandKind::Status,
+        CommandKind::Range,
+        CommandKind::InsertFrame,
+        CommandKind::RepeatFrame,
+        CommandKind::QuickSyncFrameRequest,
+        CommandKind::QuickSyncFrameResponse,
+        CommandKind::BackfillFrameRequest,
+        CommandKind::BackfillFrameResponse,
+        CommandKind::EpochRequest,
+        CommandKind::QuickEpochResponse,
+        CommandKind::EpochResponse,
+        CommandKind::BucketContentRequest,
+        CommandKind::BucketContentResponse,
+        CommandKind::StationDataRequest,
+        CommandKind::StationDataResponse,
+        CommandKind::PingRequest,
+        CommandKind::PingResponse,
+    ];
+
+    /// Lowercase hyphenated name of this kind, such as `ping-request`, for command lines.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandKind::Status => "status",
+            CommandKind::Range => "range",
+            CommandKind::InsertFrame => "insert-frame",
+            CommandKind::RepeatFrame => "repeat-frame",
+            CommandKind::QuickSyncFrameRequest => "quick-sync-frame-request",
+            CommandKind::QuickSyncFrameResponse => "quick-sync-frame-response",
+            CommandKind::BackfillFrameRequest => "backfill-frame-request",
+            CommandKind::BackfillFrameResponse => "backfill-frame-response",
+            CommandKind::EpochRequest => "epoch-request",
+            CommandKind::QuickEpochResponse => "quick-epoch-response",
+            CommandKind::EpochResponse => "epoch-response",
+            CommandKind::BucketContentRequest => "bucket-content-request",
+            CommandKind::BucketContentResponse => "bucket-content-response",
+            CommandKind::StationDataRequest => "station-data-request",
+            CommandKind::StationDataResponse => "station-data-response",
+            CommandKind::PingRequest => "ping-request",
+            CommandKind::PingResponse => "ping-response",
+        }
+    }
+}
+
+impl std::fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for CommandKind {
+    type Err = Error;
+
+    /// Parse a kind from the name given by `name`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CommandKind::ALL
+            .into_iter()
+            .find(|k| k.name() == s)
+            .ok_or_else(|| Error::UnknownCommandKind(s.to_owned()))
+    }
+}
+
 /// Station announces what data it has and recently-added frames.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Status {
     /// Current epoch from point of view of transmitting station. (0-7)
     pub epoch_now_mod8: u8,
@@ -95,7 +334,12 @@ pub struct Status {
     /// range then get flushed out.
     pub epoch_next_crc: u32,
 
-    /// List of up to 4 stations whose data frames we received last.
+    /// List of up to `MAX_RECENTLY_ADDED` (4) stations whose data frames we
+    /// received last.
+    ///
+    /// Encoding a `Status` listing more is a bug: only the first 4 are sent,
+    /// and debug builds panic. A received `Status` listing more, or ending
+    /// partway through a station, is rejected as corrupt.
     ///
     /// The idea is that a station who is mostly up-to-date will be
     /// able to pick off a precise station+frame combination that they
@@ -105,6 +349,20 @@ pub struct Status {
     pub recently_added: Vec<StationSparse>,
 }
 
+impl Status {
+    /// Each epoch checksum paired with its offset in weeks from the current epoch, oldest first.
+    pub fn epoch_crcs(&self) -> [(i32, u32); 6] {
+        [
+            (-4, self.epoch_4_ago_crc),
+            (-3, self.epoch_3_ago_crc),
+            (-2, self.epoch_2_ago_crc),
+            (-1, self.epoch_1_ago_crc),
+            (0, self.epoch_now_crc),
+            (1, self.epoch_next_crc),
+        ]
+    }
+}
+
 /// A Station paired with a subset of the data frames we know from them.
 ///
 /// It is implied that this refers to the current epoch, which must be
@@ -119,6 +377,7 @@ pub struct Status {
 /// us are least likely to have. The newest data is also the most interesting
 /// from the user's perspective, so make it quick and easy to fetch.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StationSparse {
     /// A station for which at least one frame is known.
     pub station: Station,
@@ -132,6 +391,7 @@ pub struct StationSparse {
 
 /// Station is indicating which stations are in radio range.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Range {
     /// Index (starting from 0) of the last page of data when spread across
     /// multiple `Range`s. In other words, (page count - 1).
@@ -154,6 +414,7 @@ pub struct Range {
 
 /// A Station that we can hear, paired with whether we think they hear us.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StationHeard {
     /// Remote station that is in range (we can hear them)
     pub station: Station,
@@ -166,6 +427,7 @@ pub struct StationHeard {
 
 /// Station is inserting a data frame of their own.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InsertFrame {
     /// Frame inserted.
     pub frame: FrameWithMetadata,
@@ -173,6 +435,7 @@ pub struct InsertFrame {
 
 /// Full information about a frame except who inserted it.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FrameWithMetadata {
     /// Current epoch. (0-7)
     pub epoch_mod8: u8,
@@ -190,11 +453,13 @@ pub struct FrameWithMetadata {
     pub application: u8,
 
     /// Frame content. Maximum length is 80 bytes.
+    #[cfg_attr(feature = "serde", serde(with = "super::json::hex_bytes"))]
     pub data: Vec<u8>,
 }
 
 /// Station is sharing a data frame from someone else.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FrameDefinition {
     /// Station which originally inserted this frame.
     pub station: Station,
@@ -205,6 +470,7 @@ pub struct FrameDefinition {
 
 /// Station requests another station to repeat a frame that they have.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FrameRequest {
     /// Station who is being asked to transmit the frame.
     ///
@@ -221,12 +487,42 @@ pub struct FrameRequest {
     pub index: u16,
 }
 
+/// Which kind of `FrameRequest` is being answered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameResponseKind {
+    /// Reply to a `QuickSyncFrameRequest`.
+    QuickSync,
+    /// Reply to a `BackfillFrameRequest`.
+    Backfill,
+}
+
+impl FrameRequest {
+    /// Build the command which answers this request with `frame`, inserted by `station`.
+    ///
+    /// `kind` must match the command in which the request arrived so that the
+    /// requester sees the corresponding response command.
+    pub fn into_response(
+        self,
+        kind: FrameResponseKind,
+        frame: FrameWithMetadata,
+        station: Station,
+    ) -> Command {
+        let definition = FrameDefinition { station, frame };
+        match kind {
+            FrameResponseKind::QuickSync => Command::QuickSyncFrameResponse(definition),
+            FrameResponseKind::Backfill => Command::BackfillFrameResponse(definition),
+        }
+    }
+}
+
 /// Station requests another station to provide more detail about a
 /// given epoch.
 ///
 /// Responder will send an `QuickEpochResponse` if this is possible,
 /// or else an `EpochResponse`.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EpochRequest {
     /// Station being asked about the data they have.
     ///
@@ -245,6 +541,7 @@ pub struct EpochRequest {
 /// message. Once the number of stations means that is no longer possible, all
 /// stations will start using `EpochResponse` and buckets to perform backfill.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct QuickEpochResponse {
     /// Epoch we're talking about.
     pub epoch_mod8: u8,
@@ -257,6 +554,7 @@ pub struct QuickEpochResponse {
 /// Station summarises an epoch's data in by sorting station identifiers
 /// into 16 buckets and checksumming the data within each bucket.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EpochResponse {
     /// Epoch whose data content is being summarised.
     pub epoch_mod8: u8,
@@ -270,6 +568,7 @@ pub struct EpochResponse {
 /// Station requests another station to provide more detail about a
 /// bucket within a given epoch.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BucketContentRequest {
     /// Station being asked about data which they have.
     ///
@@ -291,6 +590,7 @@ pub struct BucketContentRequest {
 ///
 /// The bucket is implicit from the CRC of the station identifiers included.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BucketContentResponse {
     /// Epoch we're talking about.
     pub epoch_mod8: u8,
@@ -310,6 +610,7 @@ pub struct BucketContentResponse {
 ///
 /// The epoch is implicit and must be specified separately from this struct.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StationSummary {
     /// The station whose data frames we're talking about.
     pub station: Station,
@@ -324,9 +625,21 @@ pub struct StationSummary {
     pub epoch_crc: u32,
 }
 
+impl StationSummary {
+    /// Number of bytes this summary occupies in the compact binary encoding.
+    ///
+    /// This is the station identifier, then 2 bytes for the range if `bottom`
+    /// is 0 or 4 bytes otherwise, then the 4-byte CRC.
+    pub fn encoded_len(&self, net_prefix: &str) -> usize {
+        let range_len = if self.bottom == 0 { 2 } else { 4 };
+        self.station.encoded(net_prefix).len() + range_len + 4
+    }
+}
+
 /// Station requests another station to list the frames it has which
 /// were inserted by a given station during a given epoch.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StationDataRequest {
     /// Station being asked about data which they have.
     ///
@@ -349,6 +662,7 @@ pub struct StationDataRequest {
 
 /// Station reports which frames exist for a given station identifier.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StationDataResponse {
     /// Station who inserted these data frames.
     pub station: Station,
@@ -369,6 +683,7 @@ pub struct StationDataResponse {
 
 /// Range of data frame indices known for a particular station.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ContiguousRange {
     /// Top index (inclusive)
     pub top: u16,
@@ -388,6 +703,7 @@ pub struct ContiguousRange {
 /// station"). It should only be used manually by operators and there is no
 /// reason to make ping requests in normal network operation.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PingRequest {
     /// Station that is requested to respond to a ping.
     pub target: Station,
@@ -395,13 +711,197 @@ pub struct PingRequest {
 
 /// Stations responds to a `PingRequest`.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PingResponse {
     /// The station to whose ping is being replied.
     pub target: Station,
 
     /// A short string (less than 80 UTF-8 bytes) decribing the software.
     ///
+    /// Anything beyond 80 bytes is dropped when encoding, without splitting a character.
+    ///
     /// It's recommended that this indicates a software version. For example:
     /// > `Chatteroo by VK7XT v1.5.0`
+    ///
+    /// A station which is shutting down may say so by sending `GOODBYE`,
+    /// optionally followed by a space and any other text, without being
+    /// pinged. See `PingResponse::is_goodbye`. Chat uses the same kind of
+    /// unsolicited response for `chat::Activity`.
     pub diagnostic: String,
 }
+
+/// Diagnostic sent in a `PingResponse` by a station which is leaving the network.
+pub const GOODBYE_DIAGNOSTIC: &str = "GOODBYE";
+
+impl PingResponse {
+    /// Notice that the sender is leaving, addressed to `target`.
+    ///
+    /// Since it doesn't answer a ping, the target is nominal. Stations
+    /// conventionally address it to themselves.
+    pub fn goodbye(target: Station) -> Self {
+        Self {
+            target,
+            diagnostic: GOODBYE_DIAGNOSTIC.to_owned(),
+        }
+    }
+
+    /// Whether the sender is announcing that it is leaving the network.
+    ///
+    /// Peers which hear this can stop trying to sync with the sender instead
+    /// of waiting for it to time out.
+    pub fn is_goodbye(&self) -> bool {
+        match self.diagnostic.strip_prefix(GOODBYE_DIAGNOSTIC) {
+            Some(rest) => rest.is_empty() || rest.starts_with(' '),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_request_responses() {
+        let inserter = Station::new("VK7NTK".to_owned(), 1).unwrap();
+        let request = FrameRequest {
+            target: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            inserter: inserter.clone(),
+            epoch_mod8: 2,
+            index: 40,
+        };
+        let frame = FrameWithMetadata {
+            epoch_mod8: 2,
+            index: 40,
+            start_of_message: true,
+            end_of_message: false,
+            application: 1,
+            data: b"hi".to_vec(),
+        };
+        let expected = FrameDefinition {
+            station: inserter.clone(),
+            frame: frame.clone(),
+        };
+
+        let quick = request.clone().into_response(
+            FrameResponseKind::QuickSync,
+            frame.clone(),
+            inserter.clone(),
+        );
+        assert_eq!(quick, Command::QuickSyncFrameResponse(expected.clone()));
+
+        let backfill = request.into_response(FrameResponseKind::Backfill, frame, inserter);
+        assert_eq!(backfill, Command::BackfillFrameResponse(expected));
+    }
+
+    #[test]
+    fn command_epoch_resolved() {
+        let now = OffsetDateTime::now_utc();
+        let epoch = Epoch::at(now);
+        let target = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let request = |epoch_mod8| {
+            Command::EpochRequest(EpochRequest {
+                target: target.clone(),
+                epoch_mod8,
+            })
+        };
+
+        let current = request(epoch.index_mod8());
+        assert_eq!(current.resolve_epoch_at(now).unwrap(), Some(epoch));
+
+        // Two weeks in the future is the one mod-8 value which never resolves
+        let dead = request((epoch.index_mod8() + 2) % 8);
+        assert!(matches!(
+            dead.resolve_epoch_at(now),
+            Err(Error::UnreadableEpoch)
+        ));
+
+        let ping = Command::PingRequest(PingRequest { target });
+        assert_eq!(ping.resolve_epoch_at(now).unwrap(), None);
+    }
+
+    #[test]
+    fn goodbye_recognised() {
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        assert!(PingResponse::goodbye(station.clone()).is_goodbye());
+        let response = |diagnostic: &str| PingResponse {
+            target: station.clone(),
+            diagnostic: diagnostic.to_owned(),
+        };
+        assert!(response("GOODBYE").is_goodbye());
+        assert!(response("GOODBYE Chatteroo by VK7XT v1.5.0").is_goodbye());
+        assert!(!response("GOODBYES").is_goodbye());
+        assert!(!response("goodbye").is_goodbye());
+        assert!(!response("Chatteroo by VK7XT v1.5.0").is_goodbye());
+    }
+
+    #[test]
+    fn transmission_summary() {
+        let t = Transmission {
+            version: ChatterooVersion::V1,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            command: Command::Status(Status {
+                epoch_now_mod8: 3,
+                epoch_4_ago_crc: 0,
+                epoch_3_ago_crc: 0,
+                epoch_2_ago_crc: 0,
+                epoch_1_ago_crc: 0,
+                epoch_now_crc: 0,
+                epoch_next_crc: 0,
+                recently_added: vec![],
+            }),
+        };
+        assert_eq!(t.to_string(), "VK7XT-5@VK7 v1 status(epoch 3)");
+        assert_eq!(
+            t.display_for_network(&t.network),
+            "XT-5@VK7 v1 status(epoch 3)"
+        );
+        assert_eq!(
+            t.display_for_network(&Network::new("VK3".to_owned()).unwrap()),
+            "VK7XT-5@VK7 v1 status(epoch 3)"
+        );
+    }
+
+    #[test]
+    fn transmission_bytes_round_trip() {
+        let t = Transmission {
+            version: ChatterooVersion::V1,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            command: Command::PingResponse(PingResponse {
+                target: Station::new("VK3ABC".to_owned(), 0).unwrap(),
+                diagnostic: "73".to_owned(),
+            }),
+        };
+        let bytes = t.to_bytes();
+        assert_eq!(&bytes[..5], [1, 3, b'V', b'K', b'7']);
+        assert_eq!(Transmission::from_bytes(&bytes).unwrap(), t);
+
+        for len in 0..6 {
+            assert!(Transmission::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn command_kind_names() {
+        for kind in CommandKind::ALL {
+            assert_eq!(kind.to_string().parse::<CommandKind>().unwrap(), kind);
+        }
+        assert_eq!(
+            "ping-request".parse::<CommandKind>().unwrap(),
+            CommandKind::PingRequest
+        );
+        assert_eq!(CommandKind::EpochRequest.to_string(), "epoch-request");
+        assert!(matches!(
+            "PingRequest".parse::<CommandKind>(),
+            Err(Error::UnknownCommandKind(_))
+        ));
+
+        let target = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let ping = Command::PingRequest(PingRequest { target });
+        assert_eq!(ping.kind(), CommandKind::PingRequest);
+        assert_eq!(ping.name(), "ping-request");
+        assert_eq!(ping.to_string(), "ping-request");
+    }
+}
@@ -0,0 +1,49 @@
+//! Bytes written as hex digits, for dumps, JSON and CSV.
+
+/// `bytes` as lowercase hex digits, with `separator` between each byte.
+pub fn encode(bytes: &[u8], separator: &str) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Bytes written as hex digits such as those from `encode`.
+///
+/// Whitespace anywhere is ignored and either case is accepted. Returns `None`
+/// if there is anything else in `s`, or an odd number of digits.
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let bytes = [0x00, 0x0f, 0xab, 0xff];
+        assert_eq!(encode(&bytes, " "), "00 0f ab ff");
+        assert_eq!(encode(&bytes, ""), "000fabff");
+        assert_eq!(encode(&[], " "), "");
+        assert_eq!(decode("00 0f ab ff").unwrap(), bytes);
+        assert_eq!(decode("000F\nABff").unwrap(), bytes);
+        assert_eq!(decode(""), Some(vec![]));
+        assert_eq!(decode("00 0"), None);
+        assert_eq!(decode("0g"), None);
+    }
+}
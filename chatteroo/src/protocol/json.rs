@@ -0,0 +1,235 @@
+//! Conversion of transmissions to and from JSON, for monitoring tools.
+//!
+//! Stations are written in their `CALLSIGN-SSID` form, networks as their id
+//! and frame data as a lowercase hex string. Everything else follows the
+//! field names of the structs in `protocol::global`.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use super::{
+    global::{
+        Command, FrameDefinition, InsertFrame, Transmission, MAX_FRAME_INDEX, MAX_PAYLOAD_LEN,
+        MAX_RECENTLY_ADDED,
+    },
+    network::Network,
+    station::Station,
+};
+
+/// Represent `t` as a JSON value.
+pub fn transmission_to_json(t: &Transmission) -> Value {
+    serde_json::to_value(t).expect("Transmission has only string keys")
+}
+
+/// Read a transmission from a JSON value produced by `transmission_to_json`.
+///
+/// Numbers which fit their JSON fields but not the wire encoding, such as an
+/// `epoch_mod8` above 7, are rejected.
+pub fn transmission_from_json(value: &Value) -> Result<Transmission, serde_json::Error> {
+    let t = Transmission::deserialize(value)?;
+    check_ranges(&t.command).map_err(de::Error::custom)?;
+    Ok(t)
+}
+
+/// Check that the values in `command` can be carried on the wire.
+fn check_ranges(command: &Command) -> Result<(), String> {
+    if let Some(epoch_mod8) = command.epoch_mod8().filter(|e| *e > 7) {
+        return Err(format!("epoch_mod8 {} is greater than 7", epoch_mod8));
+    }
+    if let Command::Status(status) = command {
+        if status.recently_added.len() > MAX_RECENTLY_ADDED {
+            return Err(format!(
+                "status lists {} recently added stations, more than {}",
+                status.recently_added.len(),
+                MAX_RECENTLY_ADDED
+            ));
+        }
+    }
+    let (index, data_len) = match command {
+        Command::InsertFrame(InsertFrame { frame })
+        | Command::RepeatFrame(FrameDefinition { frame, .. })
+        | Command::QuickSyncFrameResponse(FrameDefinition { frame, .. })
+        | Command::BackfillFrameResponse(FrameDefinition { frame, .. }) => {
+            (Some(frame.index), frame.data.len())
+        }
+        Command::QuickSyncFrameRequest(r) | Command::BackfillFrameRequest(r) => (Some(r.index), 0),
+        _ => (None, 0),
+    };
+    if let Some(index) = index.filter(|i| *i > MAX_FRAME_INDEX) {
+        return Err(format!(
+            "frame index {} is greater than {}",
+            index, MAX_FRAME_INDEX
+        ));
+    }
+    if data_len > MAX_PAYLOAD_LEN {
+        return Err(format!(
+            "frame data of {} bytes is longer than {}",
+            data_len, MAX_PAYLOAD_LEN
+        ));
+    }
+    Ok(())
+}
+
+impl Serialize for Station {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Station {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Network {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Network {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Network::new(String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// Frame data as a hex string, for use with `#[serde(with)]`.
+pub(crate) mod hex_bytes {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::protocol::hex;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(data, ""))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let data = String::deserialize(deserializer)?;
+        hex::decode(&data).ok_or_else(|| de::Error::custom("invalid hex data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::global::{
+        ChatterooVersion, Command, FrameWithMetadata, InsertFrame, StationSparse, Status,
+    };
+
+    #[test]
+    fn transmission_round_trip() {
+        let t = Transmission {
+            version: ChatterooVersion::V1,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7XT".to_owned(), 5).unwrap(),
+            command: Command::InsertFrame(InsertFrame {
+                frame: FrameWithMetadata {
+                    epoch_mod8: 3,
+                    index: 40,
+                    start_of_message: true,
+                    end_of_message: true,
+                    application: 1,
+                    data: vec![0x00, 0xab, 0xff],
+                },
+            }),
+        };
+        let json = transmission_to_json(&t);
+        assert_eq!(json["sender"], "VK7XT-5");
+        assert_eq!(json["network"], "VK7");
+        assert_eq!(json["command"]["InsertFrame"]["frame"]["data"], "00abff");
+        assert_eq!(transmission_from_json(&json).unwrap(), t);
+
+        let text = serde_json::to_string(&json).unwrap();
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(transmission_from_json(&parsed).unwrap(), t);
+    }
+
+    #[test]
+    fn invalid_fields_rejected() {
+        let mut json = serde_json::json!({
+            "version": "V1",
+            "network": "VK7",
+            "sender": "VK7XT-5",
+            "command": { "InsertFrame": { "frame": {
+                "epoch_mod8": 3,
+                "index": 40,
+                "start_of_message": true,
+                "end_of_message": true,
+                "application": 1,
+                "data": "00abf",
+            } } },
+        });
+        assert!(transmission_from_json(&json).is_err());
+        json["command"]["InsertFrame"]["frame"]["data"] = "00abff".into();
+        assert!(transmission_from_json(&json).is_ok());
+        json["sender"] = "VK7XT".into();
+        assert!(transmission_from_json(&json).is_err());
+    }
+
+    #[test]
+    fn out_of_range_values_rejected() {
+        let json = serde_json::json!({
+            "version": "V1",
+            "network": "VK7",
+            "sender": "VK7XT-5",
+            "command": { "InsertFrame": { "frame": {
+                "epoch_mod8": 7,
+                "index": 8191,
+                "start_of_message": true,
+                "end_of_message": true,
+                "application": 1,
+                "data": "ab".repeat(80),
+            } } },
+        });
+        assert!(transmission_from_json(&json).is_ok());
+        for (field, value) in [
+            ("epoch_mod8", Value::from(8)),
+            ("index", Value::from(8192)),
+            ("data", Value::from("ab".repeat(81))),
+        ] {
+            let mut json = json.clone();
+            json["command"]["InsertFrame"]["frame"][field] = value;
+            assert!(transmission_from_json(&json).is_err(), "{} accepted", field);
+        }
+
+        let mut request = serde_json::json!({
+            "version": "V1",
+            "network": "VK7",
+            "sender": "VK7XT-5",
+            "command": { "EpochRequest": {
+                "target": "VK7NTK-0",
+                "epoch_mod8": 7,
+            } },
+        });
+        assert!(transmission_from_json(&request).is_ok());
+        request["command"]["EpochRequest"]["epoch_mod8"] = 9.into();
+        assert!(transmission_from_json(&request).is_err());
+
+        let sparse = StationSparse {
+            station: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            top: 20,
+            bottom: 0,
+        };
+        for count in [4, 5] {
+            let status = Transmission {
+                version: ChatterooVersion::V1,
+                network: Network::new("VK7".to_owned()).unwrap(),
+                sender: Station::new("VK7XT".to_owned(), 5).unwrap(),
+                command: Command::Status(Status {
+                    epoch_now_mod8: 3,
+                    epoch_4_ago_crc: 0,
+                    epoch_3_ago_crc: 0,
+                    epoch_2_ago_crc: 0,
+                    epoch_1_ago_crc: 0,
+                    epoch_now_crc: 0,
+                    epoch_next_crc: 0,
+                    recently_added: vec![sparse.clone(); count],
+                }),
+            };
+            let decoded = transmission_from_json(&transmission_to_json(&status));
+            assert_eq!(decoded.is_ok(), count <= MAX_RECENTLY_ADDED, "{}", count);
+        }
+    }
+}
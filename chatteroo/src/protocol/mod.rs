@@ -6,10 +6,21 @@
 //! translation to or from the messages defined here. However, the
 //! first channel type to be supported is AX.25 and the messages
 //! have been designed to be efficiently representable that way.
+//! The binary form shared by all channels is in `wire`.
 
+pub mod application;
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
 pub mod chat;
 pub mod epoch;
 pub mod forum;
 pub mod global;
+pub(crate) mod hex;
+#[cfg(feature = "serde")]
+pub mod json;
 pub mod network;
+pub mod ping;
 pub mod station;
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod test_vectors;
+pub mod wire;
@@ -2,6 +2,8 @@
 
 use crate::error::Error;
 
+use super::station::Station;
+
 /// Chatteroo network identifier.
 ///
 /// Nodes in different networks are completely independent and will
@@ -36,4 +38,48 @@ impl Network {
     pub fn id(&self) -> &str {
         &self.0
     }
+
+    /// Advice for an operator whose `station` is not local to this network, if any.
+    ///
+    /// This is not an error, but callsigns which don't begin with the network
+    /// identifier can't use the compact encoding, so every frame mentioning the
+    /// station is longer. Setup tools can show the returned text to suggest
+    /// that the operator check their configuration.
+    pub fn warns_for(&self, station: &Station) -> Option<String> {
+        if self.0.is_empty() {
+            Some("The network identifier is empty, so no callsigns can be sent in compact form. Consider using the callsign prefix of your region.".to_owned())
+        } else if !station.callsign().starts_with(&self.0) {
+            Some(format!(
+                "Callsign {} does not begin with the network identifier {}, so it can't be sent in compact form. Check that this is the right network for your station.",
+                station.callsign(),
+                self.0
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_station_not_warned() {
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let station = Station::new("VK7XT".to_owned(), 5).unwrap();
+        assert_eq!(network.warns_for(&station), None);
+    }
+
+    #[test]
+    fn non_local_station_warned() {
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let station = Station::new("VK3ABC".to_owned(), 0).unwrap();
+        let warning = network.warns_for(&station).unwrap();
+        assert!(warning.contains("VK3ABC"));
+        assert!(warning.contains("VK7"));
+
+        let empty = Network::new(String::new()).unwrap();
+        assert!(empty.warns_for(&station).is_some());
+    }
 }
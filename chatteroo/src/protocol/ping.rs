@@ -0,0 +1,55 @@
+//! Building the commands for a manual ping exchange.
+//!
+//! An operator testing a link sends `make_request` to the station they want
+//! to hear from, which answers with `make_response`. See `PingRequest`.
+
+use super::{
+    global::{Command, PingRequest, PingResponse},
+    station::Station,
+};
+
+/// A request for `target` to answer with a `PingResponse`.
+pub fn make_request(target: Station) -> Command {
+    Command::PingRequest(PingRequest { target })
+}
+
+/// The answer to a ping from `target`, describing this station's software.
+///
+/// The diagnostic takes the recommended form of the software name followed by
+/// its version, such as `Chatteroo by VK7XT v1.5.0` for a `software` of
+/// `Chatteroo by VK7XT` and a `version` of `1.5.0`.
+pub fn make_response(target: Station, software: &str, version: &str) -> Command {
+    Command::PingResponse(PingResponse {
+        target,
+        diagnostic: format!("{} v{}", software, version),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::wire::{decode_command, encode_command};
+
+    #[test]
+    fn exchange_round_trip() {
+        let target = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let request = make_request(target.clone());
+        assert_eq!(
+            decode_command(&encode_command(&request, "VK7"), "VK7").unwrap(),
+            request
+        );
+
+        let response = make_response(target.clone(), "Chatteroo by VK7XT", "1.5.0");
+        assert_eq!(
+            response,
+            Command::PingResponse(PingResponse {
+                target,
+                diagnostic: "Chatteroo by VK7XT v1.5.0".to_owned(),
+            })
+        );
+        assert_eq!(
+            decode_command(&encode_command(&response, "VK7"), "VK7").unwrap(),
+            response
+        );
+    }
+}
@@ -37,7 +37,7 @@ use crate::error::Error;
 /// Unique identifier for a participant in the chatteroo network.
 ///
 /// Callsigns may only be ASCII uppercase and SSIDs must only be `0` to `9`.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Station {
     callsign: String,
     ssid: u8,
@@ -133,6 +133,32 @@ impl Station {
         out
     }
 
+    /// Human-readable identifier with `net_prefix` removed if present, e.g. `XT-5` for `VK7XT-5` on `VK7`.
+    ///
+    /// This matches the callsign characters which are sent on the wire.
+    /// Stations without the prefix are shown in full.
+    pub fn display_compact(&self, net_prefix: &str) -> String {
+        match self.callsign.strip_prefix(net_prefix) {
+            Some(remainder) if !net_prefix.is_empty() => format!("{}-{}", remainder, self.ssid),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Find the shortest encoding of this station identifier among candidate `prefixes`.
+    ///
+    /// Returns the encoding and the prefix used to produce it, or `None` if no
+    /// prefix does better than encoding the full callsign.
+    pub fn best_encoding(&self, prefixes: &[&str]) -> (Vec<u8>, Option<String>) {
+        let mut best = (self.encoded(""), None);
+        for prefix in prefixes {
+            let encoded = self.encoded(prefix);
+            if encoded.len() < best.0.len() {
+                best = (encoded, Some(prefix.to_string()));
+            }
+        }
+        best
+    }
+
     /// Try to parse a station from the beginning of the encoded data.
     ///
     /// If successful, returns a `Station` instance and the remainder of
@@ -219,6 +245,17 @@ impl std::fmt::Display for Station {
     }
 }
 
+impl std::str::FromStr for Station {
+    type Err = Error;
+
+    /// Parse a station in the same `CALLSIGN-SSID` form produced by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (callsign, ssid) = s.rsplit_once('-').ok_or(Error::InvalidStationIdentifier)?;
+        let ssid = ssid.parse().map_err(|_| Error::InvalidSsid)?;
+        Station::new(callsign.to_owned(), ssid)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -320,6 +357,29 @@ mod tests {
         assert!(remainder.is_empty());
     }
 
+    #[test]
+    fn best_encoding() {
+        let s = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let (encoded, prefix) = s.best_encoding(&["VK3", "VK7", "W1"]);
+        assert_eq!(encoded, s.encoded("VK7"));
+        assert_eq!(encoded.len(), 3);
+        assert_eq!(prefix.as_deref(), Some("VK7"));
+
+        let (encoded, prefix) = s.best_encoding(&["VK3", "W1"]);
+        assert_eq!(encoded, s.encoded(""));
+        assert_eq!(prefix, None);
+    }
+
+    #[test]
+    fn parse_string() {
+        let s: Station = "VK7XT-5".parse().unwrap();
+        assert_eq!(s, Station::new("VK7XT".to_owned(), 5).unwrap());
+        assert_eq!(s.to_string().parse::<Station>().unwrap(), s);
+        assert!("VK7XT".parse::<Station>().is_err());
+        assert!("VK7XT-10".parse::<Station>().is_err());
+        assert!("vk7xt-5".parse::<Station>().is_err());
+    }
+
     #[test]
     fn buckets() {
         for (callsign, ssid) in [
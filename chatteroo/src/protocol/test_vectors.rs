@@ -0,0 +1,361 @@
+//! Canonical encodings of transmissions, for checking other implementations against this one.
+//!
+//! Each `TestVector` pairs a transmission with the exact bytes of its
+//! self-contained encoding from `wire::encode_transmission`, and of the AX.25
+//! information field it is sent with: the encoded command followed by the
+//! 4-byte packet CRC. Since that CRC also covers the frame's source and
+//! destination addresses, those are given too. There is at least one vector
+//! for every `Command`. These bytes are guaranteed; a change to any of them is
+//! a change to the protocol.
+
+use super::{
+    global::{
+        BucketContentRequest, BucketContentResponse, ChatterooVersion, Command, ContiguousRange,
+        EpochRequest, EpochResponse, FrameDefinition, FrameRequest, FrameWithMetadata, InsertFrame,
+        PingRequest, PingResponse, QuickEpochResponse, Range, StationDataRequest,
+        StationDataResponse, StationHeard, StationSparse, StationSummary, Status, Transmission,
+    },
+    hex,
+    network::Network,
+    station::Station,
+};
+
+/// A transmission and the information field it must be encoded to.
+#[derive(Clone, Debug)]
+pub struct TestVector {
+    /// Short description of what the vector covers.
+    pub label: &'static str,
+
+    /// The transmission to encode.
+    pub transmission: Transmission,
+
+    /// Expected self-contained encoding from `wire::encode_transmission`.
+    pub wire: Vec<u8>,
+
+    /// Expected AX.25 information field, including the packet CRC.
+    pub info: Vec<u8>,
+
+    /// Expected AX.25 source address, such as `VK7XT-5`.
+    pub source: &'static str,
+
+    /// Expected AX.25 destination address, such as `CHTVK7-1`.
+    pub destination: &'static str,
+}
+
+/// All test vectors, covering every command.
+///
+/// Unless the label says otherwise, transmissions are protocol version 1 on
+/// the `VK7` network from `VK7XT-5`.
+pub fn test_vectors() -> Vec<TestVector> {
+    vec![
+        vector(
+            "status",
+            Command::Status(Status {
+                epoch_now_mod8: 3,
+                epoch_4_ago_crc: 0x11111111,
+                epoch_3_ago_crc: 0x22222222,
+                epoch_2_ago_crc: 0x33333333,
+                epoch_1_ago_crc: 0x44444444,
+                epoch_now_crc: 0x55555555,
+                epoch_next_crc: 0,
+                recently_added: vec![
+                    StationSparse {
+                        station: station("VK7NTK", 1),
+                        top: 40,
+                        bottom: 0,
+                    },
+                    StationSparse {
+                        station: station("VK3ABC", 0),
+                        top: 12,
+                        bottom: 5,
+                    },
+                ],
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 60 11 11 11 11 22 22 22 22 33 \
+             33 33 33 44 44 44 44 55 55 55 55 00 00 00 00 35 32 af 80 28 \
+             54 a7 40 04 29 00 00 0c 00 05",
+            "60 11 11 11 11 22 22 22 22 33 33 33 33 44 44 44 44 55 55 55 \
+             55 00 00 00 00 35 32 af 80 28 54 a7 40 04 29 00 00 0c 00 05 \
+             73 b6 a8 6e",
+        ),
+        vector(
+            "range",
+            Command::Range(Range {
+                final_page: 1,
+                page: 0,
+                stations: vec![
+                    StationHeard {
+                        station: station("VK7NTK", 1),
+                        is_mutual: true,
+                    },
+                    StationHeard {
+                        station: station("VK3ABC", 0),
+                        is_mutual: false,
+                    },
+                ],
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 01 10 02 35 32 af 54 a7 40 04 \
+             29 00 80",
+            "01 10 02 35 32 af 54 a7 40 04 29 00 80 d7 38 e2 78",
+        ),
+        vector(
+            "insert frame",
+            Command::InsertFrame(InsertFrame { frame: frame() }),
+            "01 03 56 4b 37 54 a8 57 4e 90 02 c1 2c 81 47 27 64 61 79",
+            "02 c1 2c 81 47 27 64 61 79 0a 75 d9 f8",
+        ),
+        vector(
+            "repeat frame",
+            Command::RepeatFrame(FrameDefinition {
+                station: station("VK7NTK", 1),
+                frame: frame(),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 03 35 32 af c1 2c 81 47 27 64 \
+             61 79",
+            "03 35 32 af c1 2c 81 47 27 64 61 79 4b 80 b0 5f",
+        ),
+        vector(
+            "quick sync frame request",
+            Command::QuickSyncFrameRequest(frame_request()),
+            "01 03 56 4b 37 54 a8 57 4e 90 04 35 32 af 54 a7 40 04 29 00 \
+             c1 2c",
+            "04 35 32 af 54 a7 40 04 29 00 c1 2c 0d 89 bf 98",
+        ),
+        vector(
+            "quick sync frame response",
+            Command::QuickSyncFrameResponse(FrameDefinition {
+                station: station("VK7NTK", 1),
+                frame: frame(),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 05 35 32 af c1 2c 81 47 27 64 \
+             61 79",
+            "05 35 32 af c1 2c 81 47 27 64 61 79 a4 bd 15 bf",
+        ),
+        vector(
+            "backfill frame request",
+            Command::BackfillFrameRequest(frame_request()),
+            "01 03 56 4b 37 54 a8 57 4e 90 06 35 32 af 54 a7 40 04 29 00 \
+             c1 2c",
+            "06 35 32 af 54 a7 40 04 29 00 c1 2c e1 b2 21 07",
+        ),
+        vector(
+            "backfill frame response",
+            Command::BackfillFrameResponse(FrameDefinition {
+                station: station("VK7NTK", 1),
+                frame: frame(),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 07 35 32 af c1 2c 81 47 27 64 \
+             61 79",
+            "07 35 32 af c1 2c 81 47 27 64 61 79 48 86 8b 20",
+        ),
+        vector(
+            "epoch request",
+            Command::EpochRequest(EpochRequest {
+                target: station("VK7NTK", 1),
+                epoch_mod8: 6,
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 c8 35 32 af",
+            "c8 35 32 af 06 02 0b 1e",
+        ),
+        vector(
+            "quick epoch response",
+            Command::QuickEpochResponse(QuickEpochResponse {
+                epoch_mod8: 6,
+                stations: station_summaries(),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 c9 35 32 af 80 28 de ad be ef \
+             54 a7 40 04 29 00 00 0c 00 05 01 23 45 67",
+            "c9 35 32 af 80 28 de ad be ef 54 a7 40 04 29 00 00 0c 00 05 \
+             01 23 45 67 fb bf 2d cc",
+        ),
+        vector(
+            "epoch response",
+            Command::EpochResponse(EpochResponse {
+                epoch_mod8: 6,
+                checksums: std::array::from_fn(|i| i as u32 * 0x01010101),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 ca 00 00 00 00 01 01 01 01 02 \
+             02 02 02 03 03 03 03 04 04 04 04 05 05 05 05 06 06 06 06 07 \
+             07 07 07 08 08 08 08 09 09 09 09 0a 0a 0a 0a 0b 0b 0b 0b 0c \
+             0c 0c 0c 0d 0d 0d 0d 0e 0e 0e 0e 0f 0f 0f 0f",
+            "ca 00 00 00 00 01 01 01 01 02 02 02 02 03 03 03 03 04 04 04 \
+             04 05 05 05 05 06 06 06 06 07 07 07 07 08 08 08 08 09 09 09 \
+             09 0a 0a 0a 0a 0b 0b 0b 0b 0c 0c 0c 0c 0d 0d 0d 0d 0e 0e 0e \
+             0e 0f 0f 0f 0f 79 68 6f 92",
+        ),
+        vector(
+            "bucket content request",
+            Command::BucketContentRequest(BucketContentRequest {
+                target: station("VK7NTK", 1),
+                epoch_mod8: 6,
+                bucket: 9,
+                page: 2,
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 cb 35 32 af 92",
+            "cb 35 32 af 92 71 aa 58 5d",
+        ),
+        vector(
+            "bucket content response",
+            Command::BucketContentResponse(BucketContentResponse {
+                epoch_mod8: 6,
+                final_page: 2,
+                page: 2,
+                stations: station_summaries(),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 cc 22 35 32 af 80 28 de ad be \
+             ef 54 a7 40 04 29 00 00 0c 00 05 01 23 45 67",
+            "cc 22 35 32 af 80 28 de ad be ef 54 a7 40 04 29 00 00 0c 00 \
+             05 01 23 45 67 2b 3a f9 5c",
+        ),
+        vector(
+            "station data request",
+            Command::StationDataRequest(StationDataRequest {
+                target: station("VK7NTK", 1),
+                station: station("VK3ABC", 0),
+                epoch_mod8: 6,
+                from_index: 300,
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 0d 35 32 af 54 a7 40 04 29 00 \
+             c1 2c",
+            "0d 35 32 af 54 a7 40 04 29 00 c1 2c 90 20 85 09",
+        ),
+        vector(
+            "station data response",
+            Command::StationDataResponse(StationDataResponse {
+                station: station("VK3ABC", 0),
+                epoch_mod8: 6,
+                end_of_data: true,
+                ranges: vec![
+                    ContiguousRange {
+                        top: 300,
+                        bottom: 280,
+                    },
+                    ContiguousRange { top: 99, bottom: 0 },
+                ],
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 0e 54 a7 40 04 29 00 86 01 2c \
+             01 18 80 63",
+            "0e 54 a7 40 04 29 00 86 01 2c 01 18 80 63 96 32 e0 4a",
+        ),
+        vector(
+            "ping request",
+            Command::PingRequest(PingRequest {
+                target: station("VK7NTK", 1),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 0f 35 32 af",
+            "0f 35 32 af ed 98 dd a1",
+        ),
+        vector(
+            "ping response",
+            Command::PingResponse(PingResponse {
+                target: station("VK7NTK", 1),
+                diagnostic: "73 de VK7XT".to_owned(),
+            }),
+            "01 03 56 4b 37 54 a8 57 4e 90 10 35 32 af 37 33 20 64 65 20 \
+             56 4b 37 58 54",
+            "10 35 32 af 37 33 20 64 65 20 56 4b 37 58 54 c6 08 25 18",
+        ),
+        TestVector {
+            label: "test version from a station outside the network",
+            transmission: Transmission {
+                version: ChatterooVersion::Test,
+                network: Network::new("VK7".to_owned()).unwrap(),
+                sender: station("VK3ABC", 0),
+                command: Command::PingRequest(PingRequest {
+                    target: station("VK7NTK", 1),
+                }),
+            },
+            wire: hex::decode("00 03 56 4b 37 54 a7 40 04 29 00 0f 35 32 af").unwrap(),
+            info: hex::decode("0f 35 32 af bd 30 7f eb").unwrap(),
+            source: "VK3ABC-0",
+            destination: "CHTVK7-0",
+        },
+    ]
+}
+
+fn vector(label: &'static str, command: Command, wire: &str, info: &str) -> TestVector {
+    TestVector {
+        label,
+        transmission: Transmission {
+            version: ChatterooVersion::V1,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: station("VK7XT", 5),
+            command,
+        },
+        wire: hex::decode(wire).unwrap(),
+        info: hex::decode(info).unwrap(),
+        source: "VK7XT-5",
+        destination: "CHTVK7-1",
+    }
+}
+
+fn station(callsign: &str, ssid: u8) -> Station {
+    Station::new(callsign.to_owned(), ssid).unwrap()
+}
+
+fn frame() -> FrameWithMetadata {
+    FrameWithMetadata {
+        epoch_mod8: 6,
+        index: 300,
+        start_of_message: true,
+        end_of_message: false,
+        application: 1,
+        data: b"G'day".to_vec(),
+    }
+}
+
+fn frame_request() -> FrameRequest {
+    FrameRequest {
+        target: station("VK7NTK", 1),
+        inserter: station("VK3ABC", 0),
+        epoch_mod8: 6,
+        index: 300,
+    }
+}
+
+fn station_summaries() -> Vec<StationSummary> {
+    vec![
+        StationSummary {
+            station: station("VK7NTK", 1),
+            top: 40,
+            bottom: 0,
+            epoch_crc: 0xdeadbeef,
+        },
+        StationSummary {
+            station: station("VK3ABC", 0),
+            top: 12,
+            bottom: 5,
+            epoch_crc: 0x01234567,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::ax25::encode_transmission_info;
+
+    #[test]
+    fn vectors_match_encoding() {
+        for v in test_vectors() {
+            assert_eq!(
+                encode_transmission_info(&v.transmission).unwrap(),
+                v.info,
+                "{}",
+                v.label
+            );
+        }
+    }
+
+    #[test]
+    fn crc_covers_addresses() {
+        for v in test_vectors() {
+            let (command, crc) = v.info.split_at(v.info.len() - 4);
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(v.source.as_bytes());
+            hasher.update(v.destination.as_bytes());
+            hasher.update(command);
+            assert_eq!(hasher.finalize().to_be_bytes(), crc, "{}", v.label);
+        }
+    }
+}
@@ -0,0 +1,1048 @@
+//! Channel-agnostic binary encoding of transmissions.
+//!
+//! `encode_command` gives the compact form of a `Command` that every channel
+//! carries. Channels add their own addressing and integrity checks around it:
+//! AX.25 puts the network and version in the destination address, the sender
+//! in the source address and appends a CRC. A channel which has no addressing
+//! of its own can send the self-contained form from `encode_transmission`
+//! instead, which also carries the version, network and sender.
+
+use thiserror::Error;
+
+use crate::error::Error;
+
+use super::{
+    global::{
+        BucketContentRequest, BucketContentResponse, ChatterooVersion, Command, ContiguousRange,
+        EpochRequest, EpochResponse, FrameDefinition, FrameRequest, FrameWithMetadata, InsertFrame,
+        PingRequest, PingResponse, QuickEpochResponse, Range, StationDataRequest,
+        StationDataResponse, StationHeard, StationSparse, StationSummary, Status, Transmission,
+        MAX_PAYLOAD_LEN, MAX_RECENTLY_ADDED,
+    },
+    hex,
+    network::Network,
+    station::Station,
+};
+
+#[derive(Error, Debug)]
+pub enum WireError {
+    #[error("Invalid Chatteroo version {0}")]
+    InvalidChatterooVersion(u8),
+
+    #[error("Chatteroo protocol error {0:?}")]
+    ProtocolError(#[from] Error),
+
+    #[error("Invalid Command")]
+    InvalidCommand,
+
+    #[error("No command to decode")]
+    EmptyCommand,
+
+    #[error("Invalid station")]
+    InvalidStation,
+
+    #[error("Truncated message")]
+    Truncated,
+
+    #[error("Invalid UTF-8")]
+    InvalidUtf8,
+
+    #[error("Unexpected data after end of command")]
+    TrailingData,
+
+    #[error("Invalid hex")]
+    InvalidHex,
+}
+
+impl From<WireError> for Error {
+    /// Errors from the protocol layer which were wrapped in `ProtocolError` are
+    /// unwrapped to the original `Error`. Everything else is carried in `Error::Wire`.
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::ProtocolError(e) => e,
+            e => Error::Wire(Box::new(e)),
+        }
+    }
+}
+
+/// Number identifying `version` on the wire.
+pub fn version_byte(version: &ChatterooVersion) -> u8 {
+    match version {
+        ChatterooVersion::Test => 0,
+        ChatterooVersion::V1 => 1,
+    }
+}
+
+/// The protocol version identified by `byte` on the wire.
+pub fn version_from_byte(byte: u8) -> Result<ChatterooVersion, WireError> {
+    match byte {
+        0 => Ok(ChatterooVersion::Test),
+        1 => Ok(ChatterooVersion::V1),
+        n => Err(WireError::InvalidChatterooVersion(n)),
+    }
+}
+
+/// Encode `t` in a self-contained form which doesn't rely on any channel's addressing.
+///
+/// The layout is the protocol version (1 byte, see `version_byte`), the length
+/// of the network id (1 byte), the network id, the sender in compact form
+/// without a net prefix, then the command from `encode_command` using the
+/// network id as the net prefix.
+pub fn encode_transmission(t: &Transmission) -> Vec<u8> {
+    let network = t.network.id();
+    let mut out = vec![version_byte(&t.version), network.len() as u8];
+    out.extend(network.as_bytes());
+    out.extend(t.sender.encoded(""));
+    out.extend(encode_command(&t.command, network));
+    out
+}
+
+/// Decode a transmission written by `encode_transmission`.
+pub fn decode_transmission(bytes: &[u8]) -> Result<Transmission, WireError> {
+    let (&version, bytes) = bytes.split_first().ok_or(WireError::Truncated)?;
+    let version = version_from_byte(version)?;
+    let (&network_len, bytes) = bytes.split_first().ok_or(WireError::Truncated)?;
+    if bytes.len() < network_len as usize {
+        return Err(WireError::Truncated);
+    }
+    let (network, bytes) = bytes.split_at(network_len as usize);
+    let network = std::str::from_utf8(network).map_err(|_| Error::InvalidNetwork)?;
+    let network = Network::new(network.to_owned())?;
+    let (sender, bytes) = Station::try_parse(bytes, "")?;
+    let command = decode_command(bytes, network.id())?;
+    Ok(Transmission {
+        version,
+        network,
+        sender,
+        command,
+    })
+}
+
+/// Hex dump of `encode_transmission`, for pasting into bug reports and chats.
+///
+/// Bytes are lowercase hex with a space between each.
+pub fn encode_to_hex(t: &Transmission) -> String {
+    hex::encode(&encode_transmission(t), " ")
+}
+
+/// Decode a transmission from a hex dump such as one from `encode_to_hex`.
+///
+/// Whitespace anywhere is ignored and either case is accepted, so dumps
+/// which have been rewrapped or written without spaces still decode.
+pub fn decode_from_hex(s: &str) -> Result<Transmission, WireError> {
+    decode_transmission(&hex::decode(s).ok_or(WireError::InvalidHex)?)
+}
+
+/// Decode a command written by `encode_command` with the same `net_prefix`.
+///
+/// `info` must hold exactly one command, with any channel framing such as a
+/// packet CRC already checked and removed.
+pub fn decode_command(info: &[u8], net_prefix: &str) -> Result<Command, WireError> {
+    if info.is_empty() {
+        return Err(WireError::EmptyCommand);
+    }
+    let command: Command = match info[0] & 0b00011111 {
+        0 => {
+            // Status
+            let epoch_now_mod8 = info[0] >> 5;
+            let remaining = &info[1..];
+            let (epoch_4_ago_crc, remaining) = take_crc(remaining)?;
+            let (epoch_3_ago_crc, remaining) = take_crc(remaining)?;
+            let (epoch_2_ago_crc, remaining) = take_crc(remaining)?;
+            let (epoch_1_ago_crc, remaining) = take_crc(remaining)?;
+            let (epoch_now_crc, remaining) = take_crc(remaining)?;
+            let (epoch_next_crc, mut remaining) = take_crc(remaining)?;
+            let mut recently_added = vec![];
+            for _ in 0..MAX_RECENTLY_ADDED {
+                if remaining.is_empty() {
+                    break;
+                }
+                let (station, r) = Station::try_parse(remaining, net_prefix)
+                    .map_err(|_| WireError::InvalidStation)?;
+                let (top, bottom, r) = take_contiguous_range(r)?;
+                remaining = r;
+                recently_added.push(StationSparse {
+                    station,
+                    top,
+                    bottom,
+                });
+            }
+            // A partial or fifth station means the packet was corrupted
+            if !remaining.is_empty() {
+                return Err(WireError::TrailingData);
+            }
+            Command::Status(Status {
+                epoch_now_mod8,
+                epoch_4_ago_crc,
+                epoch_3_ago_crc,
+                epoch_2_ago_crc,
+                epoch_1_ago_crc,
+                epoch_now_crc,
+                epoch_next_crc,
+                recently_added,
+            })
+        }
+        1 => {
+            // Range
+            if info.len() < 3 {
+                return Err(WireError::Truncated);
+            }
+            let final_page = info[1] >> 4;
+            let page = info[1] & 0x0f;
+            let stations_len = info[2] as usize;
+            let mutual_len = stations_len.div_ceil(8);
+            let mut remaining = &info[3..];
+            // Every station takes at least one byte, so don't go looking for
+            // more stations than there could possibly be
+            if stations_len + mutual_len > remaining.len() {
+                return Err(WireError::Truncated);
+            }
+            let mut stations = vec![];
+            for _ in 0..stations_len {
+                let (station, r) = Station::try_parse(remaining, net_prefix)
+                    .map_err(|_| WireError::InvalidStation)?;
+                stations.push(StationHeard {
+                    station,
+                    is_mutual: false,
+                });
+                remaining = r;
+            }
+            if remaining.len() < mutual_len {
+                return Err(WireError::Truncated);
+            }
+            if remaining.len() > mutual_len {
+                return Err(WireError::TrailingData);
+            }
+            for (i, station) in stations.iter_mut().enumerate() {
+                station.is_mutual = remaining[i / 8] & 1 << (7 - (i % 8)) > 0;
+            }
+            Command::Range(Range {
+                final_page,
+                page,
+                stations,
+            })
+        }
+        2 => {
+            // InsertFrame
+            let frame = decode_frame_with_metadata(&info[1..])?;
+            Command::InsertFrame(InsertFrame { frame })
+        }
+        3 => {
+            // RepeatFrame
+            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            let frame = decode_frame_with_metadata(remaining)?;
+            Command::RepeatFrame(FrameDefinition { station, frame })
+        }
+        4 => {
+            // QuickSyncFrameRequest
+            let request = decode_frame_request(&info[1..], net_prefix)?;
+            Command::QuickSyncFrameRequest(request)
+        }
+        5 => {
+            // QuickSyncFrameResponse
+            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            let frame = decode_frame_with_metadata(remaining)?;
+            Command::QuickSyncFrameResponse(FrameDefinition { station, frame })
+        }
+        6 => {
+            // BackfillFrameRequest
+            let request = decode_frame_request(&info[1..], net_prefix)?;
+            Command::BackfillFrameRequest(request)
+        }
+        7 => {
+            // BackfillFrameResponse
+            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            let frame = decode_frame_with_metadata(remaining)?;
+            Command::BackfillFrameResponse(FrameDefinition { station, frame })
+        }
+        8 => {
+            // EpochRequest
+            let epoch_mod8 = info[0] >> 5;
+            let (target, _) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            Command::EpochRequest(EpochRequest { target, epoch_mod8 })
+        }
+        9 => {
+            // QuickEpochResponse
+            let epoch_mod8 = info[0] >> 5;
+            let mut stations = vec![];
+            let mut remaining = &info[1..];
+            while !remaining.is_empty() {
+                let (station, r) = take_station_summary(remaining, net_prefix)?;
+                stations.push(station);
+                remaining = r;
+            }
+            Command::QuickEpochResponse(QuickEpochResponse {
+                epoch_mod8,
+                stations,
+            })
+        }
+        10 => {
+            // EpochResponse
+            let epoch_mod8 = info[0] >> 5;
+            let mut checksums = [0u32; 16];
+            let mut remaining = &info[1..];
+            for c in checksums.iter_mut() {
+                let (crc, r) = take_crc(remaining)?;
+                *c = crc;
+                remaining = r;
+            }
+            if !remaining.is_empty() {
+                return Err(WireError::TrailingData);
+            }
+            Command::EpochResponse(EpochResponse {
+                epoch_mod8,
+                checksums,
+            })
+        }
+        11 => {
+            // BucketContentRequest
+            let epoch_mod8 = info[0] >> 5;
+            let (target, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            if remaining.len() < 1 {
+                return Err(WireError::Truncated);
+            }
+            let bucket = remaining[0] >> 4;
+            let page = remaining[0] & 0x0f;
+            Command::BucketContentRequest(BucketContentRequest {
+                target,
+                epoch_mod8,
+                bucket,
+                page,
+            })
+        }
+        12 => {
+            // BucketContentResponse
+            let epoch_mod8 = info[0] >> 5;
+            if info.len() < 2 {
+                return Err(WireError::Truncated);
+            }
+            let final_page = info[1] >> 4;
+            let page = info[1] & 0x0f;
+            let mut remaining = &info[2..];
+            let mut stations = vec![];
+            while !remaining.is_empty() {
+                let (ss, r) = take_station_summary(remaining, net_prefix)?;
+                stations.push(ss);
+                remaining = r;
+            }
+            Command::BucketContentResponse(BucketContentResponse {
+                epoch_mod8,
+                final_page,
+                page,
+                stations,
+            })
+        }
+        13 => {
+            // StationDataRequest
+            let (target, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            let (station, remaining) =
+                Station::try_parse(remaining, net_prefix).map_err(|_| WireError::InvalidStation)?;
+            if remaining.len() < 2 {
+                return Err(WireError::Truncated);
+            }
+            let epoch_mod8 = remaining[0] >> 5;
+            let from_index = u16::from_be_bytes([remaining[0], remaining[1]]) & 0x1fff;
+            Command::StationDataRequest(StationDataRequest {
+                target,
+                station,
+                epoch_mod8,
+                from_index,
+            })
+        }
+        14 => {
+            // StationDataResponse
+            let (station, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            if remaining.is_empty() {
+                return Err(WireError::Truncated);
+            }
+            let epoch_mod8 = remaining[0] & 0b0000111;
+            let end_of_data = (remaining[0] & 0b10000000) > 0;
+            let mut ranges = vec![];
+            let mut remaining = &remaining[1..];
+            while !remaining.is_empty() {
+                let (top, bottom, r) = take_contiguous_range(remaining)?;
+                ranges.push(ContiguousRange { top, bottom });
+                remaining = r;
+            }
+            Command::StationDataResponse(StationDataResponse {
+                station,
+                epoch_mod8,
+                end_of_data,
+                ranges,
+            })
+        }
+        15 => {
+            // PingRequest
+            let (target, _) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            Command::PingRequest(PingRequest { target })
+        }
+        16 => {
+            // PingResponse
+            let (target, remaining) = Station::try_parse(&info[1..], net_prefix)
+                .map_err(|_| WireError::InvalidStation)?;
+            let diagnostic = std::str::from_utf8(remaining)
+                .map_err(|_| WireError::InvalidUtf8)?
+                .to_string();
+            Command::PingResponse(PingResponse { target, diagnostic })
+        }
+        _ => return Err(WireError::InvalidCommand),
+    };
+    Ok(command)
+}
+
+fn take_crc(buf: &[u8]) -> Result<(u32, &[u8]), WireError> {
+    if buf.len() < 4 {
+        return Err(WireError::Truncated);
+    }
+    let crc = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    Ok((crc, &buf[4..]))
+}
+
+fn command_byte(c: &Command) -> u8 {
+    match c {
+        Command::Status(_) => 0,
+        Command::Range(_) => 1,
+        Command::InsertFrame(_) => 2,
+        Command::RepeatFrame(_) => 3,
+        Command::QuickSyncFrameRequest(_) => 4,
+        Command::QuickSyncFrameResponse(_) => 5,
+        Command::BackfillFrameRequest(_) => 6,
+        Command::BackfillFrameResponse(_) => 7,
+        Command::EpochRequest(_) => 8,
+        Command::QuickEpochResponse(_) => 9,
+        Command::EpochResponse(_) => 10,
+        Command::BucketContentRequest(_) => 11,
+        Command::BucketContentResponse(_) => 12,
+        Command::StationDataRequest(_) => 13,
+        Command::StationDataResponse(_) => 14,
+        Command::PingRequest(_) => 15,
+        Command::PingResponse(_) => 16,
+    }
+}
+
+/// Encode `c` in the compact binary form which channels carry, such as in an AX.25 information field.
+///
+/// Stations are written in compact form relative to `net_prefix`.
+pub fn encode_command(c: &Command, net_prefix: &str) -> Vec<u8> {
+    let mut out = vec![];
+    // Top 3 bits of command byte may be used for an epoch mod8 to save space
+    let mut cmd_byte = command_byte(&c);
+    match c {
+        Command::Status(status) => {
+            cmd_byte |= status.epoch_now_mod8 << 5;
+            out.push(cmd_byte);
+            out.extend(status.epoch_4_ago_crc.to_be_bytes().into_iter());
+            out.extend(status.epoch_3_ago_crc.to_be_bytes().into_iter());
+            out.extend(status.epoch_2_ago_crc.to_be_bytes().into_iter());
+            out.extend(status.epoch_1_ago_crc.to_be_bytes().into_iter());
+            out.extend(status.epoch_now_crc.to_be_bytes().into_iter());
+            out.extend(status.epoch_next_crc.to_be_bytes().into_iter());
+            debug_assert!(
+                status.recently_added.len() <= MAX_RECENTLY_ADDED,
+                "Status lists {} recently_added stations",
+                status.recently_added.len()
+            );
+            for ss in status.recently_added.iter().take(MAX_RECENTLY_ADDED) {
+                out.extend(ss.station.encoded(net_prefix));
+                encode_contiguous_range(ss.top, ss.bottom, &mut out);
+            }
+        }
+        Command::Range(range) => {
+            out.push(cmd_byte);
+            let mut page_byte = range.page;
+            page_byte |= range.final_page << 4;
+            out.push(page_byte);
+            out.push(range.stations.len() as u8);
+            let mut mutual = vec![0; range.stations.len().div_ceil(8)];
+            for (i, sh) in range.stations.iter().enumerate() {
+                out.extend(sh.station.encoded(net_prefix));
+                if sh.is_mutual {
+                    mutual[i / 8] |= 1 << (7 - (i % 8));
+                }
+            }
+            out.append(&mut mutual);
+        }
+        Command::InsertFrame(insert) => {
+            out.push(cmd_byte);
+            encode_frame_with_metadata(&insert.frame, &mut out);
+        }
+        Command::RepeatFrame(repeat) => {
+            out.push(cmd_byte);
+            out.extend(repeat.station.encoded(net_prefix));
+            encode_frame_with_metadata(&repeat.frame, &mut out);
+        }
+        Command::QuickSyncFrameRequest(request) => {
+            out.push(cmd_byte);
+            encode_frame_request(request, net_prefix, &mut out);
+        }
+        Command::QuickSyncFrameResponse(response) => {
+            out.push(cmd_byte);
+            out.extend(response.station.encoded(net_prefix));
+            encode_frame_with_metadata(&response.frame, &mut out);
+        }
+        Command::BackfillFrameRequest(request) => {
+            out.push(cmd_byte);
+            encode_frame_request(request, net_prefix, &mut out);
+        }
+        Command::BackfillFrameResponse(response) => {
+            out.push(cmd_byte);
+            out.extend(response.station.encoded(net_prefix));
+            encode_frame_with_metadata(&response.frame, &mut out);
+        }
+        Command::EpochRequest(epoch) => {
+            cmd_byte |= epoch.epoch_mod8 << 5;
+            out.push(cmd_byte);
+            out.extend(epoch.target.encoded(net_prefix));
+        }
+        Command::QuickEpochResponse(response) => {
+            cmd_byte |= response.epoch_mod8 << 5;
+            out.push(cmd_byte);
+            for ss in &response.stations {
+                encode_station_summary(ss, net_prefix, &mut out);
+            }
+        }
+        Command::EpochResponse(response) => {
+            cmd_byte |= response.epoch_mod8 << 5;
+            out.push(cmd_byte);
+            for crc in response.checksums {
+                out.extend(crc.to_be_bytes().into_iter());
+            }
+        }
+        Command::BucketContentRequest(request) => {
+            cmd_byte |= request.epoch_mod8 << 5;
+            out.push(cmd_byte);
+            out.extend(request.target.encoded(net_prefix));
+            let mut page = request.page;
+            page |= request.bucket << 4;
+            out.push(page);
+        }
+        Command::BucketContentResponse(response) => {
+            cmd_byte |= response.epoch_mod8 << 5;
+            out.push(cmd_byte);
+            let mut page = response.page;
+            page |= response.final_page << 4;
+            out.push(page);
+            for ss in &response.stations {
+                encode_station_summary(ss, net_prefix, &mut out);
+            }
+        }
+        Command::StationDataRequest(request) => {
+            out.push(cmd_byte);
+            out.extend(request.target.encoded(net_prefix));
+            out.extend(request.station.encoded(net_prefix));
+            let index = request.from_index | (request.epoch_mod8 as u16) << 13;
+            out.extend(index.to_be_bytes().into_iter());
+        }
+        Command::StationDataResponse(response) => {
+            out.push(cmd_byte);
+            out.extend(response.station.encoded(net_prefix));
+            let mut epoch = response.epoch_mod8;
+            if response.end_of_data {
+                epoch |= 1 << 7;
+            }
+            out.push(epoch);
+            for r in &response.ranges {
+                encode_contiguous_range(r.top, r.bottom, &mut out);
+            }
+        }
+        Command::PingRequest(request) => {
+            out.push(cmd_byte);
+            out.extend(request.target.encoded(net_prefix));
+        }
+        Command::PingResponse(response) => {
+            out.push(cmd_byte);
+            out.extend(response.target.encoded(net_prefix));
+            out.extend(truncate_utf8(&response.diagnostic, MAX_PAYLOAD_LEN).as_bytes());
+        }
+    }
+    out
+}
+
+/// The longest prefix of `s` which is at most `max` bytes and doesn't split a character.
+fn truncate_utf8(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn encode_frame_with_metadata(f: &FrameWithMetadata, out: &mut Vec<u8>) {
+    let mut index = f.index;
+    index |= (f.epoch_mod8 as u16) << 13;
+    out.extend(index.to_be_bytes().into_iter());
+    let mut application = f.application & 0x0f;
+    if f.start_of_message {
+        application |= 1 << 7;
+    }
+    if f.end_of_message {
+        application |= 1 << 6;
+    }
+    out.push(application);
+    out.extend(f.data.iter());
+}
+
+fn decode_frame_with_metadata(buf: &[u8]) -> Result<FrameWithMetadata, WireError> {
+    if buf.len() < 3 {
+        return Err(WireError::Truncated);
+    }
+    let epoch_mod8 = buf[0] >> 5;
+    let index = u16::from_be_bytes([buf[0], buf[1]]) & 0x1fff;
+    let application = buf[2] & 0x0f;
+    let start_of_message = buf[2] & (1 << 7) > 0;
+    let end_of_message = buf[2] & (1 << 6) > 0;
+    let data = buf[3..].to_vec();
+    Ok(FrameWithMetadata {
+        epoch_mod8,
+        index,
+        start_of_message,
+        end_of_message,
+        application,
+        data,
+    })
+}
+
+fn encode_frame_request(fr: &FrameRequest, net_prefix: &str, out: &mut Vec<u8>) {
+    out.extend(fr.target.encoded(net_prefix));
+    out.extend(fr.inserter.encoded(net_prefix));
+    let mut index = fr.index;
+    index |= (fr.epoch_mod8 as u16) << 13;
+    out.extend(index.to_be_bytes().into_iter());
+}
+
+fn decode_frame_request(buf: &[u8], net_prefix: &str) -> Result<FrameRequest, WireError> {
+    let (target, remaining) =
+        Station::try_parse(&buf, net_prefix).map_err(|_| WireError::InvalidStation)?;
+    let (inserter, remaining) =
+        Station::try_parse(&remaining, net_prefix).map_err(|_| WireError::InvalidStation)?;
+    if remaining.len() < 2 {
+        return Err(WireError::Truncated);
+    }
+    let epoch_mod8 = remaining[0] >> 5;
+    let index = u16::from_be_bytes([remaining[0], remaining[1]]) & 0x1fff;
+    Ok(FrameRequest {
+        target,
+        inserter,
+        epoch_mod8,
+        index,
+    })
+}
+
+fn encode_contiguous_range(top: u16, bottom: u16, out: &mut Vec<u8>) {
+    let mut top = top;
+    if bottom == 0 {
+        top |= 1 << 15;
+        out.extend(top.to_be_bytes().into_iter());
+    } else {
+        out.extend(top.to_be_bytes().into_iter());
+        out.extend(bottom.to_be_bytes().into_iter());
+    }
+}
+
+fn take_contiguous_range(buf: &[u8]) -> Result<(u16, u16, &[u8]), WireError> {
+    if buf.is_empty() {
+        return Err(WireError::Truncated);
+    }
+    if buf[0] & 0b10000000 > 0 {
+        if buf.len() < 2 {
+            return Err(WireError::Truncated);
+        }
+        let top = u16::from_be_bytes([buf[0] & 0b01111111, buf[1]]);
+        Ok((top, 0, &buf[2..]))
+    } else {
+        if buf.len() < 4 {
+            return Err(WireError::Truncated);
+        }
+        let top = u16::from_be_bytes([buf[0], buf[1]]);
+        let bottom = u16::from_be_bytes([buf[2], buf[3]]);
+        Ok((top, bottom, &buf[4..]))
+    }
+}
+
+fn encode_station_summary(ss: &StationSummary, net_prefix: &str, out: &mut Vec<u8>) {
+    out.extend(ss.station.encoded(net_prefix));
+    encode_contiguous_range(ss.top, ss.bottom, out);
+    out.extend(ss.epoch_crc.to_be_bytes().into_iter());
+}
+
+fn take_station_summary<'a, 'b>(
+    buf: &'a [u8],
+    net_prefix: &'b str,
+) -> Result<(StationSummary, &'a [u8]), WireError> {
+    let (station, remaining) =
+        Station::try_parse(buf, net_prefix).map_err(|_| WireError::InvalidStation)?;
+    let (top, bottom, remaining) = take_contiguous_range(remaining)?;
+    let (epoch_crc, remaining) = take_crc(remaining)?;
+    Ok((
+        StationSummary {
+            station,
+            top,
+            bottom,
+            epoch_crc,
+        },
+        remaining,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::test_vectors::test_vectors;
+
+    #[test]
+    fn every_command_round_trips() {
+        for v in test_vectors() {
+            let t = v.transmission;
+            let info = encode_command(&t.command, t.network.id());
+            assert_eq!(
+                decode_command(&info, t.network.id()).unwrap(),
+                t.command,
+                "{}",
+                v.label
+            );
+            // Foreign stations are written in full, so any prefix decodes them
+            assert_eq!(
+                decode_command(&encode_command(&t.command, ""), "").unwrap(),
+                t.command,
+                "{}",
+                v.label
+            );
+            assert_eq!(
+                decode_transmission(&encode_transmission(&t)).unwrap(),
+                t,
+                "{}",
+                v.label
+            );
+        }
+    }
+
+    #[test]
+    fn vectors_match_encoding() {
+        for v in test_vectors() {
+            assert_eq!(encode_transmission(&v.transmission), v.wire, "{}", v.label);
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        for v in test_vectors() {
+            let hex = encode_to_hex(&v.transmission);
+            assert_eq!(
+                decode_from_hex(&hex).unwrap(),
+                v.transmission,
+                "{}",
+                v.label
+            );
+            let squashed = hex.replace(' ', "").to_uppercase();
+            assert_eq!(
+                decode_from_hex(&squashed).unwrap(),
+                v.transmission,
+                "{}",
+                v.label
+            );
+        }
+        let ping_response = test_vectors()
+            .into_iter()
+            .find(|v| v.label == "ping response")
+            .unwrap();
+        assert_eq!(
+            encode_to_hex(&ping_response.transmission),
+            "01 03 56 4b 37 54 a8 57 4e 90 10 35 32 af 37 33 20 64 65 20 56 4b 37 58 54"
+        );
+        assert!(matches!(
+            decode_from_hex("01 0"),
+            Err(WireError::InvalidHex)
+        ));
+        assert!(matches!(
+            decode_from_hex("01 zz"),
+            Err(WireError::InvalidHex)
+        ));
+    }
+
+    fn status_with(recently_added: Vec<StationSparse>) -> Command {
+        Command::Status(Status {
+            epoch_now_mod8: 2,
+            epoch_4_ago_crc: 4,
+            epoch_3_ago_crc: 3,
+            epoch_2_ago_crc: 2,
+            epoch_1_ago_crc: 1,
+            epoch_now_crc: 0,
+            epoch_next_crc: 0,
+            recently_added,
+        })
+    }
+
+    #[test]
+    fn status_recently_added_counts() {
+        let sparse: Vec<StationSparse> = ["VK7NTK", "VK3ABC", "VK7XT", "W1AW", "VK2DEF"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, callsign)| StationSparse {
+                station: Station::new(callsign.to_owned(), 1).unwrap(),
+                top: 20 + i as u16,
+                bottom: i as u16,
+            })
+            .collect();
+        for count in [0, 2, 4] {
+            let status = status_with(sparse[..count].to_vec());
+            let info = encode_command(&status, "VK7");
+            assert_eq!(decode_command(&info, "VK7").unwrap(), status, "{}", count);
+        }
+
+        // A fifth station is never sent, so one which arrives, whole or
+        // truncated, is corruption
+        let four = encode_command(&status_with(sparse[..4].to_vec()), "VK7");
+        let fifth = encode_command(&status_with(vec![sparse[4].clone()]), "VK7");
+        let extra = &fifth[25..];
+        for len in 1..=extra.len() {
+            let mut info = four.clone();
+            info.extend(&extra[..len]);
+            assert!(
+                matches!(decode_command(&info, "VK7"), Err(WireError::TrailingData)),
+                "{}",
+                len
+            );
+        }
+
+        // As is a truncated fourth
+        assert!(decode_command(&four[..four.len() - 1], "VK7").is_err());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Status lists 5 recently_added stations")]
+    fn status_fifth_station_not_encoded() {
+        let sparse = StationSparse {
+            station: Station::new("VK7NTK".to_owned(), 1).unwrap(),
+            top: 20,
+            bottom: 0,
+        };
+        encode_command(&status_with(vec![sparse; 5]), "VK7");
+    }
+
+    #[test]
+    fn transmission_errors() {
+        let t = &test_vectors()[0].transmission;
+        let bytes = encode_transmission(t);
+        assert!(matches!(
+            decode_transmission(&[]),
+            Err(WireError::Truncated)
+        ));
+        let mut bad_version = bytes.clone();
+        bad_version[0] = 7;
+        assert!(matches!(
+            decode_transmission(&bad_version),
+            Err(WireError::InvalidChatterooVersion(7))
+        ));
+        assert!(matches!(
+            decode_transmission(&bytes[..4]),
+            Err(WireError::Truncated)
+        ));
+        assert!(matches!(
+            decode_transmission(&bytes[..bytes.len() - 1]),
+            Err(WireError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn status_crc_fields() {
+        let status = Status {
+            epoch_now_mod8: 6,
+            epoch_4_ago_crc: 0x44444444,
+            epoch_3_ago_crc: 0x33333333,
+            epoch_2_ago_crc: 0x22222222,
+            epoch_1_ago_crc: 0x11111111,
+            epoch_now_crc: 0x00000000,
+            epoch_next_crc: 0xffffffff,
+            recently_added: vec![],
+        };
+        let info = encode_command(&Command::Status(status), "VK7");
+        // Pin the wire layout so encode and decode can't drift together
+        assert_eq!(info[0], 6 << 5);
+        assert_eq!(&info[1..5], &[0x44; 4]);
+        assert_eq!(&info[5..9], &[0x33; 4]);
+        assert_eq!(&info[9..13], &[0x22; 4]);
+        assert_eq!(&info[13..17], &[0x11; 4]);
+        assert_eq!(&info[17..21], &[0x00; 4]);
+        assert_eq!(&info[21..25], &[0xff; 4]);
+        assert_eq!(info.len(), 25);
+
+        match decode_command(&info, "VK7").unwrap() {
+            Command::Status(decoded) => {
+                assert_eq!(decoded.epoch_now_mod8, 6);
+                assert_eq!(decoded.epoch_4_ago_crc, 0x44444444);
+                assert_eq!(decoded.epoch_3_ago_crc, 0x33333333);
+                assert_eq!(decoded.epoch_2_ago_crc, 0x22222222);
+                assert_eq!(decoded.epoch_1_ago_crc, 0x11111111);
+                assert_eq!(decoded.epoch_now_crc, 0x00000000);
+                assert_eq!(decoded.epoch_next_crc, 0xffffffff);
+                assert!(decoded.recently_added.is_empty());
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn frame_definition_round_trip() {
+        let frame = FrameWithMetadata {
+            epoch_mod8: 7,
+            index: 8191,
+            start_of_message: true,
+            end_of_message: true,
+            application: 2,
+            data: b"73 de VK7XT".to_vec(),
+        };
+        for callsign in ["VK7XT", "VK3ABC"] {
+            let definition = FrameDefinition {
+                station: Station::new(callsign.to_owned(), 4).unwrap(),
+                frame: frame.clone(),
+            };
+            for command in [
+                Command::RepeatFrame(definition.clone()),
+                Command::QuickSyncFrameResponse(definition.clone()),
+                Command::BackfillFrameResponse(definition),
+            ] {
+                let info = encode_command(&command, "VK7");
+                assert_eq!(decode_command(&info, "VK7").unwrap(), command);
+            }
+        }
+    }
+
+    fn range_of(count: usize) -> Command {
+        let stations = (0..count)
+            .map(|i| StationHeard {
+                station: Station::new(format!("VK7A{}", (b'A' + i as u8) as char), 0).unwrap(),
+                is_mutual: i % 3 == 0,
+            })
+            .collect();
+        Command::Range(Range {
+            final_page: 0,
+            page: 0,
+            stations,
+        })
+    }
+
+    #[test]
+    fn range_round_trip() {
+        for count in [0, 1, 7, 8, 9, 16] {
+            let range = range_of(count);
+            let info = encode_command(&range, "VK7");
+            assert_eq!(decode_command(&info, "VK7").unwrap(), range, "{}", count);
+        }
+    }
+
+    #[test]
+    fn range_station_count_checked() {
+        let info = encode_command(&range_of(3), "VK7");
+
+        // Far more stations than could fit in the remaining bytes
+        let mut inflated = info.clone();
+        inflated[2] = 255;
+        assert!(matches!(
+            decode_command(&inflated, "VK7"),
+            Err(WireError::Truncated)
+        ));
+
+        // Claiming fewer stations leaves station data where the bitmap should be
+        let mut deflated = info.clone();
+        deflated[2] = 2;
+        assert!(matches!(
+            decode_command(&deflated, "VK7"),
+            Err(WireError::TrailingData)
+        ));
+
+        let mut extended = info;
+        extended.push(0);
+        assert!(matches!(
+            decode_command(&extended, "VK7"),
+            Err(WireError::TrailingData)
+        ));
+    }
+
+    #[test]
+    fn epoch_response_length_checked() {
+        let response = Command::EpochResponse(EpochResponse {
+            epoch_mod8: 5,
+            checksums: [0x12345678; 16],
+        });
+        let info = encode_command(&response, "VK7");
+        assert_eq!(info.len() - 1, 64);
+        assert_eq!(decode_command(&info, "VK7").unwrap(), response);
+
+        let mut extended = info.clone();
+        extended.extend([0; 4]);
+        assert!(matches!(
+            decode_command(&extended, "VK7"),
+            Err(WireError::TrailingData)
+        ));
+        assert!(matches!(
+            decode_command(&info[..info.len() - 1], "VK7"),
+            Err(WireError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn station_data_response_ranges() {
+        let response = |ranges| {
+            Command::StationDataResponse(StationDataResponse {
+                station: Station::new("VK7XT".to_owned(), 5).unwrap(),
+                epoch_mod8: 3,
+                end_of_data: true,
+                ranges,
+            })
+        };
+
+        // Header only: a valid response with no ranges
+        let empty = response(vec![]);
+        let header = encode_command(&empty, "VK7");
+        assert_eq!(decode_command(&header, "VK7").unwrap(), empty);
+
+        for range in [
+            ContiguousRange { top: 20, bottom: 0 },
+            ContiguousRange { top: 20, bottom: 5 },
+        ] {
+            let one = response(vec![range]);
+            let info = encode_command(&one, "VK7");
+            assert_eq!(decode_command(&info, "VK7").unwrap(), one);
+
+            // Every partial range following the header is rejected
+            for len in header.len() + 1..info.len() {
+                assert!(
+                    matches!(
+                        decode_command(&info[..len], "VK7"),
+                        Err(WireError::Truncated)
+                    ),
+                    "{}",
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ping_diagnostic_truncated() {
+        let target = Station::new("VK7XT".to_owned(), 5).unwrap();
+        // 100 bytes, with the 80th in the middle of a two-byte character
+        let diagnostic = format!("{}é{}", "a".repeat(79), "b".repeat(19));
+        let response = Command::PingResponse(PingResponse {
+            target: target.clone(),
+            diagnostic,
+        });
+        let info = encode_command(&response, "VK7");
+        assert_eq!(
+            decode_command(&info, "VK7").unwrap(),
+            Command::PingResponse(PingResponse {
+                target,
+                diagnostic: "a".repeat(79),
+            })
+        );
+    }
+}
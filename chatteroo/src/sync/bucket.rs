@@ -0,0 +1,100 @@
+//! Collection of a bucket's station summaries when they span several responses.
+
+use std::collections::BTreeMap;
+
+use crate::protocol::global::{BucketContentResponse, StationSummary};
+
+/// Gathers the pages of `BucketContentResponse`s for one bucket until we have them all.
+///
+/// Pages may arrive in any order. If a page reports a different `final_page`
+/// from the ones before it, the bucket's contents must have changed between
+/// responses, so the earlier pages are discarded and collection starts again.
+#[derive(Default)]
+pub struct BucketContentReassembler {
+    final_page: Option<u8>,
+    pages: BTreeMap<u8, Vec<StationSummary>>,
+}
+
+impl BucketContentReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a page, returning every station in the bucket if this completes the set.
+    ///
+    /// Pages beyond the final one carry no stations and are ignored.
+    pub fn add(&mut self, response: &BucketContentResponse) -> Option<Vec<StationSummary>> {
+        if self.final_page != Some(response.final_page) {
+            self.pages.clear();
+            self.final_page = Some(response.final_page);
+        }
+        if response.page <= response.final_page {
+            self.pages.insert(response.page, response.stations.clone());
+        }
+        if !self.is_complete() {
+            return None;
+        }
+        Some(self.pages.values().flatten().cloned().collect())
+    }
+
+    /// Pages up to the final one which haven't been added yet, in ascending order.
+    ///
+    /// Empty until the first page is added, since until then we don't know how many there are.
+    pub fn missing_pages(&self) -> Vec<u8> {
+        match self.final_page {
+            Some(last) => (0..=last).filter(|p| !self.pages.contains_key(p)).collect(),
+            None => vec![],
+        }
+    }
+
+    /// Whether every page from 0 to the final one has been added.
+    pub fn is_complete(&self) -> bool {
+        self.final_page.is_some() && self.missing_pages().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::station::Station;
+
+    fn page(page: u8, callsign: &str) -> BucketContentResponse {
+        BucketContentResponse {
+            epoch_mod8: 3,
+            final_page: 2,
+            page,
+            stations: vec![StationSummary {
+                station: Station::new(callsign.to_owned(), 0).unwrap(),
+                top: 10,
+                bottom: 0,
+                epoch_crc: 0x12345678,
+            }],
+        }
+    }
+
+    #[test]
+    fn incomplete_until_missing_page_arrives() {
+        let mut reassembler = BucketContentReassembler::new();
+        assert!(!reassembler.is_complete());
+
+        assert!(reassembler.add(&page(0, "VK7AA")).is_none());
+        assert!(reassembler.add(&page(2, "VK7CC")).is_none());
+        assert_eq!(reassembler.missing_pages(), vec![1]);
+        assert!(!reassembler.is_complete());
+
+        let stations = reassembler.add(&page(1, "VK7BB")).unwrap();
+        let callsigns: Vec<&str> = stations.iter().map(|s| s.station.callsign()).collect();
+        assert_eq!(callsigns, vec!["VK7AA", "VK7BB", "VK7CC"]);
+        assert!(reassembler.is_complete());
+    }
+
+    #[test]
+    fn changed_final_page_starts_again() {
+        let mut reassembler = BucketContentReassembler::new();
+        reassembler.add(&page(0, "VK7AA"));
+        reassembler.add(&page(1, "VK7BB"));
+        let mut shrunk = page(0, "VK7AA");
+        shrunk.final_page = 0;
+        assert_eq!(reassembler.add(&shrunk).unwrap(), shrunk.stations);
+    }
+}
@@ -0,0 +1,74 @@
+//! Small least-recently-used set of frames we have already dealt with.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::protocol::station::Station;
+
+/// Identity of a data frame across the whole network: inserter, absolute epoch and index.
+pub(crate) type FrameKey = (Station, u32, u16);
+
+/// Bounded set of `FrameKey`s, evicting the least recently used when full.
+///
+/// Capacities are expected to be modest (hundreds to low thousands) so the
+/// linear scan on a hit is cheaper than it sounds compared to a database query.
+pub(crate) struct RelayCache {
+    capacity: usize,
+    order: VecDeque<FrameKey>,
+    members: HashSet<FrameKey>,
+}
+
+impl RelayCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            members: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Returns whether `key` is present, marking it as recently used if so.
+    pub(crate) fn touch(&mut self, key: &FrameKey) -> bool {
+        if !self.members.contains(key) {
+            return false;
+        }
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            if let Some(k) = self.order.remove(pos) {
+                self.order.push_back(k);
+            }
+        }
+        true
+    }
+
+    /// Add `key` as the most recently used entry, evicting the oldest if over capacity.
+    pub(crate) fn insert(&mut self, key: FrameKey) {
+        if self.capacity == 0 || self.touch(&key) {
+            return;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.members.remove(&evicted);
+            }
+        }
+        self.members.insert(key.clone());
+        self.order.push_back(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let s = Station::new("VK7XT".to_owned(), 5).unwrap();
+        let mut cache = RelayCache::new(2);
+        cache.insert((s.clone(), 1, 0));
+        cache.insert((s.clone(), 1, 1));
+        // Using 0 makes 1 the oldest
+        assert!(cache.touch(&(s.clone(), 1, 0)));
+        cache.insert((s.clone(), 1, 2));
+        assert!(cache.touch(&(s.clone(), 1, 0)));
+        assert!(!cache.touch(&(s.clone(), 1, 1)));
+        assert!(cache.touch(&(s, 1, 2)));
+    }
+}
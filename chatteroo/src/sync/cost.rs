@@ -0,0 +1,170 @@
+//! Estimating the channel time needed to advertise what a station holds.
+
+use std::collections::HashSet;
+
+use crate::database::{query, Database, DbError};
+use crate::protocol::{
+    epoch::Epoch,
+    global::{Command, StationHeard},
+    network::Network,
+    wire::encode_command,
+};
+
+use super::{
+    epoch_response,
+    range::{RangeTracker, DEFAULT_RANGE_REFRESH},
+};
+
+/// Bytes of command encoding needed to advertise everything a station holds.
+///
+/// See `advertisement_cost`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdvertisementCost {
+    /// Size of our `Status`, without any recently added stations.
+    pub status_bytes: usize,
+
+    /// Number of `Range` pages needed to list every station with frames in the window.
+    pub range_pages: usize,
+
+    /// Total size of those `Range` pages.
+    pub range_bytes: usize,
+
+    /// Number of epochs in the window which we hold frames for.
+    pub epoch_responses: usize,
+
+    /// Total size of the quick or bucketed epoch responses we'd send for those epochs.
+    pub epoch_response_bytes: usize,
+}
+
+impl AdvertisementCost {
+    /// Bytes needed for all of the above together.
+    pub fn total_bytes(&self) -> usize {
+        self.status_bytes + self.range_bytes + self.epoch_response_bytes
+    }
+}
+
+/// Estimate the channel time needed to advertise what `db` holds at `now`, for capacity planning.
+///
+/// This builds the commands a `SyncEngine` on `network` would send: a
+/// `Status`, a `Range` listing every station with frames in the same epochs
+/// as the `Status` (as if we heard them all), and the response to an
+/// `EpochRequest` for each of those epochs which isn't empty. Their command
+/// encodings are totalled. AX.25 framing and packet CRCs aren't included.
+pub fn advertisement_cost(
+    db: &dyn Database,
+    now: &Epoch,
+    network: &Network,
+) -> Result<AdvertisementCost, DbError> {
+    let net_prefix = network.id();
+    let size = |command: Command| encode_command(&command, net_prefix).len();
+
+    let mut inserters = HashSet::new();
+    let mut epoch_responses = 0;
+    let mut epoch_response_bytes = 0;
+    for abs in now.index_abs().saturating_sub(4)..=now.index_abs() + 1 {
+        let epoch = Epoch::from_abs(abs);
+        let stations = db.station_summaries_for_epoch(&epoch)?;
+        if stations.is_empty() {
+            continue;
+        }
+        inserters.extend(stations.iter().map(|s| s.station.clone()));
+        let checksums = db.bucket_checksums(&epoch)?;
+        epoch_responses += 1;
+        epoch_response_bytes += size(epoch_response(
+            epoch.index_mod8(),
+            stations,
+            checksums,
+            net_prefix,
+        ));
+    }
+
+    let heard: Vec<StationHeard> = inserters
+        .into_iter()
+        .map(|station| StationHeard {
+            station,
+            is_mutual: false,
+        })
+        .collect();
+    let pages = RangeTracker::new(net_prefix.to_owned(), DEFAULT_RANGE_REFRESH)
+        .pages_to_send(&heard, now.start_time());
+    let status = query::build_status(db, now, vec![])?;
+
+    Ok(AdvertisementCost {
+        status_bytes: size(Command::Status(status)),
+        range_pages: pages.len(),
+        range_bytes: pages.into_iter().map(|r| size(Command::Range(r))).sum(),
+        epoch_responses,
+        epoch_response_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing::{memory_db, populate, Coverage, PopulateSpec};
+
+    #[test]
+    fn advertisement_cost_matches_commands() {
+        let now = Epoch::from_abs(300);
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let db = memory_db();
+        let empty = advertisement_cost(&db, &now, &network).unwrap();
+        assert_eq!(empty.status_bytes, 25);
+        assert_eq!((empty.range_pages, empty.range_bytes), (1, 3));
+        assert_eq!((empty.epoch_responses, empty.epoch_response_bytes), (0, 0));
+
+        // Few enough stations in epoch 299 to answer with a QuickEpochResponse
+        let small = PopulateSpec {
+            epoch: Epoch::from_abs(299),
+            stations: 3,
+            frames_per_station: 5,
+            coverage: Coverage::Full,
+        };
+        populate(&db, &small).unwrap();
+        let quick_len: usize = db
+            .station_summaries_for_epoch(&small.epoch)
+            .unwrap()
+            .iter()
+            .map(|s| s.encoded_len("VK7"))
+            .sum();
+        // Too many in epoch 300, which needs an EpochResponse and several Range pages
+        let large = PopulateSpec {
+            epoch: now,
+            stations: 40,
+            frames_per_station: 5,
+            coverage: Coverage::Gapped,
+        };
+        populate(&db, &large).unwrap();
+
+        let cost = advertisement_cost(&db, &now, &network).unwrap();
+        assert_eq!(cost.epoch_responses, 2);
+        assert_eq!(cost.epoch_response_bytes, (1 + quick_len) + 65);
+        // 40 stations of 3 bytes each, paged as the engine does with a 3 byte
+        // header and a byte of budget per station for the mutual bitmap
+        assert_eq!(cost.range_pages, 2);
+        assert_eq!(cost.range_bytes, 2 * (3 + 20 * 3 + 3));
+        assert_eq!(
+            cost.total_bytes(),
+            cost.status_bytes + cost.range_bytes + cost.epoch_response_bytes
+        );
+    }
+
+    #[test]
+    fn range_pages_match_engine() {
+        let now = Epoch::from_abs(300);
+        let network = Network::new("VK7".to_owned()).unwrap();
+        let db = memory_db();
+        populate(
+            &db,
+            &PopulateSpec {
+                epoch: now,
+                stations: 22,
+                frames_per_station: 1,
+                coverage: Coverage::Full,
+            },
+        )
+        .unwrap();
+        let cost = advertisement_cost(&db, &now, &network).unwrap();
+        assert_eq!(cost.range_pages, 2);
+    }
+}
@@ -0,0 +1,234 @@
+//! How reliably each neighbour hears us, judged from the history of its `Range`.
+
+use std::collections::{HashMap, VecDeque};
+
+use time::{Duration, OffsetDateTime};
+
+use crate::protocol::{global::Range, station::Station};
+
+/// Default number of observations of each neighbour kept by a `LinkQualityTracker`.
+pub const DEFAULT_LINK_WINDOW: usize = 10;
+
+/// How long a neighbour can go unheard before `LinkQualityTracker::prune` forgets it.
+///
+/// This spans several of the neighbour's `Range` refreshes.
+pub const LINK_EXPIRY: Duration = Duration::hours(2);
+
+/// Stability below which a link is considered marginal.
+pub const MARGINAL_STABILITY: f32 = 0.7;
+
+/// Recent history of whether each neighbour could hear us.
+///
+/// Each complete `Range` heard from a neighbour, with every page from 0 to
+/// `final_page`, is one observation of whether the link is mutual. A link
+/// which is working in both directions, or not at all, gives the same answer
+/// every time. One that keeps flipping between the two is marginal, even if it
+/// happens to be mutual right now.
+pub struct LinkQualityTracker {
+    window: usize,
+    links: HashMap<Station, Link>,
+}
+
+/// What we know about the link with one neighbour.
+struct Link {
+    observations: VecDeque<bool>,
+    pages: Option<RangePages>,
+    last_heard: OffsetDateTime,
+}
+
+/// Pages heard so far of a neighbour's current `Range`.
+struct RangePages {
+    final_page: u8,
+    /// Bit `n` is set once page `n` has been heard.
+    heard: u16,
+    /// Whether any page heard so far listed us.
+    lists_us: bool,
+}
+
+impl Default for LinkQualityTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_LINK_WINDOW)
+    }
+}
+
+impl LinkQualityTracker {
+    /// Track links keeping only the latest `window` observations of each neighbour.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(2),
+            links: HashMap::new(),
+        }
+    }
+
+    /// Record whether `peer`, heard at `now`, could hear us in one of its `Range` heartbeats.
+    pub fn record(&mut self, peer: &Station, mutual: bool, now: OffsetDateTime) {
+        let window = self.window;
+        let link = self.link(peer, now);
+        if link.observations.len() == window {
+            link.observations.pop_front();
+        }
+        link.observations.push_back(mutual);
+    }
+
+    /// Record a `Range` page heard from `peer` at `now`, as seen from station `me`.
+    ///
+    /// Once every page up to `final_page` has been heard, that is one
+    /// observation: mutual if any of the pages listed us. Hearing a page
+    /// again, or a page from a list of a different length, means the peer has
+    /// started a new list, so any pages missed from the old one are given up
+    /// on and it records nothing.
+    pub fn record_range(
+        &mut self,
+        peer: &Station,
+        range: &Range,
+        me: &Station,
+        now: OffsetDateTime,
+    ) {
+        let Some(bit) = 1u16
+            .checked_shl(range.page.into())
+            .filter(|_| range.page <= range.final_page)
+        else {
+            return;
+        };
+        let lists_us = range.stations.iter().any(|s| s.station == *me);
+        let link = self.link(peer, now);
+        let pages = match &mut link.pages {
+            Some(p) if p.final_page == range.final_page && p.heard & bit == 0 => p,
+            pages => pages.insert(RangePages {
+                final_page: range.final_page,
+                heard: 0,
+                lists_us: false,
+            }),
+        };
+        pages.heard |= bit;
+        pages.lists_us |= lists_us;
+        if pages.heard.count_ones() == u32::from(range.final_page) + 1 {
+            let mutual = pages.lists_us;
+            link.pages = None;
+            self.record(peer, mutual, now);
+        }
+    }
+
+    /// Forget neighbours which haven't been heard since `LINK_EXPIRY` before `now`.
+    pub fn prune(&mut self, now: OffsetDateTime) {
+        self.links
+            .retain(|_, link| now - link.last_heard < LINK_EXPIRY);
+    }
+
+    /// The link with `peer`, noting that it was heard at `now`.
+    fn link(&mut self, peer: &Station, now: OffsetDateTime) -> &mut Link {
+        let link = self.links.entry(peer.clone()).or_insert_with(|| Link {
+            observations: VecDeque::new(),
+            pages: None,
+            last_heard: now,
+        });
+        link.last_heard = link.last_heard.max(now);
+        link
+    }
+
+    /// How steady the link with `peer` has been, from 0 to 1.
+    ///
+    /// This is the fraction of consecutive observations in the window which
+    /// agreed with each other: 1 if the link never changed and 0 if it flipped
+    /// every time. Returns `None` until there are at least two observations.
+    pub fn stability(&self, peer: &Station) -> Option<f32> {
+        let history = &self.links.get(peer)?.observations;
+        if history.len() < 2 {
+            return None;
+        }
+        let pairs = history.len() - 1;
+        let flips = history
+            .iter()
+            .zip(history.iter().skip(1))
+            .filter(|(a, b)| a != b)
+            .count();
+        Some(1.0 - flips as f32 / pairs as f32)
+    }
+
+    /// Whether the link with `peer` has been flapping, per `MARGINAL_STABILITY`.
+    pub fn is_marginal(&self, peer: &Station) -> bool {
+        self.stability(peer).is_some_and(|s| s < MARGINAL_STABILITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::global::StationHeard;
+
+    fn station(callsign: &str) -> Station {
+        Station::new(callsign.to_owned(), 0).unwrap()
+    }
+
+    #[test]
+    fn flapping_link_unstable() {
+        let mut tracker = LinkQualityTracker::default();
+        let flapping = station("VK7NTK");
+        let solid = station("VK3ABC");
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(tracker.stability(&flapping), None);
+        for i in 0..20 {
+            tracker.record(&flapping, i % 2 == 0, now);
+            tracker.record(&solid, true, now);
+        }
+        assert_eq!(tracker.stability(&flapping), Some(0.0));
+        assert!(tracker.is_marginal(&flapping));
+        assert_eq!(tracker.stability(&solid), Some(1.0));
+        assert!(!tracker.is_marginal(&solid));
+
+        // Old flapping falls out of the window once the link settles
+        for _ in 0..DEFAULT_LINK_WINDOW {
+            tracker.record(&flapping, true, now);
+        }
+        assert_eq!(tracker.stability(&flapping), Some(1.0));
+    }
+
+    #[test]
+    fn multi_page_range_observed_once_complete() {
+        let mut tracker = LinkQualityTracker::default();
+        let me = station("VK7XT");
+        let peer = station("VK7NTK");
+        let now = OffsetDateTime::now_utc();
+        let page = |page: u8, stations: &[&Station]| Range {
+            final_page: 1,
+            page,
+            stations: stations
+                .iter()
+                .map(|s| StationHeard {
+                    station: (*s).clone(),
+                    is_mutual: true,
+                })
+                .collect(),
+        };
+        let other = station("VK3ABC");
+
+        // Listed on the second page only
+        tracker.record_range(&peer, &page(0, &[&other]), &me, now);
+        tracker.record_range(&peer, &page(1, &[&me]), &me, now);
+        // Then left out of both pages
+        tracker.record_range(&peer, &page(0, &[&other]), &me, now);
+        tracker.record_range(&peer, &page(1, &[&other]), &me, now);
+        assert_eq!(tracker.stability(&peer), Some(0.0));
+        // A list whose second page we missed says nothing
+        tracker.record_range(&peer, &page(0, &[&me]), &me, now);
+        tracker.record_range(&peer, &page(0, &[&other]), &me, now);
+        assert_eq!(tracker.stability(&peer), Some(0.0));
+        tracker.record_range(&peer, &page(1, &[&other]), &me, now);
+        assert_eq!(tracker.stability(&peer), Some(0.5));
+    }
+
+    #[test]
+    fn unheard_links_pruned() {
+        let mut tracker = LinkQualityTracker::default();
+        let old = station("VK7NTK");
+        let recent = station("VK3ABC");
+        let start = OffsetDateTime::now_utc();
+        for _ in 0..2 {
+            tracker.record(&old, true, start);
+            tracker.record(&recent, true, start + LINK_EXPIRY);
+        }
+        tracker.prune(start + LINK_EXPIRY);
+        assert_eq!(tracker.stability(&old), None);
+        assert_eq!(tracker.stability(&recent), Some(1.0));
+    }
+}
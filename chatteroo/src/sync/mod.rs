@@ -0,0 +1,2140 @@
+//! Protocol logic which keeps the local database in sync with the network.
+//!
+//! The `SyncEngine` is fed every `Transmission` heard on the channel and
+//! decides what, if anything, should be transmitted in response. It is
+//! agnostic to the type of channel in use and does no I/O of its own apart
+//! from reading and writing the database it is given.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use crc32fast::Hasher;
+use time::{Duration, OffsetDateTime};
+
+use crate::database::{
+    model::{SavedExpectation, SavedRequest, SyncState},
+    query, Database, DbError,
+};
+use crate::error::Error;
+use crate::protocol::{
+    epoch::Epoch,
+    global::{
+        BucketContentRequest, BucketContentResponse, ChatterooVersion, Command, EpochRequest,
+        EpochResponse, FrameDefinition, FrameRequest, FrameResponseKind, FrameWithMetadata,
+        QuickEpochResponse, StationDataRequest, StationDataResponse, StationSparse, StationSummary,
+        Status, Transmission, MAX_PAYLOAD_LEN, MAX_RECENTLY_ADDED,
+    },
+    network::Network,
+    station::Station,
+    wire::{decode_command, encode_command},
+};
+
+use cache::RelayCache;
+use link::LinkQualityTracker;
+use pacing::Pacer;
+use peer::{ExpectedResponse, PeerSyncTracker};
+use requests::{InFlight, InFlightRequests, RequestKey};
+
+pub mod bucket;
+mod cache;
+pub mod cost;
+pub mod link;
+mod pacing;
+pub mod peer;
+pub mod range;
+mod requests;
+
+/// Default number of recently relayed frames remembered without asking the database.
+pub const DEFAULT_RELAY_CACHE_CAPACITY: usize = 1024;
+
+/// Default time to wait for a response before an identical request may be sent again.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::seconds(60);
+
+/// Default number of times an unanswered request is sent again before giving up.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Default minimum time between the starts of two of our transmissions.
+pub const DEFAULT_RESPONSE_GAP: Duration = Duration::seconds(2);
+
+/// Default upper limit of the random time added to each `DEFAULT_RESPONSE_GAP`.
+pub const DEFAULT_RESPONSE_JITTER: Duration = Duration::seconds(1);
+
+/// Maximum number of pages in a paginated response, since page numbers are 0-15.
+const MAX_PAGES: usize = 16;
+
+/// Maximum number of frames to request via quick sync in response to a single `Status`.
+const MAX_QUICK_SYNC_REQUESTS: usize = 4;
+
+/// Maximum number of `StationDataResponse`s sent in answer to a single request.
+///
+/// If there are more ranges than this the requester will not see `end_of_data`
+/// and can ask again starting after the last index it was told about.
+const MAX_STATION_DATA_RESPONSES: usize = 4;
+
+/// Identity and tunable parameters for a `SyncEngine`.
+#[derive(Clone, Debug)]
+pub struct SyncConfig {
+    /// Our own station identifier, used as the sender of all transmissions.
+    pub station: Station,
+
+    /// Network we are participating in. Transmissions for other networks are ignored.
+    pub network: Network,
+
+    /// Protocol version we are speaking. Transmissions for other versions are ignored.
+    pub version: ChatterooVersion,
+
+    /// How many recently relayed frames to remember in memory.
+    ///
+    /// Frames found in this cache are known to be handled already and don't
+    /// require a database lookup. Zero disables the cache.
+    pub relay_cache_capacity: usize,
+
+    /// How long to wait for a response to a request before it may be sent again.
+    pub request_timeout: Duration,
+
+    /// How many times an unanswered request is sent again before giving up on it.
+    pub max_retries: u32,
+
+    /// Minimum time between the starts of two of our transmissions.
+    ///
+    /// Transmissions produced faster than this are queued and released by
+    /// later calls to `handle` or `tick`. Zero disables pacing.
+    pub response_gap: Duration,
+
+    /// Upper limit of a random time added to each `response_gap`.
+    pub response_jitter: Duration,
+
+    /// Applications whose frames we relay and present, or `None` for all of them.
+    ///
+    /// Frames for other applications are still stored, since leaving them out
+    /// would make our epoch checksums disagree with every neighbour that holds
+    /// them and sync would keep asking for them. They are not relayed, and
+    /// the application layer can hide them by checking `accepts`.
+    pub accepted_applications: Option<HashSet<u8>>,
+}
+
+impl SyncConfig {
+    /// Configuration for `station` on `network` with default tuning.
+    pub fn new(station: Station, network: Network, version: ChatterooVersion) -> Self {
+        Self {
+            station,
+            network,
+            version,
+            relay_cache_capacity: DEFAULT_RELAY_CACHE_CAPACITY,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            response_gap: DEFAULT_RESPONSE_GAP,
+            response_jitter: DEFAULT_RESPONSE_JITTER,
+            accepted_applications: None,
+        }
+    }
+}
+
+/// How much of the data our neighbours have told us about in one epoch we hold.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochProgress {
+    /// Epoch being described.
+    pub epoch: Epoch,
+
+    /// Number of frames which neighbours have advertised, up to the highest index for each station.
+    pub expected: u32,
+
+    /// Number of those frames which we hold.
+    pub held: u32,
+}
+
+impl EpochProgress {
+    /// Proportion of expected frames held, from 0.0 to 1.0.
+    ///
+    /// An epoch where nothing is expected is considered complete.
+    pub fn fraction(&self) -> f32 {
+        if self.expected == 0 {
+            return 1.0;
+        }
+        self.held as f32 / self.expected as f32
+    }
+}
+
+/// State machine which processes received transmissions and produces replies.
+pub struct SyncEngine {
+    config: SyncConfig,
+    relayed: RelayCache,
+    in_flight: InFlightRequests,
+    peers: PeerSyncTracker,
+    links: LinkQualityTracker,
+    pacer: Pacer,
+    epoch: Option<Epoch>,
+    beacon_due: bool,
+    recently_added: VecDeque<StationSparse>,
+    skew_dropped: u64,
+    unrestorable: u64,
+    anomalies: Vec<Error>,
+}
+
+impl SyncEngine {
+    pub fn new(config: SyncConfig) -> Self {
+        let relayed = RelayCache::new(config.relay_cache_capacity);
+        let mut hasher = Hasher::new();
+        config.station.hash(&mut hasher);
+        let pacer = Pacer::new(
+            config.response_gap,
+            config.response_jitter,
+            hasher.finalize(),
+        );
+        Self {
+            config,
+            relayed,
+            in_flight: InFlightRequests::default(),
+            peers: PeerSyncTracker::default(),
+            links: LinkQualityTracker::default(),
+            pacer,
+            epoch: None,
+            beacon_due: false,
+            recently_added: VecDeque::new(),
+            skew_dropped: 0,
+            unrestorable: 0,
+            anomalies: vec![],
+        }
+    }
+
+    /// Process a transmission heard on the channel at time `now`.
+    ///
+    /// Returns the transmissions that we should send now, which may be empty.
+    /// This includes any earlier transmissions that were held back for pacing
+    /// and have now become due.
+    pub fn handle(
+        &mut self,
+        db: &dyn Database,
+        t: &Transmission,
+        now: OffsetDateTime,
+    ) -> Result<Vec<Transmission>, DbError> {
+        let out = self.respond(db, t, now)?;
+        Ok(self.pace(out, now))
+    }
+
+    /// Work out what we would like to send in response to `t`, ignoring pacing.
+    fn respond(
+        &mut self,
+        db: &dyn Database,
+        t: &Transmission,
+        now: OffsetDateTime,
+    ) -> Result<Vec<Transmission>, DbError> {
+        if t.network != self.config.network || t.version != self.config.version {
+            return Ok(vec![]);
+        }
+        let epoch = match t.command.resolve_epoch_at(now) {
+            Ok(epoch) => epoch,
+            Err(_) => {
+                self.skew_dropped += 1;
+                return Ok(vec![]);
+            }
+        };
+        match (&t.command, epoch) {
+            (Command::Range(range), _) => {
+                self.peers
+                    .record_range(&t.sender, range, &self.config.station);
+                self.links
+                    .record_range(&t.sender, range, &self.config.station, now);
+                Ok(vec![])
+            }
+            (Command::Status(status), Some(epoch)) => {
+                self.receive_status(db, &t.sender, status, epoch, now)
+            }
+            (Command::EpochRequest(request), Some(epoch)) => {
+                self.answer_epoch_request(db, request, epoch)
+            }
+            (Command::BucketContentRequest(request), Some(epoch)) => {
+                self.answer_bucket_request(db, request, epoch)
+            }
+            (Command::StationDataRequest(request), Some(epoch)) => {
+                self.answer_station_data_request(db, request, epoch)
+            }
+            (Command::QuickSyncFrameRequest(request), Some(epoch)) => {
+                self.answer_frame_request(db, request, FrameResponseKind::QuickSync, epoch)
+            }
+            (Command::BackfillFrameRequest(request), Some(epoch)) => {
+                self.answer_frame_request(db, request, FrameResponseKind::Backfill, epoch)
+            }
+            (Command::InsertFrame(insert), Some(epoch)) => {
+                self.receive_frame(db, &t.sender, &insert.frame, epoch, true, now)
+            }
+            (Command::RepeatFrame(def), Some(epoch)) => {
+                self.receive_frame(db, &def.station, &def.frame, epoch, true, now)
+            }
+            (
+                Command::QuickSyncFrameResponse(def) | Command::BackfillFrameResponse(def),
+                Some(epoch),
+            ) => self.receive_frame(db, &def.station, &def.frame, epoch, false, now),
+            (
+                Command::QuickEpochResponse(_)
+                | Command::EpochResponse(_)
+                | Command::BucketContentResponse(_)
+                | Command::StationDataResponse(_),
+                Some(epoch),
+            ) => self.receive_response(&t.sender, epoch, &t.command),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Perform periodic work at time `now`.
+    ///
+    /// Requests which have gone unanswered for longer than the configured
+    /// timeout are sent again, up to the configured number of retries. After
+    /// that we give up on them and they may be triggered afresh by later
+    /// transmissions from our neighbours.
+    ///
+    /// If `now` has moved into a new epoch, the mod-8 values in our `Status`
+    /// now refer to different weeks so a fresh beacon is scheduled (see
+    /// `beacon_due`) and the list of recently added frames starts again.
+    /// Neighbours unheard for `link::LINK_EXPIRY` are dropped from `links`.
+    ///
+    /// Also returns any transmissions held back for pacing which are now due.
+    pub fn tick(&mut self, now: OffsetDateTime) -> Vec<Transmission> {
+        let epoch = Epoch::at(now);
+        if self.epoch != Some(epoch) {
+            if self.epoch.is_some() {
+                self.beacon_due = true;
+                self.recently_added.clear();
+            }
+            self.epoch = Some(epoch);
+        }
+        self.links.prune(now);
+        let (resend, abandoned) =
+            self.in_flight
+                .expire(now, self.config.request_timeout, self.config.max_retries);
+        for key in abandoned {
+            if let RequestKey::Epoch(target, abs) = key {
+                self.peers.forget(&target, &ExpectedResponse::Epoch(abs));
+            }
+        }
+        let resend = resend
+            .into_iter()
+            .map(|command| self.transmission(command))
+            .collect();
+        self.pace(resend, now)
+    }
+
+    /// The epoch we considered current when `tick` was last called.
+    pub fn current_epoch(&self) -> Option<Epoch> {
+        self.epoch
+    }
+
+    /// Whether something has changed that our neighbours should hear about in a `Status`.
+    pub fn beacon_due(&self) -> bool {
+        self.beacon_due
+    }
+
+    /// Produce a `Status` announcing the data we hold at time `now`.
+    ///
+    /// Our epoch checksums are recomputed from `db` every time, so this
+    /// reflects the latest frames even just after an epoch rollover.
+    pub fn beacon(
+        &mut self,
+        db: &dyn Database,
+        now: OffsetDateTime,
+    ) -> Result<Vec<Transmission>, DbError> {
+        let recently_added = self.recently_added.iter().cloned().collect();
+        let status = query::build_status(db, &Epoch::at(now), recently_added)?;
+        self.beacon_due = false;
+        let out = vec![self.transmission(Command::Status(status))];
+        Ok(self.pace(out, now))
+    }
+
+    /// When the next transmission held back for pacing becomes due, if any.
+    ///
+    /// The caller should make sure `tick` is called at around this time.
+    pub fn next_due(&self) -> Option<OffsetDateTime> {
+        self.pacer.next_due()
+    }
+
+    /// Number of transmissions held back for pacing.
+    pub fn queued_transmissions(&self) -> usize {
+        self.pacer.len()
+    }
+
+    /// Insert a new message of our own for `application` at time `now`.
+    ///
+    /// The data is split into frames and stored in `db` by
+    /// `Database::prepare_insert`, at the next free indices for our station in
+    /// the current epoch. An `InsertFrame` is returned for each frame to
+    /// flood-fill it. If the epoch has no room, this fails with
+    /// `DbError::EpochFull` and nothing is stored or sent.
+    pub fn insert(
+        &mut self,
+        db: &dyn Database,
+        application: u8,
+        data: Vec<u8>,
+        now: OffsetDateTime,
+    ) -> Result<Vec<Transmission>, DbError> {
+        let epoch = Epoch::at(now);
+        let me = self.config.station.clone();
+        let mut out = vec![];
+        for insert in db.prepare_insert(&me, &epoch, application, &data)? {
+            self.relayed
+                .insert((me.clone(), epoch.index_abs(), insert.frame.index));
+            self.note_recently_added(&me, insert.frame.index);
+            out.push(self.transmission(Command::InsertFrame(insert)));
+        }
+        Ok(self.pace(out, now))
+    }
+
+    /// Ask `target` to summarise the frames it holds for `epoch`.
+    ///
+    /// Returns `None` if we already have the same request outstanding.
+    pub fn request_epoch(
+        &mut self,
+        target: &Station,
+        epoch: &Epoch,
+        now: OffsetDateTime,
+    ) -> Option<Transmission> {
+        let key = RequestKey::Epoch(target.clone(), epoch.index_abs());
+        let command = Command::EpochRequest(EpochRequest {
+            target: target.clone(),
+            epoch_mod8: epoch.index_mod8(),
+        });
+        if !self
+            .in_flight
+            .start(key, &command, now, self.config.request_timeout)
+        {
+            return None;
+        }
+        self.peers
+            .expect(target, ExpectedResponse::Epoch(epoch.index_abs()));
+        Some(self.transmission(command))
+    }
+
+    /// Work out which epochs to ask about after hearing the `Status` of several peers.
+    ///
+    /// `peers` must be in the order they were heard. For each epoch where at
+    /// least one peer's checksum differs from ours, a single request is made to
+    /// the best of those peers as ranked by `best_target`. Requests are ordered
+    /// by epoch.
+    pub fn consolidate_requests(
+        &self,
+        db: &dyn Database,
+        peers: &[(Station, Status)],
+        now: OffsetDateTime,
+    ) -> Result<Vec<(Station, EpochRequest)>, DbError> {
+        let mut ours = BTreeMap::new();
+        let mut candidates: BTreeMap<u32, Vec<Station>> = BTreeMap::new();
+        for (peer, status) in peers {
+            if *peer == self.config.station {
+                continue;
+            }
+            let peer_now = match Epoch::from_mod8_at(status.epoch_now_mod8, now) {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            for (offset, crc) in status.epoch_crcs() {
+                let abs = peer_now.index_abs() as i64 + offset as i64;
+                if abs < 0 {
+                    continue;
+                }
+                let epoch = Epoch::from_abs(abs as u32);
+                let our_crc = match ours.get(&epoch.index_abs()) {
+                    Some(c) => *c,
+                    None => {
+                        let c = db.epoch_crc(&epoch)?;
+                        ours.insert(epoch.index_abs(), c);
+                        c
+                    }
+                };
+                if crc != our_crc {
+                    candidates
+                        .entry(epoch.index_abs())
+                        .or_default()
+                        .push(peer.clone());
+                }
+            }
+        }
+        Ok(candidates
+            .into_iter()
+            .filter_map(|(abs, peers)| {
+                let epoch = Epoch::from_abs(abs);
+                let peer = self.best_target(&peers, &epoch)?.clone();
+                let request = EpochRequest {
+                    target: peer.clone(),
+                    epoch_mod8: epoch.index_mod8(),
+                };
+                Some((peer, request))
+            })
+            .collect())
+    }
+
+    /// Choose which of `candidates` to ask about `epoch`.
+    ///
+    /// Stations with a link known to work in both directions are preferred,
+    /// since they are likely to hear our request and we their response. After
+    /// that we prefer whoever has told us about the most frames in `epoch`,
+    /// and finally the candidate that appears last in the list.
+    pub fn best_target<'a>(&self, candidates: &'a [Station], epoch: &Epoch) -> Option<&'a Station> {
+        candidates
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, s)| {
+                (
+                    self.peers.is_mutual(s),
+                    self.peers.advertised_coverage(s, epoch),
+                    *i,
+                )
+            })
+            .map(|(_, s)| s)
+    }
+
+    /// Save the requests we are waiting on, which peers can hear us and the
+    /// current epoch to `db`.
+    ///
+    /// Call `load_state` on a new engine after a restart to carry on where we left off.
+    pub fn save_state(&self, db: &dyn Database) -> Result<(), DbError> {
+        let net_prefix = self.config.network.id();
+        let requests = self
+            .in_flight
+            .iter()
+            .map(|(_, r)| SavedRequest {
+                command: encode_command(&r.command, net_prefix),
+                sent: r.sent,
+                retries: r.retries,
+            })
+            .collect();
+        let mutual = self.peers.mutual_peers().map(|p| p.to_string()).collect();
+        let expected = self
+            .peers
+            .expectations()
+            .map(|(peer, response)| SavedExpectation {
+                peer: peer.to_string(),
+                response: response.encoded(),
+            })
+            .collect();
+        db.save_sync_state(&SyncState {
+            epoch: self.epoch.map(|e| e.index_abs()),
+            requests,
+            mutual,
+            expected,
+        })
+    }
+
+    /// Restore the state saved by `save_state`, if there is any.
+    ///
+    /// Restored requests are retried and their responses accepted as if we
+    /// had never stopped. Saved entries which can no longer be understood are
+    /// skipped and counted in `unrestorable`.
+    pub fn load_state(&mut self, db: &dyn Database) -> Result<(), DbError> {
+        let state = match db.load_sync_state()? {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        self.epoch = state.epoch.map(Epoch::from_abs);
+        let net_prefix = self.config.network.id().to_owned();
+        for saved in state.requests {
+            let command = decode_command(&saved.command, &net_prefix).ok();
+            let Some((command, key)) =
+                command.and_then(|c| request_key(&c, saved.sent).map(|k| (c, k)))
+            else {
+                self.unrestorable += 1;
+                continue;
+            };
+            let request = InFlight {
+                command,
+                sent: saved.sent,
+                retries: saved.retries,
+            };
+            self.in_flight.restore(key, request);
+        }
+        for peer in state.mutual {
+            match peer.parse::<Station>() {
+                Ok(p) => self.peers.restore_mutual(&p),
+                Err(_) => self.unrestorable += 1,
+            }
+        }
+        for saved in state.expected {
+            let peer = saved.peer.parse::<Station>().ok();
+            match (peer, ExpectedResponse::try_parse(&saved.response)) {
+                (Some(p), Some(r)) => self.peers.expect(&p, r),
+                _ => self.unrestorable += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// How complete our data is for each epoch announced in our `Status` at time `now`.
+    ///
+    /// This compares the frames in `db` against the highest index for each
+    /// station that neighbours have reported in their epoch and bucket
+    /// responses. Epochs are listed oldest first.
+    pub fn progress(
+        &self,
+        db: &dyn Database,
+        now: OffsetDateTime,
+    ) -> Result<Vec<EpochProgress>, DbError> {
+        let current = Epoch::at(now).index_abs();
+        let mut progress = vec![];
+        for abs in current.saturating_sub(4)..=current + 1 {
+            let epoch = Epoch::from_abs(abs);
+            let mut expected = 0;
+            let mut held = 0;
+            for (station, top) in self.peers.highest_advertised(&epoch) {
+                expected += top as u32 + 1;
+                held += db
+                    .contiguous_ranges_for_station(&epoch, &station)?
+                    .iter()
+                    .filter(|r| r.bottom <= top)
+                    .map(|r| (r.top.min(top) - r.bottom) as u32 + 1)
+                    .sum::<u32>();
+            }
+            progress.push(EpochProgress {
+                epoch,
+                expected,
+                held,
+            });
+        }
+        Ok(progress)
+    }
+
+    /// What we have learned about our neighbours from their responses.
+    pub fn peers(&self) -> &PeerSyncTracker {
+        &self.peers
+    }
+
+    /// How steadily each neighbour has been able to hear us.
+    pub fn links(&self) -> &LinkQualityTracker {
+        &self.links
+    }
+
+    /// Number of transmissions dropped because their epoch couldn't be resolved.
+    ///
+    /// A steadily rising count suggests that a neighbour's clock (or ours) is
+    /// more than a week out.
+    pub fn skew_dropped(&self) -> u64 {
+        self.skew_dropped
+    }
+
+    /// Number of saved requests, peers and expectations that `load_state` couldn't restore.
+    pub fn unrestorable(&self) -> u64 {
+        self.unrestorable
+    }
+
+    /// Whether we relay and present frames for `application`.
+    pub fn accepts(&self, application: u8) -> bool {
+        match &self.config.accepted_applications {
+            Some(accepted) => accepted.contains(&application),
+            None => true,
+        }
+    }
+
+    /// Remove and return problems noticed in received transmissions since the last call.
+    ///
+    /// These don't prevent us handling other transmissions but may be of
+    /// interest to an operator. Currently the only one reported is
+    /// `Error::SpoofedSelfInsertion`.
+    pub fn take_anomalies(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.anomalies)
+    }
+
+    /// Number of requests we have sent which are still awaiting a response.
+    pub fn pending_requests(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Store a received frame if we haven't seen it before.
+    ///
+    /// Frames which arrived by flood-fill are relayed once as a `RepeatFrame` if
+    /// they were new to us. Frames we explicitly requested are only stored.
+    /// Frames for applications we don't accept are stored but never relayed.
+    ///
+    /// A frame claiming that we inserted it must already be in the database,
+    /// since we store our own frames before sending them. Any other is a spoof
+    /// or a replay from a lost database, and is reported by `take_anomalies`
+    /// instead of being stored.
+    fn receive_frame(
+        &mut self,
+        db: &dyn Database,
+        inserter: &Station,
+        frame: &FrameWithMetadata,
+        epoch: Epoch,
+        relay: bool,
+        now: OffsetDateTime,
+    ) -> Result<Vec<Transmission>, DbError> {
+        let key = (inserter.clone(), epoch.index_abs(), frame.index);
+        self.in_flight.complete_frame(&key);
+        if self.relayed.touch(&key) {
+            return Ok(vec![]);
+        }
+        // The database is the source of truth if we haven't seen it recently
+        let held = db.has_frame(inserter, &epoch, frame.index)?;
+        if !held && *inserter == self.config.station {
+            self.anomalies.push(Error::SpoofedSelfInsertion {
+                epoch: epoch.index_abs(),
+                index: frame.index,
+            });
+            return Ok(vec![]);
+        }
+        let is_new = !held && db.store_frame(inserter, &epoch, frame)?;
+        self.relayed.insert(key);
+        if is_new && epoch == Epoch::at(now) {
+            self.note_recently_added(inserter, frame.index);
+        }
+        if !is_new || !relay || !self.accepts(frame.application) {
+            return Ok(vec![]);
+        }
+        Ok(vec![self.transmission(Command::RepeatFrame(
+            FrameDefinition {
+                station: inserter.clone(),
+                frame: frame.clone(),
+            },
+        ))])
+    }
+
+    /// Record a response to one of our sync requests, if we actually asked for it.
+    fn receive_response(
+        &mut self,
+        sender: &Station,
+        epoch: Epoch,
+        command: &Command,
+    ) -> Result<Vec<Transmission>, DbError> {
+        if !self.peers.accept(sender, &epoch, command) {
+            return Ok(vec![]);
+        }
+        if let Command::QuickEpochResponse(_) | Command::EpochResponse(_) = command {
+            self.in_flight
+                .complete(&RequestKey::Epoch(sender.clone(), epoch.index_abs()));
+        }
+        Ok(vec![])
+    }
+
+    /// Remember that we just stored frame `index` from `inserter`, for our next `Status`.
+    fn note_recently_added(&mut self, inserter: &Station, index: u16) {
+        let pos = self
+            .recently_added
+            .iter()
+            .position(|s| s.station == *inserter);
+        let sparse = match pos.and_then(|p| self.recently_added.remove(p)) {
+            Some(mut s) if s.top + 1 == index => {
+                s.top = index;
+                s
+            }
+            _ => StationSparse {
+                station: inserter.clone(),
+                top: index,
+                bottom: index,
+            },
+        };
+        self.recently_added.push_front(sparse);
+        self.recently_added.truncate(MAX_RECENTLY_ADDED);
+    }
+
+    /// Use a neighbour's list of recently added frames to quick sync any we are missing.
+    fn receive_status(
+        &mut self,
+        db: &dyn Database,
+        sender: &Station,
+        status: &Status,
+        epoch: Epoch,
+        now: OffsetDateTime,
+    ) -> Result<Vec<Transmission>, DbError> {
+        let mut out = vec![];
+        for sparse in &status.recently_added {
+            if sparse.station == self.config.station {
+                continue;
+            }
+            // Newest frames first since they are the least likely to be held by others
+            for index in (sparse.bottom..=sparse.top).rev() {
+                if out.len() >= MAX_QUICK_SYNC_REQUESTS {
+                    return Ok(out);
+                }
+                let key = (sparse.station.clone(), epoch.index_abs(), index);
+                if self.relayed.touch(&key) || db.has_frame(&sparse.station, &epoch, index)? {
+                    continue;
+                }
+                let command = Command::QuickSyncFrameRequest(FrameRequest {
+                    target: sender.clone(),
+                    inserter: sparse.station.clone(),
+                    epoch_mod8: status.epoch_now_mod8,
+                    index,
+                });
+                let timeout = self.config.request_timeout;
+                if self.in_flight.start(
+                    RequestKey::Frame(sender.clone(), key),
+                    &command,
+                    now,
+                    timeout,
+                ) {
+                    out.push(self.transmission(command));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Send a single frame to a station that asked us for it, if we hold it.
+    fn answer_frame_request(
+        &mut self,
+        db: &dyn Database,
+        request: &FrameRequest,
+        kind: FrameResponseKind,
+        epoch: Epoch,
+    ) -> Result<Vec<Transmission>, DbError> {
+        if request.target != self.config.station {
+            return Ok(vec![]);
+        }
+        let frame = match db.get_frame(&request.inserter, &epoch, request.index)? {
+            Some(f) => f.to_frame_with_metadata(),
+            None => return Ok(vec![]),
+        };
+        let inserter = request.inserter.clone();
+        let command = request.clone().into_response(kind, frame, inserter);
+        Ok(vec![self.transmission(command)])
+    }
+
+    /// Describe one of our epochs to a station that asked us about it.
+    fn answer_epoch_request(
+        &mut self,
+        db: &dyn Database,
+        request: &EpochRequest,
+        epoch: Epoch,
+    ) -> Result<Vec<Transmission>, DbError> {
+        if request.target != self.config.station {
+            return Ok(vec![]);
+        }
+        let stations = db.station_summaries_for_epoch(&epoch)?;
+        let checksums = db.bucket_checksums(&epoch)?;
+        let command = self.epoch_response(request.epoch_mod8, stations, checksums);
+        Ok(vec![self.transmission(command)])
+    }
+
+    /// List the stations in one of our buckets to a station that asked about it.
+    ///
+    /// If the requested page is beyond the last one we reply with no stations
+    /// and the true `final_page`, so the requester can see there is nothing more.
+    fn answer_bucket_request(
+        &mut self,
+        db: &dyn Database,
+        request: &BucketContentRequest,
+        epoch: Epoch,
+    ) -> Result<Vec<Transmission>, DbError> {
+        if request.target != self.config.station {
+            return Ok(vec![]);
+        }
+        let stations = db.stations_in_bucket(&epoch, request.bucket)?;
+        let net_prefix = self.config.network.id();
+        let mut pages = paginate(stations, |s| s.encoded_len(net_prefix));
+        pages.truncate(MAX_PAGES);
+        let final_page = (pages.len() - 1) as u8;
+        let stations = pages
+            .into_iter()
+            .nth(request.page as usize)
+            .unwrap_or_default();
+        Ok(vec![self.transmission(Command::BucketContentResponse(
+            BucketContentResponse {
+                epoch_mod8: request.epoch_mod8,
+                final_page,
+                page: request.page,
+                stations,
+            },
+        ))])
+    }
+
+    /// List the ranges of frames we hold for one station to a station that asked about it.
+    ///
+    /// Only ranges which finish at or above the requested `from_index` are
+    /// included. The last response we send has `end_of_data` set if it contains
+    /// the highest index we hold.
+    fn answer_station_data_request(
+        &mut self,
+        db: &dyn Database,
+        request: &StationDataRequest,
+        epoch: Epoch,
+    ) -> Result<Vec<Transmission>, DbError> {
+        if request.target != self.config.station {
+            return Ok(vec![]);
+        }
+        let mut ranges = db.contiguous_ranges_for_station(&epoch, &request.station)?;
+        ranges.retain(|r| r.top >= request.from_index);
+        let pages = paginate(ranges, |r| if r.bottom == 0 { 2 } else { 4 });
+        let last = pages.len() - 1;
+        Ok(pages
+            .into_iter()
+            .take(MAX_STATION_DATA_RESPONSES)
+            .enumerate()
+            .map(|(i, ranges)| {
+                self.transmission(Command::StationDataResponse(StationDataResponse {
+                    station: request.station.clone(),
+                    epoch_mod8: request.epoch_mod8,
+                    end_of_data: i == last,
+                    ranges,
+                }))
+            })
+            .collect())
+    }
+
+    /// Choose how to describe an epoch in answer to an `EpochRequest`.
+    ///
+    /// If the summaries of all stations in the epoch fit in a single message we
+    /// send a `QuickEpochResponse`, otherwise we fall back to an `EpochResponse`
+    /// with the per-bucket `checksums`.
+    pub fn epoch_response(
+        &self,
+        epoch_mod8: u8,
+        stations: Vec<StationSummary>,
+        checksums: [u32; 16],
+    ) -> Command {
+        epoch_response(epoch_mod8, stations, checksums, self.config.network.id())
+    }
+
+    /// Queue `out` behind anything already waiting and return whatever is due at `now`.
+    fn pace(&mut self, out: Vec<Transmission>, now: OffsetDateTime) -> Vec<Transmission> {
+        for t in out {
+            self.pacer.push(t, now);
+        }
+        self.pacer.due(now)
+    }
+
+    /// Wrap a command in a transmission sent from our own station.
+    fn transmission(&self, command: Command) -> Transmission {
+        Transmission {
+            version: self.config.version.clone(),
+            network: self.config.network.clone(),
+            sender: self.config.station.clone(),
+            command,
+        }
+    }
+}
+
+/// Work out the `RequestKey` for a request `command` which was sent at time `sent`.
+fn request_key(command: &Command, sent: OffsetDateTime) -> Option<RequestKey> {
+    match command {
+        Command::QuickSyncFrameRequest(r) | Command::BackfillFrameRequest(r) => {
+            let epoch = Epoch::from_mod8_at(r.epoch_mod8, sent).ok()?;
+            Some(RequestKey::Frame(
+                r.target.clone(),
+                (r.inserter.clone(), epoch.index_abs(), r.index),
+            ))
+        }
+        Command::EpochRequest(r) => {
+            let epoch = Epoch::from_mod8_at(r.epoch_mod8, sent).ok()?;
+            Some(RequestKey::Epoch(r.target.clone(), epoch.index_abs()))
+        }
+        _ => None,
+    }
+}
+
+/// Split `items` into pages whose encoded lengths, given by `len`, fit in `MAX_PAYLOAD_LEN`.
+///
+/// Always returns at least one page, which will be empty if there are no items.
+fn paginate<T>(items: Vec<T>, len: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+    let mut pages = vec![vec![]];
+    let mut used = 0;
+    for item in items {
+        let item_len = len(&item);
+        let current = pages.last_mut().unwrap();
+        if !current.is_empty() && used + item_len > MAX_PAYLOAD_LEN {
+            pages.push(vec![item]);
+            used = item_len;
+        } else {
+            current.push(item);
+            used += item_len;
+        }
+    }
+    pages
+}
+
+/// The response to an `EpochRequest`, as described by `SyncEngine::epoch_response`.
+fn epoch_response(
+    epoch_mod8: u8,
+    stations: Vec<StationSummary>,
+    checksums: [u32; 16],
+    net_prefix: &str,
+) -> Command {
+    if quick_epoch_response_fits(&stations, net_prefix) {
+        Command::QuickEpochResponse(QuickEpochResponse {
+            epoch_mod8,
+            stations,
+        })
+    } else {
+        Command::EpochResponse(EpochResponse {
+            epoch_mod8,
+            checksums,
+        })
+    }
+}
+
+/// Whether `stations` can all be sent in a single `QuickEpochResponse`.
+pub fn quick_epoch_response_fits(stations: &[StationSummary], net_prefix: &str) -> bool {
+    let len: usize = stations.iter().map(|s| s.encoded_len(net_prefix)).sum();
+    len <= MAX_PAYLOAD_LEN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{
+        model::Frame,
+        sqlite::Db,
+        testing::{frame, memory_db},
+    };
+    use crate::protocol::global::{
+        ContiguousRange, InsertFrame, Range, StationHeard, MAX_FRAME_INDEX,
+    };
+    use crate::protocol::{chat::CHAT_APPLICATION, forum::FORUM_APPLICATION};
+    use std::cell::{Cell, RefCell};
+
+    fn config() -> SyncConfig {
+        let mut config = SyncConfig::new(
+            Station::new("VK7XT".to_owned(), 5).unwrap(),
+            Network::new("VK7".to_owned()).unwrap(),
+            ChatterooVersion::Test,
+        );
+        // Most tests want to see every response straight away
+        config.response_gap = Duration::ZERO;
+        config.response_jitter = Duration::ZERO;
+        config
+    }
+
+    fn engine() -> SyncEngine {
+        SyncEngine::new(config())
+    }
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+
+    /// Database which holds frames in memory and counts how often it is asked about them.
+    #[derive(Default)]
+    struct CountingDb {
+        frames: RefCell<HashSet<(Station, u32, u16)>>,
+        lookups: Cell<usize>,
+    }
+
+    impl Database for CountingDb {
+        fn has_frame(
+            &self,
+            inserter: &Station,
+            epoch: &Epoch,
+            index: u16,
+        ) -> Result<bool, DbError> {
+            self.lookups.set(self.lookups.get() + 1);
+            let key = (inserter.clone(), epoch.index_abs(), index);
+            Ok(self.frames.borrow().contains(&key))
+        }
+
+        fn store_frame(
+            &self,
+            inserter: &Station,
+            epoch: &Epoch,
+            frame: &FrameWithMetadata,
+        ) -> Result<bool, DbError> {
+            let key = (inserter.clone(), epoch.index_abs(), frame.index);
+            Ok(self.frames.borrow_mut().insert(key))
+        }
+
+        fn frames_in_epoch(&self, _epoch: &Epoch) -> Result<Vec<Frame>, DbError> {
+            Ok(vec![])
+        }
+    }
+
+    fn insert_from(callsign: &str, index: u16) -> Transmission {
+        Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new(callsign.to_owned(), 0).unwrap(),
+            command: Command::InsertFrame(InsertFrame {
+                frame: frame(&Epoch::now(), index, b"hello"),
+            }),
+        }
+    }
+
+    #[test]
+    fn new_frame_relayed_once() {
+        let db = memory_db();
+        let mut engine = engine();
+        let t = insert_from("VK7NTK", 0);
+
+        let out = engine.handle(&db, &t, now()).unwrap();
+        assert_eq!(out.len(), 1);
+        match &out[0].command {
+            Command::RepeatFrame(def) => {
+                assert_eq!(def.station, t.sender);
+                assert_eq!(def.frame.index, 0);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+        assert_eq!(out[0].sender, engine.config.station);
+
+        // Hearing the same frame again, or someone else's relay of it, is a no-op
+        assert!(engine.handle(&db, &t, now()).unwrap().is_empty());
+        let mut relayed = out[0].clone();
+        relayed.sender = Station::new("VK7FDAE".to_owned(), 4).unwrap();
+        assert!(engine.handle(&db, &relayed, now()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn other_network_ignored() {
+        let db = memory_db();
+        let mut engine = engine();
+        let mut t = insert_from("VK3ABC", 0);
+        t.network = Network::new("VK3".to_owned()).unwrap();
+        assert!(engine.handle(&db, &t, now()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn activity_not_stored_or_relayed() {
+        use crate::protocol::chat::{Activity, Room};
+
+        let db = CountingDb::default();
+        let mut engine = engine();
+        let sender = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        let activity = Activity {
+            author: sender.clone(),
+            room: Some(Room::new("HF".to_owned()).unwrap()),
+        };
+        let t = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender,
+            command: Command::PingResponse(activity.response()),
+        };
+        assert!(engine.handle(&db, &t, now()).unwrap().is_empty());
+        assert!(db.frames.borrow().is_empty());
+        assert_eq!(db.lookups.get(), 0);
+    }
+
+    #[test]
+    fn relay_cache_avoids_lookups() {
+        let db = CountingDb::default();
+        let mut engine = engine();
+        let t = insert_from("VK7NTK", 0);
+
+        assert_eq!(engine.handle(&db, &t, now()).unwrap().len(), 1);
+        assert_eq!(db.lookups.get(), 1);
+        for _ in 0..5 {
+            assert!(engine.handle(&db, &t, now()).unwrap().is_empty());
+        }
+        assert_eq!(db.lookups.get(), 1);
+    }
+
+    #[test]
+    fn relay_cache_capacity() {
+        let db = CountingDb::default();
+        let mut config = config();
+        config.relay_cache_capacity = 2;
+        let mut engine = SyncEngine::new(config);
+
+        for index in 0..3 {
+            engine
+                .handle(&db, &insert_from("VK7NTK", index), now())
+                .unwrap();
+        }
+        assert_eq!(db.lookups.get(), 3);
+
+        // Index 0 has been evicted so we must go back to the database, which still has it
+        assert!(engine
+            .handle(&db, &insert_from("VK7NTK", 0), now())
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.lookups.get(), 4);
+
+        // Index 2 is still cached
+        assert!(engine
+            .handle(&db, &insert_from("VK7NTK", 2), now())
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.lookups.get(), 4);
+    }
+
+    fn summaries(short: usize, long: usize) -> Vec<StationSummary> {
+        // Local callsigns encode to 3 bytes, so these are 9 and 11 bytes respectively
+        let short = (0..short).map(|i| StationSummary {
+            station: Station::new("VK7XT".to_owned(), i as u8).unwrap(),
+            top: 10,
+            bottom: 0,
+            epoch_crc: 0x12345678,
+        });
+        let long = (0..long).map(|i| StationSummary {
+            station: Station::new("VK7NTK".to_owned(), i as u8).unwrap(),
+            top: 10,
+            bottom: 5,
+            epoch_crc: 0x12345678,
+        });
+        short.chain(long).collect()
+    }
+
+    #[test]
+    fn quick_epoch_response_boundary() {
+        let engine = engine();
+
+        // 4 * 9 + 4 * 11 = 80 bytes exactly
+        let fits = summaries(4, 4);
+        assert!(quick_epoch_response_fits(&fits, "VK7"));
+        match engine.epoch_response(3, fits.clone(), [0; 16]) {
+            Command::QuickEpochResponse(r) => {
+                assert_eq!(r.epoch_mod8, 3);
+                assert_eq!(r.stations, fits);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        let too_many = summaries(5, 4);
+        assert!(!quick_epoch_response_fits(&too_many, "VK7"));
+        match engine.epoch_response(3, too_many, [7; 16]) {
+            Command::EpochResponse(r) => {
+                assert_eq!(r.epoch_mod8, 3);
+                assert_eq!(r.checksums, [7; 16]);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    fn status_from(callsign: &str, inserter: &str, top: u16, bottom: u16) -> Transmission {
+        Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new(callsign.to_owned(), 0).unwrap(),
+            command: Command::Status(Status {
+                epoch_now_mod8: Epoch::now().index_mod8(),
+                epoch_4_ago_crc: 0,
+                epoch_3_ago_crc: 0,
+                epoch_2_ago_crc: 0,
+                epoch_1_ago_crc: 0,
+                epoch_now_crc: 0,
+                epoch_next_crc: 0,
+                recently_added: vec![StationSparse {
+                    station: Station::new(inserter.to_owned(), 1).unwrap(),
+                    top,
+                    bottom,
+                }],
+            }),
+        }
+    }
+
+    #[test]
+    fn duplicate_requests_suppressed() {
+        let db = memory_db();
+        let mut engine = engine();
+        let start = now();
+
+        let out = engine
+            .handle(&db, &status_from("VK7NTK", "VK7FDAE", 0, 0), start)
+            .unwrap();
+        assert_eq!(out.len(), 1);
+        match &out[0].command {
+            Command::QuickSyncFrameRequest(r) => {
+                assert_eq!(r.target.callsign(), "VK7NTK");
+                assert_eq!(r.inserter.callsign(), "VK7FDAE");
+                assert_eq!(r.index, 0);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+        assert_eq!(engine.pending_requests(), 1);
+
+        // The same frame advertised again by the same neighbour is already requested
+        let again = engine
+            .handle(&db, &status_from("VK7NTK", "VK7FDAE", 0, 0), start)
+            .unwrap();
+        assert!(again.is_empty());
+
+        // Another neighbour is a different target, which may answer when the first can't
+        let other = engine
+            .handle(&db, &status_from("VK7ABC", "VK7FDAE", 0, 0), start)
+            .unwrap();
+        assert_eq!(other.len(), 1);
+        assert_eq!(engine.pending_requests(), 2);
+
+        // Once the timeout has elapsed we may ask again
+        let later = start + DEFAULT_REQUEST_TIMEOUT;
+        let retry = engine
+            .handle(&db, &status_from("VK7NTK", "VK7FDAE", 0, 0), later)
+            .unwrap();
+        assert_eq!(retry.len(), 1);
+    }
+
+    #[test]
+    fn response_completes_request() {
+        let db = memory_db();
+        let mut engine = engine();
+        let out = engine
+            .handle(&db, &status_from("VK7NTK", "VK7FDAE", 0, 0), now())
+            .unwrap();
+        let request = match &out[0].command {
+            Command::QuickSyncFrameRequest(r) => r.clone(),
+            c => panic!("unexpected command {:?}", c),
+        };
+        engine
+            .handle(&db, &status_from("VK7ABC", "VK7FDAE", 0, 0), now())
+            .unwrap();
+        assert_eq!(engine.pending_requests(), 2);
+        let response = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: request.target.clone(),
+            command: Command::QuickSyncFrameResponse(FrameDefinition {
+                station: request.inserter.clone(),
+                frame: FrameWithMetadata {
+                    epoch_mod8: request.epoch_mod8,
+                    ..frame(&Epoch::now(), request.index, b"hello")
+                },
+            }),
+        };
+        // Requested frames are stored but not relayed, and answer every target we asked
+        assert!(engine.handle(&db, &response, now()).unwrap().is_empty());
+        assert_eq!(engine.pending_requests(), 0);
+
+        // Now that we have it, hearing it advertised again triggers nothing
+        assert!(engine
+            .handle(&db, &status_from("VK7NTK", "VK7FDAE", 0, 0), now())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn unsolicited_response_dropped() {
+        let db = memory_db();
+        let mut engine = engine();
+        let peer = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        let epoch = Epoch::now();
+        let response = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: peer.clone(),
+            command: Command::EpochResponse(EpochResponse {
+                epoch_mod8: epoch.index_mod8(),
+                checksums: [1; 16],
+            }),
+        };
+
+        assert!(engine.handle(&db, &response, now()).unwrap().is_empty());
+        assert_eq!(engine.peers().unsolicited(), 1);
+        assert!(engine.peers().epoch_report(&peer, &epoch).is_none());
+
+        let request = engine.request_epoch(&peer, &epoch, now()).unwrap();
+        assert!(matches!(request.command, Command::EpochRequest(_)));
+        assert_eq!(engine.pending_requests(), 1);
+        engine.handle(&db, &response, now()).unwrap();
+        assert_eq!(engine.peers().unsolicited(), 1);
+        assert!(engine.peers().epoch_report(&peer, &epoch).is_some());
+        assert_eq!(engine.pending_requests(), 0);
+    }
+
+    #[test]
+    fn unanswered_requests_retried() {
+        let db = memory_db();
+        let mut config = config();
+        config.max_retries = 2;
+        let timeout = config.request_timeout;
+        let mut engine = SyncEngine::new(config);
+        let start = now();
+
+        let out = engine
+            .handle(&db, &status_from("VK7NTK", "VK7FDAE", 0, 0), start)
+            .unwrap();
+        assert_eq!(out.len(), 1);
+
+        // Nothing happens before the timeout
+        assert!(engine.tick(start + timeout / 2).is_empty());
+
+        let first = engine.tick(start + timeout);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].command, out[0].command);
+        // The timeout is measured from the retransmission
+        assert!(engine.tick(start + timeout + timeout / 2).is_empty());
+        assert_eq!(engine.tick(start + timeout * 2).len(), 1);
+
+        // Retries exhausted, so give up
+        assert!(engine.tick(start + timeout * 3).is_empty());
+        assert_eq!(engine.pending_requests(), 0);
+        assert!(engine.tick(start + timeout * 4).is_empty());
+    }
+
+    fn epoch_request(target: &Station, epoch: &Epoch) -> Transmission {
+        Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7NTK".to_owned(), 0).unwrap(),
+            command: Command::EpochRequest(EpochRequest {
+                target: target.clone(),
+                epoch_mod8: epoch.index_mod8(),
+            }),
+        }
+    }
+
+    fn store(db: &Db, callsign: &str, ssid: u8, epoch: &Epoch, index: u16) {
+        let station = Station::new(callsign.to_owned(), ssid).unwrap();
+        db.store_frame(&station, epoch, &frame(epoch, index, &[index as u8]))
+            .unwrap();
+    }
+
+    #[test]
+    fn epoch_request_answered() {
+        let db = memory_db();
+        let mut engine = engine();
+        let epoch = Epoch::now();
+        store(&db, "VK7FDAE", 4, &epoch, 0);
+        store(&db, "VK7FDAE", 4, &epoch, 1);
+        store(&db, "VK7NTK", 1, &epoch, 0);
+
+        let me = engine.config.station.clone();
+        let out = engine
+            .handle(&db, &epoch_request(&me, &epoch), now())
+            .unwrap();
+        assert_eq!(out.len(), 1);
+        match &out[0].command {
+            Command::QuickEpochResponse(r) => {
+                assert_eq!(r.epoch_mod8, epoch.index_mod8());
+                assert_eq!(r.stations.len(), 2);
+                assert_eq!(r.stations, db.station_summaries_for_epoch(&epoch).unwrap());
+                assert_eq!((r.stations[0].top, r.stations[0].bottom), (1, 0));
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        // Not addressed to us
+        let other = Station::new("VK7ABC".to_owned(), 0).unwrap();
+        assert!(engine
+            .handle(&db, &epoch_request(&other, &epoch), now())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn busy_epoch_request_answered_with_buckets() {
+        let db = memory_db();
+        let mut engine = engine();
+        let epoch = Epoch::now();
+        // Non-local stations take 10 bytes each to summarise
+        for ssid in 0..=9 {
+            store(&db, "W1AW", ssid, &epoch, 0);
+        }
+
+        let me = engine.config.station.clone();
+        let out = engine
+            .handle(&db, &epoch_request(&me, &epoch), now())
+            .unwrap();
+        match &out[0].command {
+            Command::EpochResponse(r) => {
+                assert_eq!(r.checksums, db.bucket_checksums(&epoch).unwrap());
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn bucket_request_paginated() {
+        let db = memory_db();
+        let mut engine = engine();
+        let epoch = Epoch::now();
+
+        // Find a bucket with enough non-local stations (10 bytes each) to need two pages
+        let mut buckets: Vec<Vec<Station>> = vec![vec![]; 16];
+        for letter in 'A'..='Z' {
+            for ssid in 0..=9 {
+                let s = Station::new(format!("W1A{}", letter), ssid).unwrap();
+                buckets[s.bucket() as usize].push(s);
+            }
+        }
+        let bucket = (0..16).find(|b| buckets[*b].len() >= 9).unwrap();
+        let members = &buckets[bucket][..9];
+        for s in members {
+            store(&db, s.callsign(), s.ssid(), &epoch, 0);
+        }
+
+        let me = engine.config.station.clone();
+        let mut pages = vec![];
+        for page in 0..3 {
+            let request = Transmission {
+                version: ChatterooVersion::Test,
+                network: Network::new("VK7".to_owned()).unwrap(),
+                sender: Station::new("VK7NTK".to_owned(), 0).unwrap(),
+                command: Command::BucketContentRequest(BucketContentRequest {
+                    target: me.clone(),
+                    epoch_mod8: epoch.index_mod8(),
+                    bucket: bucket as u8,
+                    page,
+                }),
+            };
+            let out = engine.handle(&db, &request, now()).unwrap();
+            match &out[0].command {
+                Command::BucketContentResponse(r) => {
+                    assert_eq!(r.final_page, 1);
+                    assert_eq!(r.page, page);
+                    pages.push(r.stations.clone());
+                }
+                c => panic!("unexpected command {:?}", c),
+            }
+        }
+        assert_eq!(pages[0].len(), 8);
+        assert_eq!(pages[1].len(), 1);
+        assert!(pages[2].is_empty());
+        let all: Vec<StationSummary> = pages.concat();
+        assert_eq!(all, db.stations_in_bucket(&epoch, bucket as u8).unwrap());
+    }
+
+    fn station_data_request(from_index: u16, epoch: &Epoch, me: &Station) -> Transmission {
+        Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: Station::new("VK7NTK".to_owned(), 0).unwrap(),
+            command: Command::StationDataRequest(StationDataRequest {
+                target: me.clone(),
+                station: Station::new("VK7FDAE".to_owned(), 4).unwrap(),
+                epoch_mod8: epoch.index_mod8(),
+                from_index,
+            }),
+        }
+    }
+
+    fn station_data_pages(out: &[Transmission]) -> Vec<(bool, Vec<(u16, u16)>)> {
+        out.iter()
+            .map(|t| match &t.command {
+                Command::StationDataResponse(r) => (
+                    r.end_of_data,
+                    r.ranges.iter().map(|r| (r.top, r.bottom)).collect(),
+                ),
+                c => panic!("unexpected command {:?}", c),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn station_data_request_answered() {
+        let db = memory_db();
+        let mut engine = engine();
+        let me = engine.config.station.clone();
+        let epoch = Epoch::now();
+        // 0-2 then every even index from 10 to 70, each a range of its own
+        for index in (0..=2).chain((10..=70).step_by(2)) {
+            store(&db, "VK7FDAE", 4, &epoch, index);
+        }
+        // Another station's frames are not included
+        store(&db, "VK7NTK", 1, &epoch, 4);
+
+        let out = engine
+            .handle(&db, &station_data_request(0, &epoch, &me), now())
+            .unwrap();
+        let pages = station_data_pages(&out);
+        assert_eq!(pages.len(), 2);
+        // 2 bytes for the range starting at 0, then 4 bytes for each other range
+        assert!(!pages[0].0);
+        assert_eq!(pages[0].1.len(), 20);
+        assert_eq!(pages[0].1[0], (2, 0));
+        assert_eq!(pages[0].1[1], (10, 10));
+        assert!(pages[1].0);
+        assert_eq!(pages[1].1.len(), 12);
+        assert_eq!(pages[1].1.last(), Some(&(70, 70)));
+
+        // Starting within a range includes that range
+        let out = engine
+            .handle(&db, &station_data_request(68, &epoch, &me), now())
+            .unwrap();
+        assert_eq!(
+            station_data_pages(&out),
+            vec![(true, vec![(68, 68), (70, 70)])]
+        );
+
+        // Nothing above the highest index
+        let out = engine
+            .handle(&db, &station_data_request(71, &epoch, &me), now())
+            .unwrap();
+        assert_eq!(station_data_pages(&out), vec![(true, vec![])]);
+
+        // Not addressed to us
+        let other = Station::new("VK7ABC".to_owned(), 0).unwrap();
+        assert!(engine
+            .handle(&db, &station_data_request(0, &epoch, &other), now())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn frame_requests_answered() {
+        let db = memory_db();
+        let mut engine = engine();
+        let me = engine.config.station.clone();
+        let epoch = Epoch::now();
+        store(&db, "VK7FDAE", 4, &epoch, 7);
+        let inserter = Station::new("VK7FDAE".to_owned(), 4).unwrap();
+
+        let request = |index: u16, target: &Station, quick: bool| {
+            let request = FrameRequest {
+                target: target.clone(),
+                inserter: inserter.clone(),
+                epoch_mod8: epoch.index_mod8(),
+                index,
+            };
+            Transmission {
+                version: ChatterooVersion::Test,
+                network: Network::new("VK7".to_owned()).unwrap(),
+                sender: Station::new("VK7NTK".to_owned(), 0).unwrap(),
+                command: if quick {
+                    Command::QuickSyncFrameRequest(request)
+                } else {
+                    Command::BackfillFrameRequest(request)
+                },
+            }
+        };
+        let expected = FrameDefinition {
+            station: inserter.clone(),
+            frame: frame(&epoch, 7, &[7]),
+        };
+
+        let out = engine.handle(&db, &request(7, &me, true), now()).unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].sender, me);
+        assert_eq!(
+            out[0].command,
+            Command::QuickSyncFrameResponse(expected.clone())
+        );
+        let out = engine.handle(&db, &request(7, &me, false), now()).unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].command, Command::BackfillFrameResponse(expected));
+
+        // Frames we don't hold
+        for quick in [true, false] {
+            assert!(engine
+                .handle(&db, &request(8, &me, quick), now())
+                .unwrap()
+                .is_empty());
+        }
+
+        // Not addressed to us
+        let other = Station::new("VK7ABC".to_owned(), 0).unwrap();
+        assert!(engine
+            .handle(&db, &request(7, &other, true), now())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn responses_paced() {
+        let db = memory_db();
+        let mut config = config();
+        config.response_gap = Duration::seconds(2);
+        config.response_jitter = Duration::milliseconds(500);
+        let mut engine = SyncEngine::new(config);
+        let me = engine.config.station.clone();
+        let epoch = Epoch::now();
+        store(&db, "VK7FDAE", 4, &epoch, 0);
+
+        // A burst of requests all arrive at once
+        let start = now();
+        let mut sent = vec![];
+        for _ in 0..5 {
+            let out = engine
+                .handle(&db, &epoch_request(&me, &epoch), start)
+                .unwrap();
+            sent.extend(out.into_iter().map(|_| start));
+        }
+        assert_eq!(sent.len(), 1);
+        assert_eq!(engine.queued_transmissions(), 4);
+
+        let mut clock = start;
+        while engine.queued_transmissions() > 0 {
+            clock += Duration::milliseconds(100);
+            assert!(clock < start + Duration::seconds(20));
+            sent.extend(engine.tick(clock).into_iter().map(|_| clock));
+        }
+        assert_eq!(sent.len(), 5);
+        for pair in sent.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::seconds(2));
+            // Allow for the granularity of our ticks
+            assert!(pair[1] - pair[0] <= Duration::milliseconds(2600));
+        }
+        assert!(engine.next_due().is_none());
+    }
+
+    #[test]
+    fn requests_consolidated() {
+        let db = memory_db();
+        let engine = engine();
+        let epoch = Epoch::now();
+        store(&db, "VK7FDAE", 4, &epoch, 0);
+        let ours = db.epoch_crc(&epoch).unwrap();
+
+        let status = |now_crc: u32| Status {
+            epoch_now_mod8: epoch.index_mod8(),
+            epoch_4_ago_crc: 0,
+            epoch_3_ago_crc: 0,
+            epoch_2_ago_crc: 0,
+            epoch_1_ago_crc: 0,
+            epoch_now_crc: now_crc,
+            epoch_next_crc: 0,
+            recently_added: vec![],
+        };
+        let first = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        let second = Station::new("VK7ABC".to_owned(), 0).unwrap();
+        let agrees = Station::new("VK7DEF".to_owned(), 0).unwrap();
+
+        // Agreeing with us on everything means there's nothing to ask
+        let peers = vec![(agrees.clone(), status(ours))];
+        assert!(engine
+            .consolidate_requests(&db, &peers, now())
+            .unwrap()
+            .is_empty());
+
+        // Two peers disagree about the current epoch
+        let peers = vec![
+            (first, status(0x1234)),
+            (second.clone(), status(0x5678)),
+            (agrees, status(ours)),
+        ];
+        let requests = engine.consolidate_requests(&db, &peers, now()).unwrap();
+        assert_eq!(
+            requests,
+            vec![(
+                second.clone(),
+                EpochRequest {
+                    target: second,
+                    epoch_mod8: epoch.index_mod8(),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn epoch_rollover_schedules_beacon() {
+        let db = memory_db();
+        let mut engine = engine();
+        // Shortly before the end of an epoch
+        let epoch = Epoch::from_abs(200);
+        let start = time::macros::datetime!(2020-01-01 0:00 UTC) + Duration::weeks(201)
+            - Duration::minutes(1);
+        assert_eq!(Epoch::at(start), epoch);
+
+        assert!(engine.tick(start).is_empty());
+        assert_eq!(engine.current_epoch(), Some(epoch));
+        assert!(!engine.beacon_due());
+
+        let mut insert = insert_from("VK7NTK", 0);
+        if let Command::InsertFrame(i) = &mut insert.command {
+            i.frame.epoch_mod8 = epoch.index_mod8();
+        }
+        assert_eq!(engine.handle(&db, &insert, start).unwrap().len(), 1);
+        let out = engine.beacon(&db, start).unwrap();
+        match &out[0].command {
+            Command::Status(s) => {
+                assert_eq!(s.epoch_now_mod8, epoch.index_mod8());
+                assert_eq!(s.recently_added.len(), 1);
+                assert_ne!(s.epoch_now_crc, query::EMPTY_EPOCH_CRC);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        let later = start + Duration::minutes(2);
+        engine.tick(later);
+        assert_eq!(engine.current_epoch(), Some(Epoch::from_abs(201)));
+        assert!(engine.beacon_due());
+
+        let out = engine.beacon(&db, later).unwrap();
+        assert!(!engine.beacon_due());
+        match &out[0].command {
+            Command::Status(s) => {
+                assert_eq!(s.epoch_now_mod8, 201 % 8);
+                // Last week's frame has slid into the previous epoch's checksum
+                assert_eq!(s.epoch_now_crc, query::EMPTY_EPOCH_CRC);
+                assert_ne!(s.epoch_1_ago_crc, query::EMPTY_EPOCH_CRC);
+                assert!(s.recently_added.is_empty());
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn unreadable_epoch_dropped() {
+        let db = CountingDb::default();
+        let mut engine = engine();
+        // The one mod-8 value which is never valid, two weeks in the future
+        let unreadable = (Epoch::now().index_mod8() + 2) % 8;
+        let mut t = insert_from("VK7NTK", 0);
+        if let Command::InsertFrame(i) = &mut t.command {
+            i.frame.epoch_mod8 = unreadable;
+        }
+
+        assert!(engine.handle(&db, &t, now()).unwrap().is_empty());
+        assert_eq!(engine.skew_dropped(), 1);
+        assert_eq!(db.lookups.get(), 0);
+        assert!(db.frames.borrow().is_empty());
+
+        let me = engine.config.station.clone();
+        let mut request = epoch_request(&me, &Epoch::now());
+        if let Command::EpochRequest(r) = &mut request.command {
+            r.epoch_mod8 = unreadable;
+        }
+        assert!(engine.handle(&db, &request, now()).unwrap().is_empty());
+        assert_eq!(engine.skew_dropped(), 2);
+
+        // Readable epochs are handled as normal
+        assert_eq!(
+            engine
+                .handle(&db, &insert_from("VK7NTK", 0), now())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(engine.skew_dropped(), 2);
+    }
+
+    #[test]
+    fn state_survives_restart() {
+        let db = memory_db();
+        let mut engine = engine();
+        let start = now();
+        let epoch = Epoch::at(start);
+        let peer = Station::new("VK7ABC".to_owned(), 0).unwrap();
+        engine.tick(start);
+        let status = status_from("VK7NTK", "VK7FDAE", 1, 0);
+        assert_eq!(engine.handle(&db, &status, start).unwrap().len(), 2);
+        assert!(engine.request_epoch(&peer, &epoch, start).is_some());
+        assert_eq!(engine.pending_requests(), 3);
+        let range = Command::Range(Range {
+            final_page: 0,
+            page: 0,
+            stations: vec![StationHeard {
+                station: config().station,
+                is_mutual: true,
+            }],
+        });
+        let range = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: peer.clone(),
+            command: range,
+        };
+        engine.handle(&db, &range, start).unwrap();
+        engine.save_state(&db).unwrap();
+
+        let mut restarted = SyncEngine::new(config());
+        restarted.load_state(&db).unwrap();
+        assert_eq!(restarted.pending_requests(), 3);
+        assert_eq!(restarted.current_epoch(), Some(epoch));
+        assert_eq!(restarted.unrestorable(), 0);
+        assert!(restarted
+            .peers()
+            .is_expecting(&peer, &ExpectedResponse::Epoch(epoch.index_abs())));
+        assert!(restarted.peers().is_mutual(&peer));
+
+        // The same requests are still considered outstanding
+        assert!(restarted.handle(&db, &status, start).unwrap().is_empty());
+        assert!(restarted.request_epoch(&peer, &epoch, start).is_none());
+
+        // And are retried just as they would have been
+        let later = start + config().request_timeout;
+        let original: Vec<Command> = engine.tick(later).into_iter().map(|t| t.command).collect();
+        let resent: Vec<Command> = restarted
+            .tick(later)
+            .into_iter()
+            .map(|t| t.command)
+            .collect();
+        assert_eq!(resent.len(), 3);
+        assert!(resent.iter().all(|c| original.contains(c)));
+    }
+
+    #[test]
+    fn unrestorable_state_counted() {
+        let db = memory_db();
+        db.save_sync_state(&SyncState {
+            epoch: None,
+            requests: vec![SavedRequest {
+                command: vec![0xff],
+                sent: now(),
+                retries: 0,
+            }],
+            mutual: vec!["VK7NTK-0".to_owned(), "nonsense".to_owned()],
+            expected: vec![SavedExpectation {
+                peer: "VK7NTK-0".to_owned(),
+                response: vec![9],
+            }],
+        })
+        .unwrap();
+
+        let mut engine = engine();
+        engine.load_state(&db).unwrap();
+        assert_eq!(engine.pending_requests(), 0);
+        assert_eq!(engine.unrestorable(), 3);
+        assert!(engine
+            .peers()
+            .is_mutual(&Station::new("VK7NTK".to_owned(), 0).unwrap()));
+    }
+
+    #[test]
+    fn progress_against_advertised() {
+        let db = memory_db();
+        let mut engine = engine();
+        let start = now();
+        let epoch = Epoch::at(start);
+        let peer = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        for index in 0..5 {
+            store(&db, "VK7FDAE", 4, &epoch, index);
+        }
+        let progress = engine.progress(&db, start).unwrap();
+        assert_eq!(progress.len(), 6);
+        assert!(progress.iter().all(|p| p.fraction() == 1.0));
+
+        engine.request_epoch(&peer, &epoch, start).unwrap();
+        let response = Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: peer,
+            command: Command::QuickEpochResponse(QuickEpochResponse {
+                epoch_mod8: epoch.index_mod8(),
+                stations: vec![StationSummary {
+                    station: Station::new("VK7FDAE".to_owned(), 4).unwrap(),
+                    top: 9,
+                    bottom: 0,
+                    epoch_crc: 0x1234,
+                }],
+            }),
+        };
+        engine.handle(&db, &response, start).unwrap();
+
+        let progress = engine.progress(&db, start).unwrap();
+        let current = progress.iter().find(|p| p.epoch == epoch).unwrap();
+        assert_eq!((current.held, current.expected), (5, 10));
+        assert_eq!(current.fraction(), 0.5);
+        assert_eq!(progress[4], *current);
+    }
+
+    #[test]
+    fn best_target_chosen() {
+        let db = memory_db();
+        let mut engine = engine();
+        let me = engine.config.station.clone();
+        let epoch = Epoch::now();
+        let near = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        let far = Station::new("VK7ABC".to_owned(), 0).unwrap();
+        let heard = |sender: &Station, command: Command| Transmission {
+            version: ChatterooVersion::Test,
+            network: Network::new("VK7".to_owned()).unwrap(),
+            sender: sender.clone(),
+            command,
+        };
+        let summary = |top: u16| StationSummary {
+            station: Station::new("VK7FDAE".to_owned(), 4).unwrap(),
+            top,
+            bottom: 0,
+            epoch_crc: 0x1234,
+        };
+
+        // Both have told us what they hold, but `near` has more
+        for (peer, top) in [(&near, 20), (&far, 5)] {
+            engine.request_epoch(peer, &epoch, now()).unwrap();
+            let response = Command::QuickEpochResponse(QuickEpochResponse {
+                epoch_mod8: epoch.index_mod8(),
+                stations: vec![summary(top)],
+            });
+            engine.handle(&db, &heard(peer, response), now()).unwrap();
+        }
+        let candidates = vec![near.clone(), far.clone()];
+        assert_eq!(engine.best_target(&candidates, &epoch), Some(&near));
+
+        // Only `near` can hear us
+        let range = |stations: Vec<Station>| {
+            Command::Range(Range {
+                final_page: 0,
+                page: 0,
+                stations: stations
+                    .into_iter()
+                    .map(|station| StationHeard {
+                        station,
+                        is_mutual: true,
+                    })
+                    .collect(),
+            })
+        };
+        engine
+            .handle(&db, &heard(&near, range(vec![me.clone()])), now())
+            .unwrap();
+        engine
+            .handle(&db, &heard(&far, range(vec![near.clone()])), now())
+            .unwrap();
+        assert_eq!(engine.best_target(&candidates, &epoch), Some(&near));
+
+        // A mutual link outranks a larger holding
+        engine
+            .handle(&db, &heard(&near, range(vec![])), now())
+            .unwrap();
+        engine
+            .handle(&db, &heard(&far, range(vec![me])), now())
+            .unwrap();
+        assert_eq!(engine.best_target(&candidates, &epoch), Some(&far));
+
+        // And is used when consolidating requests
+        let status = Status {
+            epoch_now_mod8: epoch.index_mod8(),
+            epoch_4_ago_crc: 0,
+            epoch_3_ago_crc: 0,
+            epoch_2_ago_crc: 0,
+            epoch_1_ago_crc: 0,
+            epoch_now_crc: 0x5678,
+            epoch_next_crc: 0,
+            recently_added: vec![],
+        };
+        let peers = vec![(far.clone(), status.clone()), (near, status)];
+        let requests = engine.consolidate_requests(&db, &peers, now()).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, far);
+    }
+
+    #[test]
+    fn beacon_from_empty_database() {
+        let db = memory_db();
+        let mut engine = engine();
+        let out = engine.beacon(&db, now()).unwrap();
+        assert_eq!(out.len(), 1);
+        let status = match &out[0].command {
+            Command::Status(s) => s.clone(),
+            c => panic!("unexpected command {:?}", c),
+        };
+        assert_eq!(status.epoch_now_mod8, Epoch::now().index_mod8());
+        for (_, crc) in status.epoch_crcs() {
+            assert_eq!(crc, query::EMPTY_EPOCH_CRC);
+        }
+        assert!(status.recently_added.is_empty());
+
+        let encoded = encode_command(&out[0].command, "VK7");
+        assert_eq!(
+            decode_command(&encoded, "VK7").unwrap(),
+            Command::Status(status)
+        );
+    }
+
+    #[test]
+    fn unaccepted_application_stored_not_relayed() {
+        let db = memory_db();
+        let mut config = config();
+        config.accepted_applications = Some([CHAT_APPLICATION].into_iter().collect());
+        let mut engine = SyncEngine::new(config);
+
+        let chat = insert_from("VK7NTK", 0);
+        assert_eq!(engine.handle(&db, &chat, now()).unwrap().len(), 1);
+
+        let mut forum = insert_from("VK7NTK", 1);
+        if let Command::InsertFrame(i) = &mut forum.command {
+            i.frame.application = FORUM_APPLICATION;
+        }
+        assert!(engine.handle(&db, &forum, now()).unwrap().is_empty());
+        assert!(!engine.accepts(FORUM_APPLICATION));
+
+        // Stored all the same, so that our checksums match those of our neighbours
+        let inserter = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        assert!(db.has_frame(&inserter, &Epoch::now(), 0).unwrap());
+        assert!(db.has_frame(&inserter, &Epoch::now(), 1).unwrap());
+    }
+
+    #[test]
+    fn long_insert_split_into_frames() {
+        let db = memory_db();
+        let mut engine = engine();
+        let me = engine.config.station.clone();
+        let start = now();
+
+        let out = engine
+            .insert(&db, 1, vec![7; MAX_PAYLOAD_LEN + 1], start)
+            .unwrap();
+        let frames: Vec<_> = out
+            .iter()
+            .map(|t| match &t.command {
+                Command::InsertFrame(i) => i.frame.clone(),
+                c => panic!("unexpected command {:?}", c),
+            })
+            .collect();
+        assert_eq!(frames.iter().map(|f| f.index).collect::<Vec<_>>(), [0, 1]);
+        assert!(frames.iter().all(|f| f.data.len() <= MAX_PAYLOAD_LEN));
+        assert!(db.has_frame(&me, &Epoch::at(start), 1).unwrap());
+
+        // Both frames are in the relay cache and announced as recently added
+        let mut echo = out[1].clone();
+        echo.sender = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        echo.command = Command::RepeatFrame(FrameDefinition {
+            station: me.clone(),
+            frame: frames[1].clone(),
+        });
+        assert!(engine.handle(&db, &echo, start).unwrap().is_empty());
+        let beacon = engine.beacon(&db, start).unwrap();
+        match &beacon[0].command {
+            Command::Status(s) => assert_eq!(
+                s.recently_added,
+                vec![StationSparse {
+                    station: me,
+                    top: 1,
+                    bottom: 0,
+                }]
+            ),
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn insert_into_full_epoch_fails() {
+        let db = memory_db();
+        let mut engine = engine();
+        let me = engine.config.station.clone();
+        let start = now();
+        let epoch = Epoch::at(start);
+        db.store_frame(&me, &epoch, &frame(&epoch, MAX_FRAME_INDEX, &[]))
+            .unwrap();
+
+        assert!(matches!(
+            engine.insert(&db, 1, b"no room".to_vec(), start),
+            Err(DbError::EpochFull)
+        ));
+        assert_eq!(engine.queued_transmissions(), 0);
+        assert_eq!(db.frames_in_epoch(&epoch).unwrap().len(), 1);
+        match &engine.beacon(&db, start).unwrap()[0].command {
+            Command::Status(s) => assert!(s.recently_added.is_empty()),
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn spoofed_self_insertion_reported() {
+        let db = memory_db();
+        let mut engine = engine();
+        let me = engine.config.station.clone();
+        let start = now();
+
+        // Our own frame coming back to us is expected
+        let ours = engine.insert(&db, 1, b"mine".to_vec(), start).unwrap();
+        let mut echo = ours[0].clone();
+        echo.sender = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        if let Command::InsertFrame(i) = echo.command {
+            echo.command = Command::RepeatFrame(FrameDefinition {
+                station: me.clone(),
+                frame: i.frame,
+            });
+        }
+        assert!(engine.handle(&db, &echo, start).unwrap().is_empty());
+        assert!(engine.take_anomalies().is_empty());
+
+        let mut spoof = insert_from("VK7NTK", 5);
+        if let Command::InsertFrame(i) = spoof.command {
+            spoof.command = Command::RepeatFrame(FrameDefinition {
+                station: me.clone(),
+                frame: i.frame,
+            });
+        }
+        assert!(engine.handle(&db, &spoof, start).unwrap().is_empty());
+        assert!(!db.has_frame(&me, &Epoch::at(start), 5).unwrap());
+        let anomalies = engine.take_anomalies();
+        assert_eq!(anomalies.len(), 1);
+        assert!(matches!(
+            anomalies[0],
+            Error::SpoofedSelfInsertion { index: 5, .. }
+        ));
+        assert!(engine.take_anomalies().is_empty());
+    }
+
+    #[test]
+    fn late_arrival_fills_gap() {
+        let db = memory_db();
+        let mut engine = engine();
+        let epoch = Epoch::now();
+        let inserter = Station::new("VK7NTK".to_owned(), 0).unwrap();
+        for index in [0, 1, 2, 4, 5, 3] {
+            let out = engine
+                .handle(&db, &insert_from("VK7NTK", index), now())
+                .unwrap();
+            assert_eq!(out.len(), 1, "{}", index);
+        }
+        assert_eq!(
+            db.contiguous_ranges_for_station(&epoch, &inserter).unwrap(),
+            vec![ContiguousRange { top: 5, bottom: 0 }]
+        );
+        assert_eq!(db.next_index(&epoch, &inserter).unwrap(), 6);
+    }
+}
@@ -0,0 +1,78 @@
+//! Metering of outgoing transmissions so that we don't flood a busy channel.
+
+use std::collections::VecDeque;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::protocol::global::Transmission;
+
+/// Queue which releases transmissions no closer together than a minimum gap.
+///
+/// Each gap is lengthened by a random jitter so that stations which heard the
+/// same request at the same moment don't keep colliding with each other.
+pub(crate) struct Pacer {
+    gap: Duration,
+    jitter: Duration,
+    next_free: Option<OffsetDateTime>,
+    queue: VecDeque<(OffsetDateTime, Transmission)>,
+    rng: u32,
+}
+
+impl Pacer {
+    /// Different stations should use a different `seed` so their jitter differs.
+    pub(crate) fn new(gap: Duration, jitter: Duration, seed: u32) -> Self {
+        Self {
+            gap,
+            jitter,
+            next_free: None,
+            queue: VecDeque::new(),
+            // xorshift gets stuck at zero
+            rng: seed | 1,
+        }
+    }
+
+    /// Schedule `t` to be sent at the first opportunity at or after `now`.
+    pub(crate) fn push(&mut self, t: Transmission, now: OffsetDateTime) {
+        let at = match self.next_free {
+            Some(free) if free > now => free,
+            _ => now,
+        };
+        self.next_free = Some(at + self.gap + self.next_jitter());
+        self.queue.push_back((at, t));
+    }
+
+    /// Remove and return the transmissions which are due to be sent at `now`.
+    pub(crate) fn due(&mut self, now: OffsetDateTime) -> Vec<Transmission> {
+        let mut out = vec![];
+        while let Some((at, _)) = self.queue.front() {
+            if *at > now {
+                break;
+            }
+            if let Some((_, t)) = self.queue.pop_front() {
+                out.push(t);
+            }
+        }
+        out
+    }
+
+    /// When the next queued transmission will be due, if there are any.
+    pub(crate) fn next_due(&self) -> Option<OffsetDateTime> {
+        self.queue.front().map(|(at, _)| *at)
+    }
+
+    /// Number of transmissions waiting to be sent.
+    pub(crate) fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn next_jitter(&mut self) -> Duration {
+        let range = self.jitter.whole_milliseconds();
+        if range <= 0 {
+            return Duration::ZERO;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        Duration::milliseconds((self.rng as i128 % (range + 1)) as i64)
+    }
+}
@@ -0,0 +1,353 @@
+//! Knowledge about what neighbouring stations hold, learned from their responses.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::protocol::{
+    epoch::Epoch,
+    global::{Command, ContiguousRange, Range, StationSummary},
+    station::Station,
+};
+
+/// A response we are expecting from a peer because we asked for it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExpectedResponse {
+    /// `QuickEpochResponse` or `EpochResponse` for the given absolute epoch.
+    Epoch(u32),
+
+    /// `BucketContentResponse` for the given absolute epoch, bucket and page.
+    BucketContent(u32, u8, u8),
+
+    /// `StationDataResponse` for frames inserted by a station in the given absolute epoch.
+    StationData(u32, Station),
+}
+
+impl ExpectedResponse {
+    /// Compact encoding of this expectation, for saving across restarts.
+    pub fn encoded(&self) -> Vec<u8> {
+        let (kind, abs) = match self {
+            ExpectedResponse::Epoch(abs) => (0, abs),
+            ExpectedResponse::BucketContent(abs, _, _) => (1, abs),
+            ExpectedResponse::StationData(abs, _) => (2, abs),
+        };
+        let mut out = vec![kind];
+        out.extend_from_slice(&abs.to_be_bytes());
+        match self {
+            ExpectedResponse::Epoch(_) => {}
+            ExpectedResponse::BucketContent(_, bucket, page) => out.extend([*bucket, *page]),
+            ExpectedResponse::StationData(_, station) => {
+                out.extend_from_slice(station.to_string().as_bytes())
+            }
+        }
+        out
+    }
+
+    /// Parse an expectation produced by `encoded`, or `None` if it is invalid.
+    pub fn try_parse(encoded: &[u8]) -> Option<Self> {
+        let (&kind, rest) = encoded.split_first()?;
+        let abs = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?);
+        let rest = &rest[4..];
+        match (kind, rest) {
+            (0, []) => Some(ExpectedResponse::Epoch(abs)),
+            (1, &[bucket, page]) => Some(ExpectedResponse::BucketContent(abs, bucket, page)),
+            (2, station) => {
+                let station = std::str::from_utf8(station).ok()?.parse().ok()?;
+                Some(ExpectedResponse::StationData(abs, station))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a peer summarised one of its epochs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EpochReport {
+    /// Every station was listed individually in a `QuickEpochResponse`.
+    Stations(Vec<StationSummary>),
+
+    /// Per-bucket checksums were given in an `EpochResponse`.
+    Buckets([u32; 16]),
+}
+
+/// Tracks the responses we expect from each peer and what those responses told us.
+///
+/// Responses which don't correspond to a request we made are dropped without
+/// being recorded, so that a misbehaving station can't steer our sync process
+/// by feeding us data we never asked for.
+#[derive(Default)]
+pub struct PeerSyncTracker {
+    expected: HashSet<(Station, ExpectedResponse)>,
+    epochs: HashMap<(Station, u32), EpochReport>,
+    summaries: HashMap<(Station, u32), HashMap<Station, StationSummary>>,
+    ranges: HashMap<(Station, u32, Station), Vec<ContiguousRange>>,
+    mutual: HashSet<Station>,
+    unsolicited: u64,
+}
+
+impl PeerSyncTracker {
+    /// Note that we have asked `peer` for `response`.
+    pub fn expect(&mut self, peer: &Station, response: ExpectedResponse) {
+        self.expected.insert((peer.clone(), response));
+    }
+
+    /// Stop waiting for `peer` to send `response`, for example because the request timed out.
+    pub fn forget(&mut self, peer: &Station, response: &ExpectedResponse) {
+        self.take(peer, response);
+    }
+
+    /// Whether we are still waiting for `peer` to send `response`.
+    pub fn is_expecting(&self, peer: &Station, response: &ExpectedResponse) -> bool {
+        self.expected.contains(&(peer.clone(), response.clone()))
+    }
+
+    /// Validate a response `command` from `peer` which refers to `epoch`, recording it if valid.
+    ///
+    /// Returns `false` if we did not ask for this response, in which case it
+    /// is counted as unsolicited and otherwise ignored. Commands which are not
+    /// responses to sync requests are always accepted.
+    pub fn accept(&mut self, peer: &Station, epoch: &Epoch, command: &Command) -> bool {
+        let abs = epoch.index_abs();
+        let solicited = match command {
+            Command::QuickEpochResponse(_) | Command::EpochResponse(_) => {
+                self.take(peer, &ExpectedResponse::Epoch(abs))
+            }
+            Command::BucketContentResponse(r) => {
+                let bucket = match r.stations.first() {
+                    Some(first) => first.station.bucket(),
+                    None => self.expected_bucket(peer, abs, r.page).unwrap_or(0),
+                };
+                r.stations.iter().all(|s| s.station.bucket() == bucket)
+                    && self.take(peer, &ExpectedResponse::BucketContent(abs, bucket, r.page))
+            }
+            Command::StationDataResponse(r) => {
+                self.take(peer, &ExpectedResponse::StationData(abs, r.station.clone()))
+            }
+            _ => return true,
+        };
+        if !solicited {
+            self.unsolicited += 1;
+            return false;
+        }
+
+        match command {
+            Command::QuickEpochResponse(r) => {
+                self.record_summaries(peer, abs, &r.stations);
+                self.epochs.insert(
+                    (peer.clone(), abs),
+                    EpochReport::Stations(r.stations.clone()),
+                );
+            }
+            Command::EpochResponse(r) => {
+                self.epochs
+                    .insert((peer.clone(), abs), EpochReport::Buckets(r.checksums));
+            }
+            Command::BucketContentResponse(r) => {
+                self.record_summaries(peer, abs, &r.stations);
+            }
+            Command::StationDataResponse(r) => {
+                self.ranges
+                    .entry((peer.clone(), abs, r.station.clone()))
+                    .or_default()
+                    .extend(r.ranges.iter().cloned());
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// The most recent summary `peer` gave us of `epoch`, if any.
+    pub fn epoch_report(&self, peer: &Station, epoch: &Epoch) -> Option<&EpochReport> {
+        self.epochs.get(&(peer.clone(), epoch.index_abs()))
+    }
+
+    /// Summaries of individual stations' frames that `peer` reported for `epoch`.
+    pub fn station_summaries(&self, peer: &Station, epoch: &Epoch) -> Vec<&StationSummary> {
+        self.summaries
+            .get(&(peer.clone(), epoch.index_abs()))
+            .map(|m| m.values().collect())
+            .unwrap_or_default()
+    }
+
+    /// For each station, the highest index in `epoch` that any peer has told us it holds.
+    pub fn highest_advertised(&self, epoch: &Epoch) -> HashMap<Station, u16> {
+        let mut highest: HashMap<Station, u16> = HashMap::new();
+        for ((_, abs), stations) in &self.summaries {
+            if *abs != epoch.index_abs() {
+                continue;
+            }
+            for summary in stations.values() {
+                let top = highest
+                    .entry(summary.station.clone())
+                    .or_insert(summary.top);
+                *top = (*top).max(summary.top);
+            }
+        }
+        highest
+    }
+
+    /// Ranges of frames inserted by `inserter` in `epoch` that `peer` reported holding.
+    pub fn station_ranges(
+        &self,
+        peer: &Station,
+        epoch: &Epoch,
+        inserter: &Station,
+    ) -> &[ContiguousRange] {
+        self.ranges
+            .get(&(peer.clone(), epoch.index_abs(), inserter.clone()))
+            .map(|r| r.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Record which stations `peer` says it can hear, as seen from station `me`.
+    ///
+    /// If we are listed then the link with `peer` works in both directions. If
+    /// a complete single-page list leaves us out then it no longer does.
+    pub fn record_range(&mut self, peer: &Station, range: &Range, me: &Station) {
+        if range.stations.iter().any(|s| s.station == *me) {
+            self.mutual.insert(peer.clone());
+        } else if range.final_page == 0 {
+            self.mutual.remove(peer);
+        }
+    }
+
+    /// Whether `peer` has told us that it can hear us.
+    pub fn is_mutual(&self, peer: &Station) -> bool {
+        self.mutual.contains(peer)
+    }
+
+    /// Every peer that has told us it can hear us.
+    pub fn mutual_peers(&self) -> impl Iterator<Item = &Station> {
+        self.mutual.iter()
+    }
+
+    /// Note that `peer` can hear us, as previously learned from its `Range`.
+    pub fn restore_mutual(&mut self, peer: &Station) {
+        self.mutual.insert(peer.clone());
+    }
+
+    /// Every response we are still waiting for, with the peer we asked.
+    pub fn expectations(&self) -> impl Iterator<Item = (&Station, &ExpectedResponse)> {
+        self.expected.iter().map(|(p, e)| (p, e))
+    }
+
+    /// Total number of frames in `epoch` that `peer` has reported holding, up
+    /// to the highest index of each station.
+    pub fn advertised_coverage(&self, peer: &Station, epoch: &Epoch) -> u32 {
+        self.station_summaries(peer, epoch)
+            .iter()
+            .map(|s| s.top as u32 + 1)
+            .sum()
+    }
+
+    /// Number of responses dropped because we never asked for them.
+    pub fn unsolicited(&self) -> u64 {
+        self.unsolicited
+    }
+
+    fn take(&mut self, peer: &Station, response: &ExpectedResponse) -> bool {
+        self.expected.remove(&(peer.clone(), response.clone()))
+    }
+
+    /// For an empty bucket page, find which bucket we asked `peer` about.
+    fn expected_bucket(&self, peer: &Station, abs: u32, page: u8) -> Option<u8> {
+        self.expected.iter().find_map(|(p, e)| match e {
+            ExpectedResponse::BucketContent(a, bucket, pg)
+                if p == peer && *a == abs && *pg == page =>
+            {
+                Some(*bucket)
+            }
+            _ => None,
+        })
+    }
+
+    fn record_summaries(&mut self, peer: &Station, abs: u32, stations: &[StationSummary]) {
+        let known = self.summaries.entry((peer.clone(), abs)).or_default();
+        for s in stations {
+            known.insert(s.station.clone(), s.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::global::{BucketContentResponse, EpochResponse};
+
+    fn station(callsign: &str) -> Station {
+        Station::new(callsign.to_owned(), 0).unwrap()
+    }
+
+    #[test]
+    fn unsolicited_epoch_response_ignored() {
+        let mut tracker = PeerSyncTracker::default();
+        let peer = station("VK7NTK");
+        let epoch = Epoch::from_abs(100);
+        let response = Command::EpochResponse(EpochResponse {
+            epoch_mod8: epoch.index_mod8(),
+            checksums: [1; 16],
+        });
+
+        assert!(!tracker.accept(&peer, &epoch, &response));
+        assert_eq!(tracker.unsolicited(), 1);
+        assert!(tracker.epoch_report(&peer, &epoch).is_none());
+
+        tracker.expect(&peer, ExpectedResponse::Epoch(100));
+        // A different station can't answer on behalf of the one we asked
+        assert!(!tracker.accept(&station("VK7XT"), &epoch, &response));
+        assert_eq!(tracker.unsolicited(), 2);
+
+        assert!(tracker.accept(&peer, &epoch, &response));
+        assert_eq!(
+            tracker.epoch_report(&peer, &epoch),
+            Some(&EpochReport::Buckets([1; 16]))
+        );
+        // Only one response per request
+        assert!(!tracker.accept(&peer, &epoch, &response));
+        assert_eq!(tracker.unsolicited(), 3);
+    }
+
+    #[test]
+    fn bucket_must_match_request() {
+        let mut tracker = PeerSyncTracker::default();
+        let peer = station("VK7NTK");
+        let epoch = Epoch::from_abs(100);
+        let summary = StationSummary {
+            station: station("VK7FDAE"),
+            top: 4,
+            bottom: 0,
+            epoch_crc: 0x1234,
+        };
+        let bucket = summary.station.bucket();
+        let response = Command::BucketContentResponse(BucketContentResponse {
+            epoch_mod8: epoch.index_mod8(),
+            final_page: 0,
+            page: 0,
+            stations: vec![summary.clone()],
+        });
+
+        tracker.expect(
+            &peer,
+            ExpectedResponse::BucketContent(100, (bucket + 1) % 16, 0),
+        );
+        assert!(!tracker.accept(&peer, &epoch, &response));
+        assert!(tracker.station_summaries(&peer, &epoch).is_empty());
+
+        tracker.expect(&peer, ExpectedResponse::BucketContent(100, bucket, 0));
+        assert!(tracker.accept(&peer, &epoch, &response));
+        assert_eq!(tracker.station_summaries(&peer, &epoch), vec![&summary]);
+    }
+
+    #[test]
+    fn expectations_round_trip() {
+        for expected in [
+            ExpectedResponse::Epoch(100),
+            ExpectedResponse::BucketContent(100, 7, 2),
+            ExpectedResponse::StationData(100, station("VK7FDAE")),
+        ] {
+            let encoded = expected.encoded();
+            assert_eq!(ExpectedResponse::try_parse(&encoded), Some(expected));
+            assert_eq!(ExpectedResponse::try_parse(&encoded[..4]), None);
+        }
+        assert_eq!(ExpectedResponse::try_parse(&[3, 0, 0, 0, 100]), None);
+        assert_eq!(ExpectedResponse::try_parse(&[0, 0, 0, 0, 100, 1]), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! Deciding which pages of our `Range` need to be transmitted again.
+
+use time::{Duration, OffsetDateTime};
+
+use crate::protocol::global::{Range, StationHeard};
+
+use super::{paginate, MAX_PAGES};
+
+/// Default time after which every page of our `Range` is sent again even if unchanged.
+pub const DEFAULT_RANGE_REFRESH: Duration = Duration::minutes(30);
+
+/// Remembers the `Range` pages we last sent so that only changed pages are sent again.
+///
+/// When the set of stations we hear is unchanged nothing needs to be sent,
+/// except that all pages are repeated once every refresh interval so that
+/// stations which have just come into range learn about us.
+pub struct RangeTracker {
+    net_prefix: String,
+    refresh: Duration,
+    sent: Vec<Range>,
+    refreshed: Option<OffsetDateTime>,
+}
+
+impl RangeTracker {
+    /// Track the `Range` for a network with `net_prefix`, resending it all every `refresh`.
+    pub fn new(net_prefix: String, refresh: Duration) -> Self {
+        Self {
+            net_prefix,
+            refresh,
+            sent: vec![],
+            refreshed: None,
+        }
+    }
+
+    /// The pages which should be sent now for the stations we `current`ly hear.
+    ///
+    /// Stations are ordered by identifier before being split into pages, so the
+    /// order of `current` doesn't matter. If the number of pages changes, or
+    /// the refresh interval has passed, every page is returned. Otherwise only
+    /// those whose content differs from when they were last sent. The returned
+    /// pages are assumed to be sent.
+    pub fn pages_to_send(&mut self, current: &[StationHeard], now: OffsetDateTime) -> Vec<Range> {
+        let mut stations = current.to_vec();
+        stations.sort_by_cached_key(|s| s.station.to_string());
+        let mut pages = paginate(stations, |s| s.station.encoded(&self.net_prefix).len() + 1);
+        pages.truncate(MAX_PAGES);
+        let final_page = (pages.len() - 1) as u8;
+        let pages: Vec<Range> = pages
+            .into_iter()
+            .enumerate()
+            .map(|(page, stations)| Range {
+                final_page,
+                page: page as u8,
+                stations,
+            })
+            .collect();
+
+        let refresh_due = self.refreshed.is_none_or(|t| now - t >= self.refresh);
+        let to_send = if refresh_due || pages.len() != self.sent.len() {
+            self.refreshed = Some(now);
+            pages.clone()
+        } else {
+            pages
+                .iter()
+                .zip(&self.sent)
+                .filter(|(page, sent)| page != sent)
+                .map(|(page, _)| page.clone())
+                .collect()
+        };
+        self.sent = pages;
+        to_send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::station::Station;
+
+    fn heard(n: usize) -> Vec<StationHeard> {
+        (0..n)
+            .map(|i| StationHeard {
+                station: Station::new(format!("VK7A{}", i), 0).unwrap(),
+                is_mutual: i % 2 == 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_range_suppressed() {
+        let now = OffsetDateTime::now_utc();
+        let mut tracker = RangeTracker::new("VK7".to_owned(), DEFAULT_RANGE_REFRESH);
+        let stations = heard(30);
+        let first = tracker.pages_to_send(&stations, now);
+        assert!(first.len() > 1);
+
+        let mut reversed = stations.clone();
+        reversed.reverse();
+        assert!(tracker
+            .pages_to_send(&reversed, now + Duration::minutes(1))
+            .is_empty());
+        assert_eq!(
+            tracker.pages_to_send(&stations, now + DEFAULT_RANGE_REFRESH),
+            first
+        );
+    }
+
+    #[test]
+    fn changed_page_sent_alone() {
+        let now = OffsetDateTime::now_utc();
+        let mut tracker = RangeTracker::new("VK7".to_owned(), DEFAULT_RANGE_REFRESH);
+        let mut stations = heard(30);
+        let first = tracker.pages_to_send(&stations, now);
+
+        // Sorts after everything else, so lands on the last page
+        stations.push(StationHeard {
+            station: Station::new("VK7ZZ".to_owned(), 0).unwrap(),
+            is_mutual: false,
+        });
+        let changed = tracker.pages_to_send(&stations, now + Duration::minutes(1));
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].page, first.last().unwrap().page);
+        assert_eq!(changed[0].final_page, first[0].final_page);
+        assert_eq!(
+            changed[0].stations.last().unwrap().station.to_string(),
+            "VK7ZZ-0"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Tracking of requests we have sent and are still waiting to have answered.
+
+use std::collections::HashMap;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::protocol::{global::Command, station::Station};
+
+use super::cache::FrameKey;
+
+/// What a request is asking for, and which station was asked.
+///
+/// Two requests with the same key would be answered with the same data so
+/// there is no point having both outstanding at once.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum RequestKey {
+    /// A single data frame from the given target, via quick sync or backfill.
+    Frame(Station, FrameKey),
+
+    /// A summary of a particular station's view of an absolute epoch.
+    Epoch(Station, u32),
+}
+
+/// A request that has been transmitted and not yet answered.
+#[derive(Clone, Debug)]
+pub(crate) struct InFlight {
+    /// The command that was sent.
+    pub(crate) command: Command,
+
+    /// When the command was most recently sent.
+    pub(crate) sent: OffsetDateTime,
+
+    /// How many times the command has been sent again after timing out.
+    pub(crate) retries: u32,
+}
+
+/// Set of outstanding requests.
+#[derive(Default)]
+pub(crate) struct InFlightRequests {
+    requests: HashMap<RequestKey, InFlight>,
+}
+
+impl InFlightRequests {
+    /// Record that we would like to send `command`, identified by `key`, at time `now`.
+    ///
+    /// Returns `false` if an equivalent request is already outstanding and was
+    /// sent less than `timeout` ago, in which case the request should not be sent.
+    pub(crate) fn start(
+        &mut self,
+        key: RequestKey,
+        command: &Command,
+        now: OffsetDateTime,
+        timeout: Duration,
+    ) -> bool {
+        if let Some(existing) = self.requests.get(&key) {
+            if now - existing.sent < timeout {
+                return false;
+            }
+        }
+        self.requests.insert(
+            key,
+            InFlight {
+                command: command.clone(),
+                sent: now,
+                retries: 0,
+            },
+        );
+        true
+    }
+
+    /// Mark the request for `key` as answered, returning it if it was outstanding.
+    pub(crate) fn complete(&mut self, key: &RequestKey) -> Option<InFlight> {
+        self.requests.remove(key)
+    }
+
+    /// Mark every request for the frame `key` as answered, whichever station was asked.
+    ///
+    /// A frame is the same however it reaches us, so once we hold it there is
+    /// no point waiting for any other target to send it.
+    pub(crate) fn complete_frame(&mut self, key: &FrameKey) {
+        self.requests
+            .retain(|k, _| !matches!(k, RequestKey::Frame(_, frame) if frame == key));
+    }
+
+    /// Find requests that have gone unanswered for at least `timeout`.
+    ///
+    /// Requests that have been retried fewer than `max_retries` times are marked
+    /// as sent again at `now` and their commands returned in the first list.
+    /// Requests which have run out of retries are removed and their keys
+    /// returned in the second list.
+    pub(crate) fn expire(
+        &mut self,
+        now: OffsetDateTime,
+        timeout: Duration,
+        max_retries: u32,
+    ) -> (Vec<Command>, Vec<RequestKey>) {
+        let mut resend = vec![];
+        let mut abandoned = vec![];
+        for (key, req) in self.requests.iter_mut() {
+            if now - req.sent < timeout {
+                continue;
+            }
+            if req.retries >= max_retries {
+                abandoned.push(key.clone());
+            } else {
+                req.retries += 1;
+                req.sent = now;
+                resend.push(req.command.clone());
+            }
+        }
+        for key in &abandoned {
+            self.requests.remove(key);
+        }
+        (resend, abandoned)
+    }
+
+    /// Put back a request that was outstanding before a restart.
+    pub(crate) fn restore(&mut self, key: RequestKey, request: InFlight) {
+        self.requests.insert(key, request);
+    }
+
+    /// All outstanding requests.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&RequestKey, &InFlight)> {
+        self.requests.iter()
+    }
+
+    /// Number of outstanding requests.
+    pub(crate) fn len(&self) -> usize {
+        self.requests.len()
+    }
+}